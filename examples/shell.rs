@@ -17,7 +17,8 @@
 
 //! Example of a command line shell with history support
 //!
-//! Use the arrow keys to go forwards and backwards through the history.
+//! Use the arrow keys to go forwards and backwards through the history. Repeated commands, and
+//! commands that begin with a space, are not added to the history.
 //!
 //! Currently supported commands:
 //!
@@ -32,17 +33,29 @@ extern crate rl_sys;
 extern crate time;
 
 use rl_sys::history::{expand, listinfo, listmgmt, mgmt};
-use rl_sys::readline;
+use rl_sys::history::policy::HistoryPolicy;
+use rl_sys::readline::{self, Outcome};
 use std::process::Command;
 
 fn main() {
     println!("welcome to shrl!");
 
+    let policy = HistoryPolicy {
+        ignore_dups: true,
+        ignore_space: true,
+        ..Default::default()
+    };
+
     loop {
         let prompt = format!("{} $ ", time::now().rfc3339());
         let input: String = match readline::readline(&prompt) {
-            Ok(Some(s)) => s,
-            Ok(None) => break,
+            Ok(Outcome::Line(s)) => s,
+            Ok(Outcome::InvalidUtf8(_)) => {
+                println!("input is not valid UTF-8");
+                continue;
+            }
+            Ok(Outcome::Interrupted) => continue,
+            Ok(Outcome::Eof) => break,
             Err(e) => {
                 println!("{}", e);
                 continue;
@@ -55,7 +68,9 @@ fn main() {
         }
 
         // Add user input to history.
-        listmgmt::add(&input).unwrap_or_else(|e| println!("{:?}", e));
+        if let Err(e) = policy.add(&input) {
+            println!("{:?}", e);
+        }
 
         if input.starts_with("exit") {
             break;
@@ -16,92 +16,302 @@
 // along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
 
 //! rl-sys API Error Handling
+//!
+//! `ReadlineError` and `HistoryError` are enums, so callers can match on the kinds of failure they
+//! care about. Failures that don't have a kind of their own are reported as `Other`, with a
+//! description and detail.
+use std::error::Error;
+use std::ffi::NulError;
 use std::fmt;
+use std::io;
+use std::num::ParseIntError;
+use std::str::Utf8Error;
+use std::time::SystemTimeError;
 
+/// The error type for the functions in `rl_sys::readline`.
 #[derive(Debug)]
-/// Represents an error that has occurred within the Readline API.
-pub struct ReadlineError {
-    desc: String,
-    detail: String,
+pub enum ReadlineError {
+    /// A string argument contained an interior NUL byte.
+    Nul(NulError),
+    /// Text returned by readline was not valid UTF-8.
+    Utf8(Utf8Error),
+    /// An I/O error. For failed system calls this carries the `errno` value.
+    Io(io::Error),
+    /// Readline was unable to bind the key sequence `keyseq`.
+    BindFailed {
+        /// The key sequence that could not be bound.
+        keyseq: String,
+    },
+    /// There is no bindable function named `name`.
+    UnknownFunction {
+        /// The function name that was looked up.
+        name: String,
+    },
+    /// `undo::do_undo` was called with nothing to undo.
+    NothingToUndo,
+    /// Readline returned a null pointer where a value was expected.
+    NullPointer {
+        /// The readline function or variable that was null.
+        func: String,
+    },
+    /// Any other failure.
+    Other {
+        /// A short description of the kind of failure.
+        desc: String,
+        /// The details of the failure.
+        detail: String,
+    },
 }
 
-/// Implemented as 'self.desc: self.detail'.
 impl fmt::Display for ReadlineError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: {}", self.desc, self.detail)
+        match *self {
+            ReadlineError::Nul(ref e) => write!(f, "NulError: {}", e),
+            ReadlineError::Utf8(ref e) => write!(f, "FromUtf8Error: {}", e),
+            ReadlineError::Io(ref e) => write!(f, "I/O Error: {}", e),
+            ReadlineError::BindFailed { ref keyseq } => {
+                write!(f, "Bind Error: Unable to bind {:?}!", keyseq)
+            }
+            ReadlineError::UnknownFunction { ref name } => {
+                write!(f, "Funmap Error: Unknown function {:?}!", name)
+            }
+            ReadlineError::NothingToUndo => write!(f, "Undo Error: There was nothing to undo!"),
+            ReadlineError::NullPointer { ref func } => {
+                write!(f, "Null Pointer: {} returned null pointer!", func)
+            }
+            ReadlineError::Other { ref desc, ref detail } => write!(f, "{}: {}", desc, detail),
+        }
+    }
+}
+
+impl Error for ReadlineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ReadlineError::Nul(ref e) => Some(e),
+            ReadlineError::Utf8(ref e) => Some(e),
+            ReadlineError::Io(ref e) => Some(e),
+            _ => None,
+        }
     }
 }
 
 impl ReadlineError {
-    /// Create a ReadlineError struct from the given description and detail.
+    /// Create a `ReadlineError::Other` from the given description and detail.
     pub fn new<T>(desc: &str, detail: T) -> ReadlineError
         where T: fmt::Display
     {
-        ReadlineError {
+        ReadlineError::Other {
             desc: String::from(desc),
             detail: format!("{}", detail),
         }
     }
 }
 
-impl From<::std::ffi::NulError> for ReadlineError {
-    fn from(e: ::std::ffi::NulError) -> ReadlineError {
-        ReadlineError::new("NulError", e)
+impl From<NulError> for ReadlineError {
+    fn from(e: NulError) -> ReadlineError {
+        ReadlineError::Nul(e)
     }
 }
 
-impl From<::std::str::Utf8Error> for ReadlineError {
-    fn from(e: ::std::str::Utf8Error) -> ReadlineError {
-        ReadlineError::new("FromUtf8Error", e)
+impl From<Utf8Error> for ReadlineError {
+    fn from(e: Utf8Error) -> ReadlineError {
+        ReadlineError::Utf8(e)
     }
 }
 
-impl From<::std::io::Error> for ReadlineError {
-    fn from(e: ::std::io::Error) -> ReadlineError {
-        ReadlineError::new("I/O Error", e)
+impl From<io::Error> for ReadlineError {
+    fn from(e: io::Error) -> ReadlineError {
+        ReadlineError::Io(e)
     }
 }
 
+/// The error type for the functions in `rl_sys::history`.
 #[derive(Debug)]
-/// Represents an error that has occurred within the History API.
-pub struct HistoryError {
-    desc: String,
-    detail: String,
+pub enum HistoryError {
+    /// A string argument contained an interior NUL byte.
+    Nul(NulError),
+    /// Text returned by the history library was not valid UTF-8.
+    Utf8(Utf8Error),
+    /// A number could not be parsed.
+    ParseInt(ParseIntError),
+    /// An I/O error. For failed history file operations this carries the `errno` value.
+    Io(io::Error),
+    /// There is no history entry at `offset`.
+    HistoryOutOfRange {
+        /// The offset that was requested.
+        offset: i32,
+    },
+    /// There is no current, previous or next history entry to move to or return.
+    NoEntry,
+    /// A time stamp was before the epoch, so it can't be stored in the history list.
+    BeforeEpoch(SystemTimeError),
+    /// A history file was not in the expected format.
+    Format {
+        /// The line of the file, counting from 1, that could not be parsed.
+        line: usize,
+        /// What was wrong with it.
+        detail: String,
+    },
+    /// The history library returned a null pointer where a value was expected.
+    NullPointer {
+        /// The history library function or variable that was null.
+        func: String,
+    },
+    /// `history::store::HistoryStore::finish` was called before any entry was added.
+    #[cfg(feature = "sqlite")]
+    NothingAdded,
+    /// An error from the SQLite database behind a `history::store::HistoryStore`.
+    #[cfg(feature = "sqlite")]
+    Sqlite(::rusqlite::Error),
+    /// Any other failure.
+    Other {
+        /// A short description of the kind of failure.
+        desc: String,
+        /// The details of the failure.
+        detail: String,
+    },
 }
 
 impl HistoryError {
-    /// Create a HistoryError struct from the given description and detail.
+    /// Create a `HistoryError::Other` from the given description and detail.
     pub fn new<T>(desc: &str, detail: T) -> HistoryError
         where T: fmt::Display
     {
-        HistoryError {
+        HistoryError::Other {
             desc: String::from(desc),
             detail: format!("{}", detail),
         }
     }
 }
 
-/// Implemented as 'self.desc: self.detail'.
 impl fmt::Display for HistoryError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: {}", self.desc, self.detail)
+        match *self {
+            HistoryError::Nul(ref e) => write!(f, "NulError: {}", e),
+            HistoryError::Utf8(ref e) => write!(f, "FromUtf8Error: {}", e),
+            HistoryError::ParseInt(ref e) => write!(f, "ParseIntError: {}", e),
+            HistoryError::Io(ref e) => write!(f, "History Error: {}", e),
+            HistoryError::HistoryOutOfRange { offset } => {
+                write!(f, "History Error: No history entry at offset {}!", offset)
+            }
+            HistoryError::NoEntry => write!(f, "History Error: There is no such history entry!"),
+            HistoryError::NullPointer { ref func } => {
+                write!(f, "Null Pointer: {} returned null pointer!", func)
+            }
+            HistoryError::BeforeEpoch(ref e) => write!(f, "Timestamp Error: {}", e),
+            HistoryError::Format { line, ref detail } => {
+                write!(f, "Format Error: line {}: {}", line, detail)
+            }
+            #[cfg(feature = "sqlite")]
+            HistoryError::NothingAdded => write!(f, "Store Error: No entry has been added!"),
+            #[cfg(feature = "sqlite")]
+            HistoryError::Sqlite(ref e) => write!(f, "SQLite Error: {}", e),
+            HistoryError::Other { ref desc, ref detail } => write!(f, "{}: {}", desc, detail),
+        }
+    }
+}
+
+impl Error for HistoryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            HistoryError::Nul(ref e) => Some(e),
+            HistoryError::Utf8(ref e) => Some(e),
+            HistoryError::ParseInt(ref e) => Some(e),
+            HistoryError::Io(ref e) => Some(e),
+            HistoryError::BeforeEpoch(ref e) => Some(e),
+            #[cfg(feature = "sqlite")]
+            HistoryError::Sqlite(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<NulError> for HistoryError {
+    fn from(e: NulError) -> HistoryError {
+        HistoryError::Nul(e)
+    }
+}
+
+impl From<Utf8Error> for HistoryError {
+    fn from(e: Utf8Error) -> HistoryError {
+        HistoryError::Utf8(e)
+    }
+}
+
+impl From<ParseIntError> for HistoryError {
+    fn from(e: ParseIntError) -> HistoryError {
+        HistoryError::ParseInt(e)
+    }
+}
+
+impl From<io::Error> for HistoryError {
+    fn from(e: io::Error) -> HistoryError {
+        HistoryError::Io(e)
     }
 }
 
-impl From<::std::ffi::NulError> for HistoryError {
-    fn from(e: ::std::ffi::NulError) -> HistoryError {
-        HistoryError::new("NulError", e)
+impl From<SystemTimeError> for HistoryError {
+    fn from(e: SystemTimeError) -> HistoryError {
+        HistoryError::BeforeEpoch(e)
     }
 }
 
-impl From<::std::str::Utf8Error> for HistoryError {
-    fn from(e: ::std::str::Utf8Error) -> HistoryError {
-        HistoryError::new("FromUtf8Error", e)
+#[cfg(feature = "sqlite")]
+impl From<::rusqlite::Error> for HistoryError {
+    fn from(e: ::rusqlite::Error) -> HistoryError {
+        HistoryError::Sqlite(e)
     }
 }
 
-impl From<::std::num::ParseIntError> for HistoryError {
-    fn from(e: ::std::num::ParseIntError) -> HistoryError {
-        HistoryError::new("ParseIntError", e)
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use std::ffi::CString;
+    use std::io;
+    use super::*;
+
+    #[test]
+    fn test_source() {
+        let nul = CString::new("a\0b").unwrap_err();
+        let e = ReadlineError::from(nul);
+        assert!(e.source().is_some());
+        assert!(ReadlineError::NothingToUndo.source().is_none());
+
+        let e = HistoryError::from(io::Error::from_raw_os_error(2));
+        match e {
+            HistoryError::Io(ref io) => assert_eq!(io.raw_os_error(), Some(2)),
+            _ => panic!("expected an I/O error"),
+        }
+        assert!(e.source().is_some());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_source() {
+        let e = HistoryError::from(::rusqlite::Error::QueryReturnedNoRows);
+        assert!(e.source().is_some());
+        assert!(e.to_string().starts_with("SQLite Error: "));
+    }
+
+    #[test]
+    fn test_variant_display() {
+        let e = ReadlineError::NullPointer { func: String::from("rl_get_keymap") };
+        assert_eq!(e.to_string(), "Null Pointer: rl_get_keymap returned null pointer!");
+
+        let e = HistoryError::NullPointer { func: String::from("history_tokenize") };
+        assert_eq!(e.to_string(), "Null Pointer: history_tokenize returned null pointer!");
+
+        let e = HistoryError::Format { line: 3, detail: String::from("missing line") };
+        assert_eq!(e.to_string(), "Format Error: line 3: missing line");
+    }
+
+    #[test]
+    fn test_other_display() {
+        let e = ReadlineError::new("Test Error", "failed");
+        assert_eq!(e.to_string(), "Test Error: failed");
+        match e {
+            ReadlineError::Other { ref desc, .. } => assert_eq!(desc, "Test Error"),
+            _ => panic!("expected Other"),
+        }
     }
 }
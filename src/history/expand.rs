@@ -70,7 +70,7 @@ pub fn expand(s: &str) -> Result<(isize, String), ::HistoryError> {
         let _ = CString::from_raw(ptr);
 
         if output_ptr.is_null() {
-            Err(::HistoryError::new("NullPointer", "Null pointer returned from history_expand!"))
+            Err(::HistoryError::NullPointer { func: String::from("history_expand") })
         } else {
             let out = CStr::from_ptr(output_ptr).to_string_lossy().into_owned();
             util::free(output_ptr as *mut c_void);
@@ -107,7 +107,7 @@ pub fn get_event(s: &str, idx: &mut i32, delim: Option<char>) -> Result<String,
         let char_ptr = ext_expand::get_history_event(cs.as_ptr(), idx as *mut c_int, ch);
 
         if char_ptr.is_null() {
-            Err(::HistoryError::new("History Error", "Null pointer returned!"))
+            Err(::HistoryError::NullPointer { func: String::from("get_history_event") })
         } else {
             let out = CStr::from_ptr(char_ptr).to_string_lossy().into_owned();
             Ok(out)
@@ -140,7 +140,7 @@ pub fn tokenize(s: &str) -> Result<Vec<String>, ::HistoryError> {
         let arr_ptr = ext_expand::history_tokenize(cs.as_ptr());
 
         if arr_ptr.is_null() {
-            Err(::HistoryError::new("History Error", "Null pointer returned!"))
+            Err(::HistoryError::NullPointer { func: String::from("history_tokenize") })
         } else {
             // Loop through the char** offsets until 0x0 is found, then break.  The pointers point
             // to *mut chars (string), so use CStr to convert them.  free the string from readline
@@ -187,7 +187,7 @@ pub fn arg_extract(s: &str, first: i32, last: i32) -> Result<String, ::HistoryEr
         let char_ptr = ext_expand::history_arg_extract(first, last, cs.as_ptr());
 
         if char_ptr.is_null() {
-            Err(::HistoryError::new("History Error", "Null pointer returned!"))
+            Err(::HistoryError::NullPointer { func: String::from("history_arg_extract") })
         } else {
             let out = CStr::from_ptr(char_ptr).to_string_lossy().into_owned();
             free(char_ptr as *mut c_void);
@@ -0,0 +1,824 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! History File Formats
+//!
+//! `histfile` reads and writes readline's own format, one line per entry with optional `#<epoch>`
+//! time stamp lines. This module reads and writes other formats through the `HistoryFormat` trait:
+//!
+//! * `Plain`, one line per entry.
+//! * `Bash`, one line per entry, each preceded by a `#<epoch>` line if it has a time stamp, as
+//!   written by bash with `HISTTIMEFORMAT` set.
+//! * `ZshExtended`, zsh's `EXTENDED_HISTORY` format of `: <start>:<elapsed>;<command>`.
+//! * `JsonLines`, one JSON object per entry, which also carries each entry's `Metadata`.
+//!
+//! `import` and `export` move entries between a file and the history list, and `convert` rewrites
+//! a file from one format to another without touching the history list. An entry's `Metadata` is
+//! kept on the history list as its payload (see `history::payload`).
+use history::{self, Entry};
+use std::char;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+use std::iter::Peekable;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Metadata about a command, kept as the payload of its history entry.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    /// The exit status of the command.
+    pub exit_code: Option<i32>,
+    /// The directory the command was run in.
+    pub cwd: Option<PathBuf>,
+    /// How long the command took to run.
+    pub duration: Option<Duration>,
+}
+
+impl Metadata {
+    /// Returns true if none of the fields are set.
+    pub fn is_empty(&self) -> bool {
+        self.exit_code.is_none() && self.cwd.is_none() && self.duration.is_none()
+    }
+}
+
+/// A history entry as read from or written to a history file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    /// The line.
+    pub line: String,
+    /// The time the line was added.
+    pub timestamp: Option<SystemTime>,
+    /// The command's metadata. Only `JsonLines` keeps all of it; `ZshExtended` keeps the duration.
+    pub meta: Metadata,
+}
+
+impl<'a> From<&'a Entry> for Record {
+    fn from(entry: &'a Entry) -> Record {
+        Record {
+            line: entry.line.clone(),
+            timestamp: entry.timestamp,
+            meta: entry.get_payload::<Metadata>().cloned().unwrap_or_default(),
+        }
+    }
+}
+
+/// A history file format.
+pub trait HistoryFormat {
+    /// Parse the contents of a history file.
+    fn read(&self, buf: &[u8]) -> Result<Vec<Record>, ::HistoryError>;
+    /// Write `records` to `out`.
+    fn write(&self, records: &[Record], out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// Split `buf` into its non-empty lines.
+fn lines(buf: &[u8]) -> Vec<&[u8]> {
+    buf.split(|&b| b == b'\n').filter(|l| !l.is_empty()).collect()
+}
+
+/// Parse a line of the form `#<epoch>`.
+fn parse_comment_time(line: &[u8]) -> Option<SystemTime> {
+    if line.len() > 1 && line[0] == b'#' && line[1..].iter().all(|b| b.is_ascii_digit()) {
+        String::from_utf8_lossy(&line[1..])
+            .parse::<u64>()
+            .ok()
+            .and_then(from_epoch_secs)
+    } else {
+        None
+    }
+}
+
+/// Check that `r` fits on one line, for the formats that can't escape a newline.
+fn single_line(r: &Record) -> io::Result<()> {
+    if r.line.contains('\n') {
+        Err(io::Error::new(io::ErrorKind::InvalidInput,
+                           format!("{:?} spans more than one line", r.line)))
+    } else {
+        Ok(())
+    }
+}
+
+/// The time `secs` seconds after the epoch, or `None` if `SystemTime` can't represent it.
+fn from_epoch_secs(secs: u64) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+/// The seconds since the epoch at `time`, or 0 for a time before the epoch.
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// One line per entry, with no time stamps or metadata. An entry that spans more than one line
+/// can't be written, and `write` fails with `ErrorKind::InvalidInput`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Plain;
+
+impl HistoryFormat for Plain {
+    fn read(&self, buf: &[u8]) -> Result<Vec<Record>, ::HistoryError> {
+        let record = |line: &[u8]| {
+            Record { line: String::from_utf8_lossy(line).into_owned(), ..Default::default() }
+        };
+        Ok(lines(buf).into_iter().map(record).collect())
+    }
+
+    fn write(&self, records: &[Record], out: &mut dyn Write) -> io::Result<()> {
+        for r in records {
+            try!(single_line(r));
+            try!(writeln!(out, "{}", r.line));
+        }
+        Ok(())
+    }
+}
+
+/// One line per entry, each preceded by a `#<epoch>` line if it has a time stamp. As with `Plain`,
+/// an entry that spans more than one line can't be written.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::format::{Bash, HistoryFormat};
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let records = Bash.read(b"#1500000000\nls -al\npwd\n").unwrap();
+/// assert_eq!(records[0].line, "ls -al");
+/// assert_eq!(records[0].timestamp, Some(UNIX_EPOCH + Duration::from_secs(1_500_000_000)));
+/// assert_eq!(records[1].timestamp, None);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bash;
+
+impl HistoryFormat for Bash {
+    fn read(&self, buf: &[u8]) -> Result<Vec<Record>, ::HistoryError> {
+        let mut records = Vec::new();
+        let mut timestamp = None;
+
+        for line in lines(buf) {
+            match parse_comment_time(line) {
+                Some(t) => timestamp = Some(t),
+                None => {
+                    records.push(Record {
+                        line: String::from_utf8_lossy(line).into_owned(),
+                        timestamp: timestamp.take(),
+                        ..Default::default()
+                    })
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    fn write(&self, records: &[Record], out: &mut dyn Write) -> io::Result<()> {
+        for r in records {
+            try!(single_line(r));
+            if let Some(t) = r.timestamp {
+                try!(writeln!(out, "#{}", epoch_secs(t)));
+            }
+            try!(writeln!(out, "{}", r.line));
+        }
+        Ok(())
+    }
+}
+
+/// zsh's extended history format. Each entry is written as `: <start>:<elapsed>;<command>`, where
+/// the elapsed time is whole seconds, and newlines in the command are escaped with a backslash. An
+/// entry without a time stamp is written with a start of 0, and read back without one.
+///
+/// zsh "metafies" some bytes in its history file, so the file is not always valid UTF-8. That
+/// encoding is undone when reading and applied when writing.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::format::{HistoryFormat, ZshExtended};
+/// use std::time::Duration;
+///
+/// let records = ZshExtended.read(b": 1500000000:3;make\n: 1500000010:0;echo a\\\nb\n").unwrap();
+/// assert_eq!(records[0].meta.duration, Some(Duration::from_secs(3)));
+/// assert_eq!(records[1].line, "echo a\nb");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ZshExtended;
+
+/// zsh's escape byte for metafied characters.
+const ZSH_META: u8 = 0x83;
+
+/// Returns true if zsh metafies `b`.
+fn zsh_imeta(b: u8) -> bool {
+    b == 0 || (ZSH_META..=0xa2).contains(&b)
+}
+
+fn zsh_unmetafy(buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len());
+    let mut bytes = buf.iter();
+    while let Some(&b) = bytes.next() {
+        if b == ZSH_META {
+            if let Some(&next) = bytes.next() {
+                out.push(next ^ 32);
+            }
+        } else {
+            out.push(b);
+        }
+    }
+    out
+}
+
+fn zsh_metafy(buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len());
+    for &b in buf {
+        if zsh_imeta(b) {
+            out.push(ZSH_META);
+            out.push(b ^ 32);
+        } else {
+            out.push(b);
+        }
+    }
+    out
+}
+
+/// Parse the `: <start>:<elapsed>;` prefix of an extended history line, returning the start time,
+/// the elapsed time and the command.
+fn zsh_parse_extended(line: &str) -> Option<(u64, u64, &str)> {
+    if !line.starts_with(": ") {
+        return None;
+    }
+
+    line.find(';').and_then(|semi| {
+        let mut times = line[2..semi].splitn(2, ':');
+        match (times.next().map(|s| s.trim().parse()), times.next().map(|s| s.trim().parse())) {
+            (Some(Ok(start)), Some(Ok(elapsed))) => Some((start, elapsed, &line[semi + 1..])),
+            _ => None,
+        }
+    })
+}
+
+impl HistoryFormat for ZshExtended {
+    fn read(&self, buf: &[u8]) -> Result<Vec<Record>, ::HistoryError> {
+        let text = zsh_unmetafy(buf);
+        let text = String::from_utf8_lossy(&text);
+        let mut records = Vec::new();
+        let mut pending = String::new();
+
+        for line in text.split('\n') {
+            // A trailing backslash escapes a newline in the command.
+            if let Some(head) = line.strip_suffix('\\') {
+                pending.push_str(head);
+                pending.push('\n');
+                continue;
+            }
+            pending.push_str(line);
+            if pending.is_empty() {
+                continue;
+            }
+
+            let record = match zsh_parse_extended(&pending) {
+                Some((start, elapsed, command)) => {
+                    Record {
+                        line: String::from(command),
+                        timestamp: if start == 0 {
+                            None
+                        } else {
+                            from_epoch_secs(start)
+                        },
+                        meta: Metadata {
+                            duration: Some(Duration::from_secs(elapsed)),
+                            ..Default::default()
+                        },
+                    }
+                }
+                // Files written without EXTENDED_HISTORY have no prefix.
+                None => Record { line: pending.clone(), ..Default::default() },
+            };
+            records.push(record);
+            pending.clear();
+        }
+        Ok(records)
+    }
+
+    fn write(&self, records: &[Record], out: &mut dyn Write) -> io::Result<()> {
+        for r in records {
+            let start = r.timestamp.map_or(0, epoch_secs);
+            let elapsed = r.meta.duration.map_or(0, |d| d.as_secs());
+            let line = format!(": {}:{};{}\n", start, elapsed, r.line.replace('\n', "\\\n"));
+            try!(out.write_all(&zsh_metafy(line.as_bytes())));
+        }
+        Ok(())
+    }
+}
+
+/// One JSON object per line, with the fields `line`, and if they are set, `timestamp` (seconds
+/// since the epoch), `exit_code`, `cwd` and `duration_ms`. Unknown fields, including nested
+/// objects and arrays, are ignored when reading.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::format::{HistoryFormat, JsonLines, Metadata, Record};
+/// use std::path::PathBuf;
+///
+/// let record = Record {
+///     line: String::from("cargo test"),
+///     meta: Metadata {
+///         exit_code: Some(101),
+///         cwd: Some(PathBuf::from("/src/rl-sys")),
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// };
+///
+/// let mut out = Vec::new();
+/// JsonLines.write(&[record.clone()], &mut out).unwrap();
+/// assert_eq!(String::from_utf8(out.clone()).unwrap(),
+///            "{\"line\":\"cargo test\",\"exit_code\":101,\"cwd\":\"/src/rl-sys\"}\n");
+/// assert_eq!(JsonLines.read(&out).unwrap(), vec![record]);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct JsonLines;
+
+/// A JSON value. Only the scalar values used by `JsonLines` are kept; objects and arrays are
+/// skipped over.
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    Str(String),
+    Nested,
+}
+
+/// Write `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A parser for a single flat JSON object.
+struct JsonParser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> JsonParser<'a> {
+    fn skip_ws(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_ws();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}', found '{}'", expected, c)),
+            None => Err(format!("expected '{}', found the end of the line", expected)),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits: String = self.chars.by_ref().take(4).collect();
+        if digits.len() != 4 {
+            return Err(String::from("truncated \\u escape"));
+        }
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("bad \\u escape '{}'", digits))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        try!(self.expect('"'));
+        let mut out = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(out),
+                Some('\\') => {
+                    let c = match self.chars.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let hi = try!(self.hex4());
+                            let code = if (0xd800..0xdc00).contains(&hi) {
+                                try!(self.expect('\\'));
+                                try!(self.expect('u'));
+                                let lo = try!(self.hex4());
+                                0x10000 + ((hi - 0xd800) << 10) + (lo.wrapping_sub(0xdc00) & 0x3ff)
+                            } else {
+                                hi
+                            };
+                            try!(char::from_u32(code)
+                                     .ok_or_else(|| format!("bad code point {:x}", code)))
+                        }
+                        Some(c) => return Err(format!("bad escape '\\{}'", c)),
+                        None => return Err(String::from("unterminated string")),
+                    };
+                    out.push(c);
+                }
+                Some(c) => out.push(c),
+                None => return Err(String::from("unterminated string")),
+            }
+        }
+    }
+
+    fn literal(&mut self) -> String {
+        let mut out = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_alphanumeric() || c == '-' || c == '+' || c == '.' {
+                out.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        out
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_ws();
+        match self.chars.peek().cloned() {
+            Some('"') => self.string().map(Json::Str),
+            Some('{' | '[') => self.nested(),
+            Some(_) => {
+                match &self.literal()[..] {
+                    "null" => Ok(Json::Null),
+                    "true" => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    n if n.parse::<f64>().is_ok() => Ok(Json::Number(String::from(n))),
+                    other => Err(format!("unexpected '{}'", other)),
+                }
+            }
+            None => Err(String::from("expected a value, found the end of the line")),
+        }
+    }
+
+    /// Skip over an object or array, checking only that its brackets match.
+    fn nested(&mut self) -> Result<Json, String> {
+        let mut closers = Vec::new();
+        loop {
+            match self.chars.peek().cloned() {
+                Some('"') => {
+                    try!(self.string());
+                    continue;
+                }
+                Some('{') => closers.push('}'),
+                Some('[') => closers.push(']'),
+                Some(c) if c == '}' || c == ']' => {
+                    if closers.pop() != Some(c) {
+                        return Err(format!("unexpected '{}'", c));
+                    }
+                }
+                Some(_) => {}
+                None => return Err(String::from("unterminated object or array")),
+            }
+            self.chars.next();
+            if closers.is_empty() {
+                return Ok(Json::Nested);
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Vec<(String, Json)>, String> {
+        let mut fields = Vec::new();
+        try!(self.expect('{'));
+        self.skip_ws();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+        } else {
+            loop {
+                let key = try!(self.string());
+                try!(self.expect(':'));
+                fields.push((key, try!(self.value())));
+                self.skip_ws();
+                match self.chars.next() {
+                    Some(',') => continue,
+                    Some('}') => break,
+                    _ => return Err(String::from("expected ',' or '}'")),
+                }
+            }
+        }
+
+        self.skip_ws();
+        match self.chars.next() {
+            None => Ok(fields),
+            Some(c) => Err(format!("unexpected '{}' after the object", c)),
+        }
+    }
+}
+
+/// Parse a number field, which may be `null`.
+fn json_number<T: ::std::str::FromStr>(key: &str, value: Json) -> Result<Option<T>, String> {
+    match value {
+        Json::Null => Ok(None),
+        Json::Number(ref n) => {
+            n.parse().map(Some).map_err(|_| format!("'{}' is out of range for {}", n, key))
+        }
+        other => Err(format!("expected a number for {}, found {:?}", key, other)),
+    }
+}
+
+/// Parse a `JsonLines` line into a record.
+fn json_record(line: &str) -> Result<Record, String> {
+    let fields = try!(JsonParser { chars: line.chars().peekable() }.object());
+    let mut record = Record::default();
+    let mut has_line = false;
+
+    for (key, value) in fields {
+        match &key[..] {
+            "line" => {
+                match value {
+                    Json::Str(s) => {
+                        record.line = s;
+                        has_line = true;
+                    }
+                    other => return Err(format!("expected a string for line, found {:?}", other)),
+                }
+            }
+            "timestamp" => {
+                let secs: Option<u64> = try!(json_number(&key, value));
+                record.timestamp = match secs {
+                    Some(secs) => {
+                        let err = || format!("timestamp {} is out of range", secs);
+                        Some(try!(from_epoch_secs(secs).ok_or_else(err)))
+                    }
+                    None => None,
+                }
+            }
+            "exit_code" => record.meta.exit_code = try!(json_number(&key, value)),
+            "duration_ms" => {
+                let ms: Option<u64> = try!(json_number(&key, value));
+                record.meta.duration = ms.map(Duration::from_millis);
+            }
+            "cwd" => {
+                record.meta.cwd = match value {
+                    Json::Null => None,
+                    Json::Str(s) => Some(PathBuf::from(s)),
+                    other => return Err(format!("expected a string for cwd, found {:?}", other)),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if has_line {
+        Ok(record)
+    } else {
+        Err(String::from("missing line"))
+    }
+}
+
+impl HistoryFormat for JsonLines {
+    fn read(&self, buf: &[u8]) -> Result<Vec<Record>, ::HistoryError> {
+        let text = try!(::std::str::from_utf8(buf));
+        let mut records = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match json_record(line) {
+                Ok(r) => records.push(r),
+                Err(detail) => return Err(::HistoryError::Format { line: i + 1, detail }),
+            }
+        }
+        Ok(records)
+    }
+
+    fn write(&self, records: &[Record], out: &mut dyn Write) -> io::Result<()> {
+        for r in records {
+            let mut line = format!("{{\"line\":{}", json_string(&r.line));
+            if let Some(t) = r.timestamp {
+                line.push_str(&format!(",\"timestamp\":{}", epoch_secs(t)));
+            }
+            if let Some(code) = r.meta.exit_code {
+                line.push_str(&format!(",\"exit_code\":{}", code));
+            }
+            if let Some(ref cwd) = r.meta.cwd {
+                line.push_str(&format!(",\"cwd\":{}", json_string(&cwd.to_string_lossy())));
+            }
+            if let Some(d) = r.meta.duration {
+                let ms = d.as_secs() * 1000 + u64::from(d.subsec_millis());
+                line.push_str(&format!(",\"duration_ms\":{}", ms));
+            }
+            line.push_str("}\n");
+            try!(out.write_all(line.as_bytes()));
+        }
+        Ok(())
+    }
+}
+
+fn read_file(format: &dyn HistoryFormat, path: &Path) -> Result<Vec<Record>, ::HistoryError> {
+    let mut buf = Vec::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_end(&mut buf)));
+    format.read(&buf)
+}
+
+fn write_file(format: &dyn HistoryFormat,
+              records: &[Record],
+              path: &Path)
+              -> Result<(), ::HistoryError> {
+    let mut out = BufWriter::new(try!(File::create(path)));
+    try!(format.write(records, &mut out));
+    try!(out.flush());
+    Ok(())
+}
+
+/// Add the entries in the file at `path`, which is in `format`, to the end of the history list.
+/// Each entry's time stamp is set with `listmgmt::add_time`, and its metadata, if any, is attached
+/// as its payload. Returns the number of entries added.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::{format, listinfo, listmgmt};
+/// use rl_sys::history::format::{Metadata, ZshExtended};
+/// use std::env;
+/// use std::fs;
+///
+/// let path = env::temp_dir().join("rl-sys-import-doctest");
+/// fs::write(&path, ": 1500000000:2;make\n").unwrap();
+///
+/// listmgmt::clear();
+/// assert_eq!(format::import(&ZshExtended, &path).unwrap(), 1);
+///
+/// let entry = &listinfo::list().unwrap()[0];
+/// assert_eq!(entry.line, "make");
+/// assert!(entry.timestamp.is_some());
+/// assert!(entry.get_payload::<Metadata>().unwrap().duration.is_some());
+/// fs::remove_file(&path).unwrap();
+/// ```
+pub fn import(format: &dyn HistoryFormat, path: &Path) -> Result<usize, ::HistoryError> {
+    let records = try!(read_file(format, path));
+
+    for r in &records {
+        try!(history::add_record(r));
+    }
+    Ok(records.len())
+}
+
+/// Write the history list to the file at `path` in `format`, replacing the file if it exists. An
+/// entry's metadata is taken from its payload, if that is a `Metadata`. Returns the number of
+/// entries written.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::{format, listmgmt, payload};
+/// use rl_sys::history::format::{JsonLines, Metadata};
+/// use std::env;
+/// use std::fs;
+///
+/// listmgmt::clear();
+/// assert!(listmgmt::add("false").is_ok());
+/// assert!(payload::set_last(Metadata { exit_code: Some(1), ..Default::default() }).is_ok());
+///
+/// let path = env::temp_dir().join("rl-sys-export-doctest");
+/// assert_eq!(format::export(&JsonLines, &path).unwrap(), 1);
+/// assert!(fs::read_to_string(&path).unwrap().contains("\"exit_code\":1"));
+/// fs::remove_file(&path).unwrap();
+/// ```
+pub fn export(format: &dyn HistoryFormat, path: &Path) -> Result<usize, ::HistoryError> {
+    let records: Vec<Record> = history::iter().map(|(_, e)| Record::from(&e)).collect();
+    try!(write_file(format, &records, path));
+    Ok(records.len())
+}
+
+/// Rewrite the file at `input`, which is in the format `from`, to the file at `output` in the
+/// format `to`. The history list is not changed. Returns the number of entries converted.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::format::{self, Bash, ZshExtended};
+/// use std::env;
+/// use std::fs;
+///
+/// let input = env::temp_dir().join("rl-sys-convert-in-doctest");
+/// let output = env::temp_dir().join("rl-sys-convert-out-doctest");
+/// fs::write(&input, "#1500000000\nls\n").unwrap();
+///
+/// assert_eq!(format::convert(&Bash, &input, &ZshExtended, &output).unwrap(), 1);
+/// assert_eq!(fs::read_to_string(&output).unwrap(), ": 1500000000:0;ls\n");
+/// fs::remove_file(&input).unwrap();
+/// fs::remove_file(&output).unwrap();
+/// ```
+pub fn convert(from: &dyn HistoryFormat,
+               input: &Path,
+               to: &dyn HistoryFormat,
+               output: &Path)
+               -> Result<usize, ::HistoryError> {
+    let records = try!(read_file(from, input));
+    try!(write_file(to, &records, output));
+    Ok(records.len())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::*;
+
+    fn round_trip(format: &dyn HistoryFormat, records: &[Record]) -> Vec<Record> {
+        let mut out = Vec::new();
+        format.write(records, &mut out).unwrap();
+        format.read(&out).unwrap()
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let records = vec![Record {
+                               line: String::from("echo \"caf\u{e9}\"\tdone"),
+                               timestamp: Some(UNIX_EPOCH + Duration::from_secs(1_500_000_000)),
+                               meta: Metadata {
+                                   exit_code: Some(-1),
+                                   cwd: Some(PathBuf::from("/tmp/\u{65e5}\u{672c}")),
+                                   duration: Some(Duration::from_millis(1500)),
+                               },
+                           },
+                           Record { line: String::from("pwd"), ..Default::default() }];
+
+        assert_eq!(round_trip(&JsonLines, &records), records);
+
+        let bash = round_trip(&Bash, &records);
+        assert_eq!(bash[0].timestamp, records[0].timestamp);
+        assert_eq!(bash[1].line, "pwd");
+
+        let zsh = round_trip(&ZshExtended, &records);
+        assert_eq!(zsh[0].line, records[0].line);
+        assert_eq!(zsh[0].meta.duration, Some(Duration::from_secs(1)));
+        assert_eq!(zsh[1].timestamp, None);
+
+        let multi = vec![Record { line: String::from("a\nb"), ..Default::default() }];
+        for format in &[&Plain as &dyn HistoryFormat, &Bash] {
+            let err = format.write(&multi, &mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(round_trip(&ZshExtended, &multi)[0].line, "a\nb");
+
+        assert_eq!(Plain.read(b"ls\n\npwd").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_zsh_metafy() {
+        // The second byte of this character is one that zsh metafies.
+        let line = "\u{e0} la carte";
+        let bytes = zsh_metafy(line.as_bytes());
+        assert!(bytes.contains(&ZSH_META));
+        assert_eq!(zsh_unmetafy(&bytes), line.as_bytes());
+
+        let records = ZshExtended.read(b"ls\n: 1:0;multi\\\nline\n").unwrap();
+        assert_eq!(records[0], Record { line: String::from("ls"), ..Default::default() });
+        assert_eq!(records[1].line, "multi\nline");
+
+        let huge = ZshExtended.read(b": 18446744073709551615:0;ls\n").unwrap();
+        assert_eq!(huge[0].timestamp, None);
+        assert_eq!(Bash.read(b"#18446744073709551615\nls\n").unwrap()[0].timestamp, None);
+    }
+
+    #[test]
+    fn test_json_errors() {
+        assert!(JsonLines.read(b"{\"line\":\"a\\ud83d\\ude00\"}").unwrap()[0].line == "a\u{1f600}");
+        let nested = b"{\"line\":\"a\",\"extra\":[1,{\"b\":\"]\"}],\"more\":{}}";
+        assert_eq!(JsonLines.read(nested).unwrap()[0].line, "a");
+        assert!(JsonLines.read(b"{\"line\":\"a\",\"extra\":[1}").is_err());
+        assert!(JsonLines.read(b"{\"line\":\"a\",\"cwd\":[]}").is_err());
+        assert!(JsonLines.read(b"{\"exit_code\":1}").is_err());
+        assert!(JsonLines.read(b"{\"line\":\"a\"} x").is_err());
+        assert!(JsonLines.read(b"{\"line\":\"a\",\"exit_code\":\"1\"}").is_err());
+        assert!(JsonLines.read(b"{\"line\":\"a\",\"unknown\":null}").is_ok());
+        assert!(JsonLines.read(b"{\"line\":\"a\",\"timestamp\":18446744073709551615}").is_err());
+
+        match JsonLines.read(b"\n{\"line\":") {
+            Err(::HistoryError::Format { line, .. }) => assert_eq!(line, 2),
+            _ => panic!("expected Format"),
+        }
+    }
+}
@@ -21,7 +21,9 @@
 //! functions for managing a history file.
 use errno::errno;
 use history::mgmt::init;
+use history::tracking_evictions;
 use std::ffi::CString;
+use std::io;
 use std::path::Path;
 use std::ptr;
 
@@ -57,10 +59,7 @@ fn gen_result(res: i32) -> Result<i32, ::HistoryError> {
     if res == 0 {
         Ok(res)
     } else {
-        let e = errno();
-        let code = e.0 as i32;
-        let out = format!("Error {}: {}", code, e);
-        Err(::HistoryError::new("History Error", &out[..]))
+        Err(::HistoryError::Io(io::Error::from_raw_os_error(errno().0)))
     }
 }
 
@@ -85,8 +84,10 @@ fn gen_result(res: i32) -> Result<i32, ::HistoryError> {
 /// assert!(fs::remove_file(path).is_ok());
 pub fn read(path: Option<&Path>) -> Result<i32, ::HistoryError> {
     init();
-    with_path_ptr(path,
-                  |ptr| { unsafe { gen_result(ext_histfile::read_history(ptr)) } })
+    tracking_evictions(|| {
+        with_path_ptr(path,
+                      |ptr| { unsafe { gen_result(ext_histfile::read_history(ptr)) } })
+    })
 }
 
 /// Read a range of lines from filename, adding them to the history list. Start reading at line
@@ -111,8 +112,11 @@ pub fn read(path: Option<&Path>) -> Result<i32, ::HistoryError> {
 /// assert!(fs::remove_file(path).is_ok());
 pub fn read_range(path: Option<&Path>, from: i32, to: i32) -> Result<i32, ::HistoryError> {
     init();
-    with_path_ptr(path,
-                  |ptr| { unsafe { gen_result(ext_histfile::read_history_range(ptr, from, to)) } })
+    tracking_evictions(|| {
+        with_path_ptr(path, |ptr| {
+            unsafe { gen_result(ext_histfile::read_history_range(ptr, from, to)) }
+        })
+    })
 }
 
 /// Write the current history to `filename`, overwriting `filename` if necessary. If `filename` is
@@ -0,0 +1,221 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Iterating the History List
+//!
+//! `iter()` walks a snapshot of the history list without touching the current history offset, so
+//! it is unaffected by (and has no effect on) `move_::previous` and `move_::next`. Each entry is
+//! paired with its logical index, which is the index `listinfo::get` accepts.
+//!
+//! A `Cursor` is for browsing with the `move_` functions themselves. It saves the current offset
+//! when created and restores it when dropped, so the user's arrow-key position in `readline()` is
+//! left where it was.
+use history::{Entry, listinfo, mgmt, move_, vars};
+use history::mgmt::init;
+use std::iter::Enumerate;
+use std::vec;
+
+/// An iterator over a snapshot of the history list. See `iter()`.
+#[derive(Debug)]
+pub struct Iter {
+    base: i32,
+    entries: Enumerate<vec::IntoIter<Entry>>,
+}
+
+impl Iter {
+    #[cfg_attr(feature = "clippy", allow(cast_possible_truncation, cast_possible_wrap))]
+    fn item(&self, (i, entry): (usize, Entry)) -> (i32, Entry) {
+        (self.base + i as i32, entry)
+    }
+}
+
+impl Iterator for Iter {
+    type Item = (i32, Entry);
+
+    fn next(&mut self) -> Option<(i32, Entry)> {
+        self.entries.next().map(|e| self.item(e))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Iter {
+    fn next_back(&mut self) -> Option<(i32, Entry)> {
+        self.entries.next_back().map(|e| self.item(e))
+    }
+}
+
+impl ExactSizeIterator for Iter {}
+
+/// Return an iterator over copies of the entries in the history list, oldest first. Each entry is
+/// paired with its logical index, starting from `history_base`. Later changes to the history list
+/// are not seen by the iterator.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::{self, listinfo, listmgmt};
+///
+/// listmgmt::clear();
+/// assert!(listmgmt::add("ls -al").is_ok());
+/// assert!(listmgmt::add("pwd").is_ok());
+///
+/// let entries = history::iter();
+/// assert_eq!(entries.len(), 2);
+///
+/// for (idx, entry) in entries.rev() {
+///     assert_eq!(listinfo::get(idx).unwrap(), entry);
+/// }
+/// ```
+pub fn iter() -> Iter {
+    init();
+    Iter {
+        base: unsafe { vars::history_base },
+        entries: listinfo::list().unwrap_or_default().into_iter().enumerate(),
+    }
+}
+
+/// Saves the current history offset when created, and restores it when dropped. See the module
+/// documentation.
+#[must_use]
+#[derive(Debug)]
+pub struct Cursor {
+    saved: i32,
+}
+
+impl Cursor {
+    /// Save the current history offset and return a cursor starting from it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::{listinfo, listmgmt, move_};
+    /// use rl_sys::history::iter::Cursor;
+    ///
+    /// listmgmt::clear();
+    /// assert!(listmgmt::add("ls -al").is_ok());
+    /// assert!(listmgmt::add("pwd").is_ok());
+    /// assert!(move_::set_pos(2));
+    ///
+    /// {
+    ///     let cursor = Cursor::new();
+    ///     assert!(cursor.set_pos(0));
+    ///     assert_eq!(cursor.next().unwrap().line, "pwd");
+    ///     assert_eq!(cursor.offset(), 1);
+    /// }
+    ///
+    /// assert_eq!(listinfo::offset(), 2);
+    /// ```
+    pub fn new() -> Cursor {
+        Cursor { saved: listinfo::offset() }
+    }
+
+    /// Get the offset the cursor is at.
+    pub fn offset(&self) -> i32 {
+        listinfo::offset()
+    }
+
+    /// Move the cursor to `offset`, an absolute index into the list. Returns false if the offset is
+    /// out of range.
+    pub fn set_pos(&self, offset: i32) -> bool {
+        move_::set_pos(offset)
+    }
+
+    /// Return a copy of the entry at the cursor.
+    pub fn current(&self) -> Result<Entry, ::HistoryError> {
+        listinfo::current()
+    }
+
+    /// Move the cursor back one entry and return a copy of that entry.
+    pub fn previous(&self) -> Result<Entry, ::HistoryError> {
+        move_::previous()
+    }
+
+    /// Move the cursor forward one entry and return a copy of that entry.
+    pub fn next(&self) -> Result<Entry, ::HistoryError> {
+        move_::next()
+    }
+}
+
+impl Default for Cursor {
+    fn default() -> Cursor {
+        Cursor::new()
+    }
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        // If entries were removed while the cursor was alive, the saved offset may be past the end
+        // of the list, in which case the offset goes to the end.
+        if !move_::set_pos(self.saved) {
+            move_::set_pos(mgmt::get_state().length);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use history::{listinfo, listmgmt, move_, vars};
+    use super::*;
+
+    #[test]
+    fn test_iter() {
+        listmgmt::clear();
+        assert!(listmgmt::add("one").is_ok());
+        assert!(listmgmt::add("two").is_ok());
+        assert!(listmgmt::add("three").is_ok());
+
+        let base = unsafe { vars::history_base };
+        let mut entries = iter();
+        assert_eq!(entries.len(), 3);
+
+        let (idx, entry) = entries.next_back().unwrap();
+        assert_eq!((idx, &entry.line[..]), (base + 2, "three"));
+        let (idx, entry) = entries.next().unwrap();
+        assert_eq!((idx, &entry.line[..]), (base, "one"));
+        assert_eq!(entries.len(), 1);
+
+        listmgmt::clear();
+        assert_eq!(entries.next().map(|(_, e)| e.line), Some(String::from("two")));
+        assert!(entries.next().is_none());
+        assert_eq!(iter().len(), 0);
+    }
+
+    #[test]
+    fn test_cursor_restores_offset() {
+        listmgmt::clear();
+        assert!(listmgmt::add("one").is_ok());
+        assert!(listmgmt::add("two").is_ok());
+        assert!(move_::set_pos(1));
+
+        {
+            let cursor = Cursor::new();
+            assert!(cursor.set_pos(0));
+            assert!(cursor.previous().is_err());
+            assert_eq!(cursor.next().unwrap().line, "two");
+        }
+        assert_eq!(listinfo::offset(), 1);
+
+        {
+            let _cursor = Cursor::new();
+            listmgmt::clear();
+        }
+        assert_eq!(listinfo::offset(), 0);
+    }
+}
@@ -18,12 +18,12 @@
 //! [2.3.3 Information About the History List](https://goo.gl/8OWMTy)
 //!
 //! These functions return information about the entire history list or individual list entries.
-use history::HistoryEntry;
+use history::Entry;
 use history::mgmt::init;
-use time::Timespec;
+use std::time::SystemTime;
 
 mod ext_listinfo {
-    use libc::{c_int, c_long};
+    use libc::c_int;
     use history::HistoryEntry;
 
     extern "C" {
@@ -31,13 +31,12 @@ mod ext_listinfo {
         pub fn where_history() -> c_int;
         pub fn current_history() -> *mut HistoryEntry;
         pub fn history_get(which: c_int) -> *mut HistoryEntry;
-        pub fn history_get_time(which: *mut HistoryEntry) -> c_long;
         pub fn history_total_bytes() -> c_int;
     }
 }
 
-/// Return a `Vec<HistoryEntry>` which is the current input history. Element 0 of this list is the
-/// beginning of time. If there is no history, return an empty vector.
+/// Return a `Vec<Entry>` which is a copy of the current input history. Element 0 of this list is
+/// the beginning of time. If there is no history, return an empty vector.
 ///
 /// # Examples
 ///
@@ -48,22 +47,19 @@ mod ext_listinfo {
 /// let entries = listinfo::list().unwrap();
 /// assert!(entries.len() == 1);
 /// ```
-pub fn list() -> Result<Vec<HistoryEntry>, ::HistoryError> {
+pub fn list() -> Result<Vec<Entry>, ::HistoryError> {
     init();
     unsafe {
         let ptr = ext_listinfo::history_list();
 
         if ptr.is_null() {
-            Err(::HistoryError::new("Null Pointer", "Unable to access history list"))
+            Err(::HistoryError::NullPointer { func: String::from("history_list") })
         } else {
             let mut entries = Vec::new();
             for i in 0.. {
-                let entry_ptr = *ptr.offset(i as isize);
-
-                if entry_ptr.is_null() {
-                    break;
-                } else {
-                    entries.push(*entry_ptr);
+                match Entry::from_ptr(*ptr.offset(i as isize)) {
+                    Some(entry) => entries.push(entry),
+                    None => break,
                 }
             }
             Ok(entries)
@@ -86,8 +82,8 @@ pub fn offset() -> i32 {
     unsafe { ext_listinfo::where_history() }
 }
 
-/// Return the history entry at the current position, as determined by `where_history()`. If there
-/// is no entry there, return a `HistoryError`.
+/// Return a copy of the history entry at the current position, as determined by
+/// `where_history()`. If there is no entry there, return a `HistoryError`.
 ///
 /// # Examples
 ///
@@ -97,21 +93,15 @@ pub fn offset() -> i32 {
 /// assert!(listmgmt::add("test").is_ok());
 /// assert!(listinfo::current().is_ok());
 /// ```
-pub fn current<'a>() -> Result<&'a mut HistoryEntry, ::HistoryError> {
+pub fn current() -> Result<Entry, ::HistoryError> {
     init();
     unsafe {
-        let ptr = ext_listinfo::current_history();
-
-        if ptr.is_null() {
-            Err(::HistoryError::new("Null Pointer", "Unable to read the current history!"))
-        } else {
-            Ok(&mut *ptr)
-        }
+        Entry::from_ptr(ext_listinfo::current_history()).ok_or(::HistoryError::NoEntry)
     }
 }
 
-/// Return the history entry at position offset, starting from `history_base`. If there is no entry
-/// there, or if offset is greater than the history length, return a `HistoryError`.
+/// Return a copy of the history entry at position offset, starting from `history_base`. If there is
+/// no entry there, or if offset is greater than the history length, return a `HistoryError`.
 ///
 /// # Examples
 ///
@@ -122,35 +112,32 @@ pub fn current<'a>() -> Result<&'a mut HistoryEntry, ::HistoryError> {
 /// assert!(vars::history_base == 1);
 /// assert!(listinfo::get(1).is_ok());
 /// ```
-pub fn get<'a>(offset: i32) -> Result<&'a mut HistoryEntry, ::HistoryError> {
+pub fn get(offset: i32) -> Result<Entry, ::HistoryError> {
     init();
     unsafe {
-        let ptr = ext_listinfo::history_get(offset);
-
-        if ptr.is_null() {
-            Err(::HistoryError::new("Null Pointer", "Unable to get the history!"))
-        } else {
-            Ok(&mut *ptr)
-        }
+        Entry::from_ptr(ext_listinfo::history_get(offset))
+            .ok_or(::HistoryError::HistoryOutOfRange { offset })
     }
 }
 
-/// Return the time stamp associated with the history entry entry.
+/// Return the time stamp associated with the history entry `entry`, or `None` if it has none or
+/// the stamp could not be parsed.
 ///
 /// # Examples
 ///
 /// ```
-/// use rl_sys::history::{listinfo, listmgmt, vars};
+/// use rl_sys::history::{listinfo, listmgmt};
+/// use std::time::SystemTime;
 ///
-/// vars::set_comment_char(':');
+/// listmgmt::clear();
 /// assert!(listmgmt::add("test").is_ok());
-/// assert!(vars::history_base == 1);
-/// let entry = listinfo::get(1).unwrap();
-/// assert!(listinfo::get_time(entry).sec > 0);
+/// assert!(listmgmt::add_time(SystemTime::now()).is_ok());
+///
+/// let entry = &listinfo::list().unwrap()[0];
+/// assert!(listinfo::get_time(entry).is_some());
 /// ```
-pub fn get_time(entry: &mut HistoryEntry) -> Timespec {
-    init();
-    Timespec::new(unsafe { ext_listinfo::history_get_time(entry) } as i64, 0)
+pub fn get_time(entry: &Entry) -> Option<SystemTime> {
+    entry.timestamp
 }
 
 /// Return the number of bytes that the primary history entries are using. This function returns the
@@ -187,4 +174,12 @@ mod test {
             Err(_) => assert!(false),
         }
     }
+
+    #[test]
+    fn test_entries_outlive_clear() {
+        assert!(listmgmt::add("still here").is_ok());
+        let entries = list().unwrap();
+        listmgmt::clear();
+        assert_eq!(entries.last().map(|e| &e.line[..]), Some("still here"));
+    }
 }
@@ -20,11 +20,11 @@
 //! These functions manage individual entries on the history list, or set parameters managing the
 //! list itself.
 use libc::{c_int, c_void};
-use history::{HistoryEntry, vars};
+use history::{Entry, HistoryEntry, drop_payload, timestamps, tracking_evictions, vars};
 use history::mgmt::init;
 use std::ffi::CString;
 use std::ptr;
-use time::Timespec;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 mod ext_listmgmt {
     use libc::{c_char, c_int, c_void};
@@ -47,6 +47,7 @@ mod ext_listmgmt {
 }
 
 /// Place string at the end of the history list. The associated data field (if any) is set to NULL.
+/// If `timestamps::enable` has been called, the entry is stamped with the current time.
 ///
 /// # Examples
 ///
@@ -62,93 +63,91 @@ pub fn add(line: &str) -> Result<(), ::HistoryError> {
     init();
     let csline = try!(CString::new(line));
 
-    unsafe {
-        ext_listmgmt::add_history(csline.as_ptr());
-    }
-
-    Ok(())
-}
-
-/// Change the time stamp associated with the most recent history entry to the given time.  Note
-/// that if the `history_comment_char` variable has not been set this will have no effect.  This is
-/// stored as seconds since the epoch, so you may lose some precision.
-///
-/// # Examples
-///
-/// ```
-/// # extern crate rl_sys;
-/// # extern crate time;
-/// # fn main() {
-/// use rl_sys::history::{listmgmt, vars};
-///
-/// vars::set_comment_char(':');
-/// match listmgmt::add_time(time::now().to_timespec()) {
-///     Ok(_)  => assert!(true),
-///     Err(e) => assert!(false),
-/// }
-/// # }
-/// ```
-pub fn add_time(time: Timespec) -> Result<(), ::HistoryError> {
-    init();
-    let cc = vars::get_comment_char();
+    tracking_evictions(|| unsafe { ext_listmgmt::add_history(csline.as_ptr()) });
 
-    if cc == '\u{0}' {
-        Ok(())
+    if timestamps::is_enabled() {
+        add_time(SystemTime::now())
     } else {
-        let now_str = format!("{}{}", cc, time.sec);
-        let cstime = try!(CString::new(now_str));
-        unsafe {
-            ext_listmgmt::add_history_time(cstime.as_ptr());
-        }
         Ok(())
     }
 }
 
-/// Remove history entry at the given offset from the history. The removed element is returned so
-/// you can free the line, data, and containing structure.
+/// Change the time stamp associated with the most recent history entry to the given time. This is
+/// stored as seconds since the epoch, so you may lose some precision. Returns an Err if the time is
+/// before the epoch.
+///
+/// The time stamp begins with `history_comment_char`, or `#` if that is not set, and is only saved
+/// to the history file while `history_write_timestamps` is set. See `timestamps::enable`.
 ///
 /// # Examples
 ///
 /// ```
-/// use rl_sys::history::listmgmt;
+/// use rl_sys::history::{listinfo, listmgmt};
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
 ///
+/// listmgmt::clear();
 /// assert!(listmgmt::add("ls -al").is_ok());
-/// let _ = listmgmt::remove(0);
+/// assert!(listmgmt::add_time(time).is_ok());
+/// assert_eq!(listinfo::list().unwrap()[0].timestamp, Some(time));
+///
+/// assert!(listmgmt::add_time(UNIX_EPOCH - Duration::from_secs(1)).is_err());
 /// ```
-pub fn remove<'a>(offset: i32) -> &'a mut HistoryEntry {
+pub fn add_time(time: SystemTime) -> Result<(), ::HistoryError> {
     init();
-    unsafe { &mut *ext_listmgmt::remove_history(offset) }
+    let secs = try!(time.duration_since(UNIX_EPOCH)).as_secs();
+
+    // Readline's own stamps start with history_comment_char as is, so they're empty while it's
+    // unset. A NUL can't be put in the stamp, so use `#` then, as bash does.
+    let cc = match vars::get_comment_char() {
+        '\u{0}' => '#',
+        c => c,
+    };
+
+    let cstime = try!(CString::new(format!("{}{}", cc, secs)));
+    unsafe {
+        ext_listmgmt::add_history_time(cstime.as_ptr());
+    }
+    Ok(())
 }
 
-/// Free the history entry and any history library private data associated with it. If there is
-/// application-specific data, an Err is returned with a pointer to the data so the caller can
-/// dispose of it
+/// Remove the history entry at the given offset from the history and return a copy of it. The
+/// entry itself is freed, but any application-specific data is not, so the returned `data` is now
+/// owned by the caller. In the case of an invalid offset, an Err is returned.
 ///
 /// # Examples
 ///
 /// ```
 /// use rl_sys::history::listmgmt;
 ///
+/// listmgmt::clear();
 /// assert!(listmgmt::add("ls -al").is_ok());
-/// let entry = listmgmt::remove(0);
-/// assert!(listmgmt::free_entry(entry).is_ok());
+///
+/// let entry = listmgmt::remove(0).unwrap();
+/// assert_eq!(entry.line, "ls -al");
+/// assert!(listmgmt::remove(0).is_err());
 /// ```
-pub fn free_entry(entry: &mut HistoryEntry) -> Result<(), *mut c_void> {
+pub fn remove(offset: i32) -> Result<Entry, ::HistoryError> {
     init();
-    unsafe {
-        let data_ptr = ext_listmgmt::free_history_entry(entry);
+    tracking_evictions(|| unsafe { take_entry(ext_listmgmt::remove_history(offset), offset) })
+}
 
-        if data_ptr.is_null() {
-            Ok(())
-        } else {
-            Err(data_ptr)
+/// Copy and free an entry that has been taken off the history list, leaving its data alone.
+unsafe fn take_entry(ptr: *mut HistoryEntry, offset: i32) -> Result<Entry, ::HistoryError> {
+    match Entry::from_ptr(ptr) {
+        Some(entry) => {
+            drop_payload(ptr);
+            ext_listmgmt::free_history_entry(ptr);
+            Ok(entry)
         }
+        None => Err(::HistoryError::HistoryOutOfRange { offset }),
     }
 }
 
-/// Replace the history entry at offset with the given line and data. This returns the old entry so
-/// the caller can dispose of any application-specific data. In the case of an invalid offset, an
-/// Err is returned.
+/// Replace the history entry at offset with the given line and data. This returns a copy of the old
+/// entry so the caller can dispose of any application-specific data. In the case of an invalid
+/// offset, an Err is returned.
 ///
 /// # Examples
 ///
@@ -157,13 +156,15 @@ pub fn free_entry(entry: &mut HistoryEntry) -> Result<(), *mut c_void> {
 ///
 /// assert!(listmgmt::add("ls -al").is_ok());
 /// assert_eq!(vars::history_length, 1);
-/// assert!(listmgmt::replace_entry(0, "test", None).is_ok());
+///
+/// let old = listmgmt::replace_entry(0, "test", None).unwrap();
+/// assert_eq!(old.line, "ls -al");
 /// assert_eq!(vars::history_length, 1);
 /// ```
 pub fn replace_entry(offset: i32,
                      line: &str,
                      appdata: Option<*mut c_void>)
-                     -> Result<&mut HistoryEntry, ::HistoryError> {
+                     -> Result<Entry, ::HistoryError> {
     init();
     let csline = try!(CString::new(line));
     let ptr = match appdata {
@@ -171,15 +172,10 @@ pub fn replace_entry(offset: i32,
         None => ptr::null_mut(),
     };
 
-    unsafe {
+    tracking_evictions(|| unsafe {
         let old_entry = ext_listmgmt::replace_history_entry(offset, csline.as_ptr(), ptr);
-
-        if old_entry.is_null() {
-            Err(::HistoryError::new("Null Pointer", "Invalid replace requested!"))
-        } else {
-            Ok(&mut *old_entry)
-        }
-    }
+        take_entry(old_entry, offset)
+    })
 }
 
 /// Clear the history list by deleting all the entries.
@@ -196,7 +192,7 @@ pub fn replace_entry(offset: i32,
 /// ```
 pub fn clear() {
     init();
-    unsafe { ext_listmgmt::clear_history() }
+    tracking_evictions(|| unsafe { ext_listmgmt::clear_history() })
 }
 
 /// Stifle the history list, remembering only the last *max* entries.
@@ -211,7 +207,7 @@ pub fn clear() {
 /// ```
 pub fn stifle(max: i32) {
     init();
-    unsafe { ext_listmgmt::stifle_history(max as c_int) }
+    tracking_evictions(|| unsafe { ext_listmgmt::stifle_history(max as c_int) })
 }
 
 /// Stop stifling the history. This returns the previously-set maximum number of history entries
@@ -263,4 +259,22 @@ mod test {
         assert_eq!(max, unstifle());
         assert!(!is_stifled());
     }
+
+    #[test]
+    fn test_removed_entry_outlives_list() {
+        clear();
+        assert!(add("first").is_ok());
+        assert!(add("second").is_ok());
+
+        let removed = remove(0).unwrap();
+        let replaced = replace_entry(0, "third", None).unwrap();
+        stifle(0);
+        clear();
+        unstifle();
+
+        assert_eq!(removed.line, "first");
+        assert_eq!(replaced.line, "second");
+        assert!(remove(0).is_err());
+        assert!(replace_entry(0, "fourth", None).is_err());
+    }
 }
@@ -56,7 +56,8 @@ fn get_state_ptr() -> *mut HistoryState {
     unsafe { ext_mgmt::history_get_history_state() }
 }
 
-/// Return a structure describing the current state of the input history.
+/// Return a copy of the structure describing the current state of the input history. The
+/// `entries` pointer is only valid until the history list is next changed.
 ///
 /// # Examples
 /// ```
@@ -67,9 +68,15 @@ fn get_state_ptr() -> *mut HistoryState {
 /// let state = mgmt::get_state();
 /// assert!(state.offset == 0);
 /// ```
-pub fn get_state<'a>() -> &'a mut HistoryState {
+pub fn get_state() -> HistoryState {
+    use libc::c_void;
+    use readline::util;
+
     init();
-    unsafe { &mut *get_state_ptr() }
+    let state_ptr = get_state_ptr();
+    let state = unsafe { *state_ptr };
+    util::free(state_ptr as *mut c_void);
+    state
 }
 
 /// Set the state of the history list according to state.
@@ -101,12 +108,13 @@ pub fn set_state(state: &mut HistoryState) -> () {
 /// mgmt::cleanup();
 /// ```
 pub fn cleanup() -> () {
+    use history::tracking_evictions;
     use libc::c_void;
     use readline::{misc, util};
 
     // Clear the history via Readline API.  This frees all Histoy Entry data, but not the list
     // itself.
-    misc::clear_history();
+    tracking_evictions(misc::clear_history);
 
     // Get a pointer to the History State.
     let state_ptr = get_state_ptr();
@@ -126,6 +134,6 @@ mod test {
         use super::get_state;
 
         let state = get_state();
-        assert!(mem::size_of_val(state) > 0);
+        assert!(mem::size_of_val(&state) > 0);
     }
 }
@@ -40,19 +40,46 @@
 //! the History library's features. It supplies extern declarations for all of the library's public
 //! functions and variables, and declares all of the public data structures.
 use libc::{c_char, c_int, c_uint, c_void};
+use self::payload::Payload;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::default::Default;
 use std::ffi::CStr;
 use std::fmt;
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub mod expand;
+pub mod format;
 pub mod histfile;
+pub mod iter;
 pub mod listinfo;
 pub mod listmgmt;
 pub mod mgmt;
 pub mod move_;
+pub mod payload;
+pub mod persist;
+pub mod policy;
 pub mod search;
+#[cfg(feature = "sqlite")]
+pub mod store;
+pub mod timestamps;
 pub mod vars;
 
+pub use self::iter::iter;
+
+mod ext_history {
+    use history::HistoryEntry;
+
+    extern "C" {
+        pub fn history_list() -> *mut *mut HistoryEntry;
+    }
+}
+
+thread_local!(static PAYLOADS: RefCell<HashMap<usize, Payload>> = RefCell::new(HashMap::new()));
+thread_local!(static SYNCED: Cell<Option<ListState>> = Cell::new(None));
+
 /// Application specific data attached to the history entry.
 pub type HistoryData = *mut c_void;
 /// Inhibit Expansion Function Type.
@@ -78,8 +105,8 @@ impl Default for HistoryEntry {
 
 impl fmt::Debug for HistoryEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let line = unsafe { CStr::from_ptr(self.line).to_string_lossy().into_owned() };
-        let time = unsafe { CStr::from_ptr(self.timestamp).to_string_lossy().into_owned() };
+        let line = unsafe { lossy(self.line) };
+        let time = unsafe { lossy(self.timestamp) };
         write!(f,
                "HistoryEntry {{ line: {}, timestamp: {}, data: {:?} }}",
                line,
@@ -88,6 +115,180 @@ impl fmt::Debug for HistoryEntry {
     }
 }
 
+/// Copy the C string at `ptr`, which may be null.
+unsafe fn lossy(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(ptr).to_string_lossy().into_owned()
+    }
+}
+
+/// An owned copy of a history entry. Unlike `HistoryEntry`, which is kept for FFI, an `Entry`
+/// stays valid after the history list is changed or cleared.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// The line.
+    pub line: String,
+    /// The time the line was added, if the history library recorded one. Timestamps are only
+    /// recorded while `history_comment_char` is set.
+    pub timestamp: Option<SystemTime>,
+    data: HistoryData,
+    /// The payload attached to the entry with `payload::set`, if any.
+    pub payload: Option<Payload>,
+}
+
+impl Entry {
+    /// Copy the entry at `ptr`, or return `None` if `ptr` is null.
+    unsafe fn from_ptr(ptr: *const HistoryEntry) -> Option<Entry> {
+        if ptr.is_null() {
+            return None;
+        }
+
+        let entry = &*ptr;
+        let timestamp = if entry.timestamp.is_null() {
+            None
+        } else {
+            parse_timestamp(CStr::from_ptr(entry.timestamp).to_bytes())
+        };
+
+        Some(Entry {
+            line: lossy(entry.line),
+            timestamp,
+            data: entry.data,
+            payload: PAYLOADS.with(|p| p.borrow().get(&(ptr as usize)).cloned()),
+        })
+    }
+
+    /// The application specific data that was attached to the entry when it was copied. This is
+    /// readline's pointer, not a copy: the history list or the application owns what it points to,
+    /// so it dangles once that has been freed, for example after the entry is removed, replaced or
+    /// cleared. Only dereference it while the entry is known to still be on the history list.
+    pub fn data(&self) -> HistoryData {
+        self.data
+    }
+
+    /// Get the payload attached to the entry, if there is one of type `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::{self, listmgmt, payload};
+    ///
+    /// listmgmt::clear();
+    /// assert!(listmgmt::add("make").is_ok());
+    /// assert!(payload::set_last(2i32).is_ok());
+    ///
+    /// let (_, entry) = history::iter().next().unwrap();
+    /// assert_eq!(entry.get_payload::<i32>(), Some(&2));
+    /// assert_eq!(entry.get_payload::<String>(), None);
+    /// ```
+    pub fn get_payload<T: Any>(&self) -> Option<&T> {
+        self.payload.as_ref().and_then(|p| p.downcast_ref())
+    }
+}
+
+/// The parts of the history list that change whenever readline frees an entry other than by
+/// `replace_history_entry`: `history_base`, `history_length` and the oldest entry.
+type ListState = (c_int, c_int, usize);
+
+fn list_state() -> ListState {
+    unsafe {
+        let list = ext_history::history_list();
+        let first = if list.is_null() { 0 } else { *list as usize };
+        (vars::history_base, vars::history_length, first)
+    }
+}
+
+/// Drop the payloads of entries that are no longer on the history list.
+fn sweep_payloads() {
+    let evicted = PAYLOADS.with(|p| {
+        let mut payloads = p.borrow_mut();
+        if payloads.is_empty() {
+            return Vec::new();
+        }
+
+        let mut live = HashSet::new();
+        unsafe {
+            let list = ext_history::history_list();
+            if !list.is_null() {
+                for i in 0.. {
+                    let entry = *list.offset(i);
+                    if entry.is_null() {
+                        break;
+                    }
+                    live.insert(entry as usize);
+                }
+            }
+        }
+
+        let dead: Vec<usize> = payloads.keys().filter(|k| !live.contains(k)).cloned().collect();
+        dead.iter().filter_map(|k| payloads.remove(k)).collect::<Vec<Payload>>()
+    });
+
+    // The payloads are dropped here, after the borrow ends, in case their destructors use the
+    // history API.
+    drop(evicted);
+}
+
+/// Sweep the payloads if entries may have been freed since the last call. Appending to an unstifled
+/// list frees nothing and leaves the base and the oldest entry alone, so it doesn't need the list
+/// walked again.
+fn sync_payloads() {
+    let now = list_state();
+    match SYNCED.with(|s| s.replace(Some(now))) {
+        Some((base, length, first)) if base == now.0 && length <= now.1 && first == now.2 => {}
+        _ => sweep_payloads(),
+    }
+}
+
+/// Drop the payload of the entry at `ptr`, which is about to be freed.
+fn drop_payload(ptr: *const HistoryEntry) {
+    let old = PAYLOADS.with(|p| p.borrow_mut().remove(&(ptr as usize)));
+    drop(old);
+}
+
+/// Run `f`, which may free history entries, and drop the payloads of any that it removes.
+///
+/// Payloads are keyed by entry address, so they are also synced before `f` runs. Otherwise an entry
+/// freed by readline since the last sync could have its address reused by one that `f` allocates,
+/// which would then pick up the stale payload.
+fn tracking_evictions<T, F>(f: F) -> T
+    where F: FnOnce() -> T
+{
+    sync_payloads();
+    let res = f();
+    sync_payloads();
+    res
+}
+
+/// Parse a history timestamp, which is the comment character followed by the seconds since the
+/// epoch. A time that `SystemTime` can't represent is treated as no time stamp.
+fn parse_timestamp(ts: &[u8]) -> Option<SystemTime> {
+    if ts.len() < 2 {
+        return None;
+    }
+
+    str::from_utf8(&ts[1..])
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .and_then(|secs| if secs == 0 { None } else { Some(secs) })
+        .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+}
+
+/// Add `record` to the end of the history list, with its time stamp and, if it has any, its
+/// metadata as the entry's payload.
+fn add_record(record: &format::Record) -> Result<(), ::HistoryError> {
+    try!(listmgmt::add(&record.line));
+    if let Some(t) = record.timestamp {
+        try!(listmgmt::add_time(t));
+    }
+    if !record.meta.is_empty() {
+        try!(payload::set_last(record.meta.clone()));
+    }
+    Ok(())
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 /// The history state.
@@ -121,3 +322,33 @@ impl fmt::Debug for HistoryState {
                self.flags)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::ptr;
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp(b"#1500000000"),
+                   Some(UNIX_EPOCH + Duration::from_secs(1_500_000_000)));
+        assert_eq!(parse_timestamp(b"#0"), None);
+        assert_eq!(parse_timestamp(b"#"), None);
+        assert_eq!(parse_timestamp(b"#abc"), None);
+        assert_eq!(parse_timestamp(b"#18446744073709551615"), None);
+    }
+
+    #[test]
+    fn test_null_entry() {
+        let raw: HistoryEntry = Default::default();
+        assert!(format!("{:?}", raw).starts_with("HistoryEntry { line: , timestamp: ,"));
+        assert!(unsafe { Entry::from_ptr(ptr::null()) }.is_none());
+
+        let entry = unsafe { Entry::from_ptr(&raw) }.unwrap();
+        assert_eq!(entry.line, "");
+        assert_eq!(entry.timestamp, None);
+        assert_eq!(entry.payload, None);
+        assert!(entry.data().is_null());
+    }
+}
@@ -18,7 +18,7 @@
 //! [2.3.4 Moving Around the History List](https://goo.gl/ROYfRB)
 //!
 //! These functions allow the current index into the history list to be set or changed.
-use history::HistoryEntry;
+use history::Entry;
 use history::mgmt::init;
 
 mod ext_move {
@@ -49,7 +49,7 @@ pub fn set_pos(offset: i32) -> bool {
     unsafe { ext_move::history_set_pos(offset) == 1 }
 }
 
-/// Back up the current history offset to the previous history entry, and return a pointer to that
+/// Back up the current history offset to the previous history entry, and return a copy of that
 /// entry. If there is no previous entry, return a `HistoryError`.
 ///
 /// # Examples
@@ -64,20 +64,14 @@ pub fn set_pos(offset: i32) -> bool {
 /// assert!(move_::previous().is_ok());
 /// assert!(move_::previous().is_err());
 /// ```
-pub fn previous<'a>() -> Result<&'a mut HistoryEntry, ::HistoryError> {
+pub fn previous() -> Result<Entry, ::HistoryError> {
     init();
     unsafe {
-        let ptr = ext_move::previous_history();
-
-        if ptr.is_null() {
-            Err(::HistoryError::new("Null Pointer", "Unable to read the previous history!"))
-        } else {
-            Ok(&mut *ptr)
-        }
+        Entry::from_ptr(ext_move::previous_history()).ok_or(::HistoryError::NoEntry)
     }
 }
 
-/// Move the current history offset forward to the next history entry, and return a pointer to that
+/// Move the current history offset forward to the next history entry, and return a copy of that
 /// entry. If there is no next entry, return a `HistoryError`.
 ///
 /// # Examples
@@ -91,15 +85,9 @@ pub fn previous<'a>() -> Result<&'a mut HistoryEntry, ::HistoryError> {
 /// assert!(move_::next().is_ok());  // Move to pos 1.
 /// assert!(move_::next().is_err()); // There is no pos 2.
 /// ```
-pub fn next<'a>() -> Result<&'a mut HistoryEntry, ::HistoryError> {
+pub fn next() -> Result<Entry, ::HistoryError> {
     init();
     unsafe {
-        let ptr = ext_move::next_history();
-
-        if ptr.is_null() {
-            Err(::HistoryError::new("Null Pointer", "Unable to read the next history!"))
-        } else {
-            Ok(&mut *ptr)
-        }
+        Entry::from_ptr(ext_move::next_history()).ok_or(::HistoryError::NoEntry)
     }
 }
@@ -0,0 +1,189 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Per-entry Application Data
+//!
+//! The `data` field of a history entry can't safely hold a Rust value: readline treats a non-null
+//! `data` on an entry it navigates to as that line's undo list. Instead, this module attaches a
+//! typed payload, such as an exit status or working directory, to an entry through a side table
+//! keyed by the entry itself. The payload is copied into each `Entry` returned by `listinfo`,
+//! `move_` or `history::iter()`, and is read back with `Entry::get_payload`.
+//!
+//! A payload is dropped once its entry leaves the history list through `listmgmt::remove`,
+//! `replace_entry`, `clear`, `stifle` (or an `add` on a stifled list), or `mgmt::cleanup`. A
+//! removed or replaced `Entry` keeps its own reference to the payload. Readline replaces the entry
+//! when a history line is edited in place, so editing a line also drops its payload.
+//!
+//! The table is per-thread, so payloads should be set and read on a single thread.
+use history::{PAYLOADS, HistoryEntry, ext_history, sync_payloads, vars};
+use history::mgmt::init;
+use std::any::Any;
+use std::fmt;
+use std::rc::Rc;
+
+/// A value attached to a history entry. Cloning a payload shares the value.
+#[derive(Clone)]
+pub struct Payload(Rc<dyn Any>);
+
+impl Payload {
+    /// Get the value, if it is of type `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.0.downcast_ref()
+    }
+}
+
+impl fmt::Debug for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Payload {{ .. }}")
+    }
+}
+
+impl PartialEq for Payload {
+    /// Payloads are equal if they share the same value.
+    fn eq(&self, other: &Payload) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Get the entry at the absolute offset `offset`.
+fn entry_ptr(offset: i32) -> Result<*mut HistoryEntry, ::HistoryError> {
+    unsafe {
+        let list = ext_history::history_list();
+        if list.is_null() || offset < 0 || offset >= vars::history_length {
+            Err(::HistoryError::HistoryOutOfRange { offset })
+        } else {
+            Ok(*list.offset(offset as isize))
+        }
+    }
+}
+
+/// Attach `value` to the history entry at the absolute offset `offset`, replacing any payload it
+/// already has. In the case of an invalid offset, an Err is returned.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::{listinfo, listmgmt, payload};
+/// use std::path::PathBuf;
+///
+/// struct Status {
+///     code: i32,
+///     cwd: PathBuf,
+/// }
+///
+/// listmgmt::clear();
+/// assert!(listmgmt::add("cargo test").is_ok());
+/// assert!(payload::set(0, Status { code: 0, cwd: PathBuf::from("/src") }).is_ok());
+/// assert!(payload::set(1, 0).is_err());
+///
+/// let entry = &listinfo::list().unwrap()[0];
+/// assert_eq!(entry.get_payload::<Status>().map(|s| s.code), Some(0));
+/// ```
+pub fn set<T: Any>(offset: i32, value: T) -> Result<(), ::HistoryError> {
+    init();
+    sync_payloads();
+    let key = try!(entry_ptr(offset)) as usize;
+    let old = PAYLOADS.with(|p| p.borrow_mut().insert(key, Payload(Rc::new(value))));
+    drop(old);
+    Ok(())
+}
+
+/// Attach `value` to the most recent history entry. Returns an Err if the history is empty.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::{listinfo, listmgmt, payload};
+/// use std::time::Duration;
+///
+/// listmgmt::clear();
+/// assert!(payload::set_last(Duration::from_millis(5)).is_err());
+///
+/// assert!(listmgmt::add("sleep 0.005").is_ok());
+/// assert!(payload::set_last(Duration::from_millis(5)).is_ok());
+/// assert!(listinfo::list().unwrap()[0].get_payload::<Duration>().is_some());
+/// ```
+pub fn set_last<T: Any>(value: T) -> Result<(), ::HistoryError> {
+    set(unsafe { vars::history_length } - 1, value)
+}
+
+/// Detach the payload from the history entry at the absolute offset `offset`, and return it. In
+/// the case of an invalid offset, an Err is returned.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::{listmgmt, payload};
+///
+/// listmgmt::clear();
+/// assert!(listmgmt::add("ls").is_ok());
+/// assert!(payload::set(0, 1u8).is_ok());
+///
+/// let old = payload::unset(0).unwrap().unwrap();
+/// assert_eq!(old.downcast_ref::<u8>(), Some(&1));
+/// assert!(payload::unset(0).unwrap().is_none());
+/// ```
+pub fn unset(offset: i32) -> Result<Option<Payload>, ::HistoryError> {
+    init();
+    sync_payloads();
+    let key = try!(entry_ptr(offset)) as usize;
+    Ok(PAYLOADS.with(|p| p.borrow_mut().remove(&key)))
+}
+
+#[cfg(test)]
+mod test {
+    use history::{listinfo, listmgmt};
+    use std::rc::Rc;
+    use super::*;
+
+    #[test]
+    fn test_payload_eviction() {
+        let alive = Rc::new(());
+        listmgmt::clear();
+        assert!(listmgmt::add("one").is_ok());
+        assert!(listmgmt::add("two").is_ok());
+        assert!(listmgmt::add("three").is_ok());
+        assert!(set(0, alive.clone()).is_ok());
+        assert!(set(1, alive.clone()).is_ok());
+        assert!(set(2, alive.clone()).is_ok());
+        assert_eq!(Rc::strong_count(&alive), 4);
+
+        // A removed entry keeps its payload until the entry itself is dropped.
+        let removed = listmgmt::remove(0).unwrap();
+        assert!(removed.get_payload::<Rc<()>>().is_some());
+        assert_eq!(Rc::strong_count(&alive), 4);
+        drop(removed);
+        assert_eq!(Rc::strong_count(&alive), 3);
+
+        listmgmt::stifle(1);
+        listmgmt::unstifle();
+        assert_eq!(Rc::strong_count(&alive), 2);
+        assert!(listinfo::list().unwrap()[0].get_payload::<Rc<()>>().is_some());
+
+        // Replacing an entry frees it in place, without moving the list.
+        drop(listmgmt::replace_entry(0, "four", None).unwrap());
+        assert_eq!(Rc::strong_count(&alive), 1);
+
+        // Appending doesn't disturb the payloads already attached.
+        assert!(set(0, alive.clone()).is_ok());
+        assert!(listmgmt::add("five").is_ok());
+        assert_eq!(Rc::strong_count(&alive), 2);
+
+        listmgmt::clear();
+        assert_eq!(Rc::strong_count(&alive), 1);
+    }
+}
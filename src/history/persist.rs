@@ -0,0 +1,603 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Shared History Files
+//!
+//! The functions in `histfile` write straight to the history file, so two programs saving the same
+//! file can interleave their writes, and a crash part way through leaves a truncated file. A
+//! `HistoryFile` is for a history file that several programs share, such as the terminals of one
+//! user:
+//!
+//! * While the file is changed, an advisory `flock` is held on a lock file next to it (the file
+//!   name with `.lock` appended), so programs using `HistoryFile` take turns. The lock file is left
+//!   in place afterwards.
+//! * `save` and `truncate` write the new file to a temporary file in the same directory and
+//!   `rename` it into place, so the file is always either the old or the new version.
+//! * Like bash with `histappend` set, `append` adds only the entries added since the file was last
+//!   read or written, and reads in the entries other programs have appended since then.
+//! * Like zsh with `SHARE_HISTORY` set, `sync` does the same, but reads only the bytes appended
+//!   since the file was last read or written, rather than the whole file.
+//!
+//! The `HistoryFile` keeps track of how many entries of the file are already on the history list,
+//! and how far the list has grown since, so it should be the only way the file is read or written.
+use history::{histfile, listinfo, listmgmt, move_, parse_timestamp, vars};
+use history::mgmt::init;
+use libc;
+use std::cmp;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+/// An exclusive `flock` on a history file's lock file, released when dropped.
+struct Lock(File);
+
+impl Lock {
+    fn acquire(path: &Path) -> io::Result<Lock> {
+        let file = try!(OpenOptions::new()
+                            .write(true)
+                            .create(true)
+                            .truncate(false)
+                            .mode(0o600)
+                            .open(path));
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            Ok(Lock(file))
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+/// Count the entries in the history file at `path`, the way `read_history_range` numbers them.
+/// Time stamp lines are not counted. A missing file has no entries.
+fn count_entries(path: &Path) -> io::Result<i32> {
+    let mut buf = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => try!(file.read_to_end(&mut buf)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let cc = vars::get_comment_char() as u8;
+    let is_timestamp = |line: &[u8]| {
+        cc != 0 && line.len() > 1 && line[0] == cc && line[1].is_ascii_digit()
+    };
+
+    let lines = buf.split(|&b| b == b'\n').filter(|l| !l.is_empty() && !is_timestamp(l)).count();
+    Ok(lines as i32)
+}
+
+/// Get the metadata of the file at `path`, or `None` if there is no such file.
+fn stat(path: &Path) -> io::Result<Option<fs::Metadata>> {
+    match fs::metadata(path) {
+        Ok(md) => Ok(Some(md)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Split bytes appended to a history file into entries, each with the time stamp that preceded
+/// it, if any.
+fn parse_entries(buf: &[u8]) -> Vec<(String, Option<SystemTime>)> {
+    let cc = vars::get_comment_char() as u8;
+    let mut entries = Vec::new();
+    let mut timestamp = None;
+
+    for line in buf.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+        if cc != 0 && line.len() > 1 && line[0] == cc && line[1].is_ascii_digit() {
+            timestamp = parse_timestamp(line);
+        } else {
+            entries.push((String::from_utf8_lossy(line).into_owned(), timestamp.take()));
+        }
+    }
+    entries
+}
+
+/// The logical index one past the last entry on the history list. Unlike `history_length`, this
+/// keeps growing when a stifled list drops its oldest entries.
+fn list_end() -> i32 {
+    unsafe { vars::history_base + vars::history_length }
+}
+
+/// A history file shared with other programs. See the module documentation.
+#[derive(Debug)]
+pub struct HistoryFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    /// The number of entries in the file that are already on the history list.
+    seen: i32,
+    /// The value of `list_end()` when the file was last read or written.
+    mark: i32,
+    /// The length of the file in bytes when it was last read or written.
+    offset: u64,
+    /// The device and inode of the file when it was last read or written.
+    id: Option<(u64, u64)>,
+}
+
+impl HistoryFile {
+    /// Create a `HistoryFile` for the history file at `path`. Nothing is read until `load` is
+    /// called.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::persist::HistoryFile;
+    /// use std::env;
+    ///
+    /// let path = env::temp_dir().join("rl-sys-persist-new-doctest");
+    /// let file = HistoryFile::new(&path);
+    /// assert_eq!(file.path(), path.as_path());
+    /// ```
+    pub fn new<P: AsRef<Path>>(path: P) -> HistoryFile {
+        init();
+        let path = path.as_ref().to_path_buf();
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+
+        HistoryFile {
+            path,
+            lock_path: PathBuf::from(lock_path),
+            seen: 0,
+            mark: list_end(),
+            offset: 0,
+            id: None,
+        }
+    }
+
+    /// Get the path of the history file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add the entries in the history file to the history list. A missing file is treated as
+    /// empty. Returns the number of entries read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::{listinfo, listmgmt};
+    /// use rl_sys::history::persist::HistoryFile;
+    /// use std::env;
+    /// use std::fs;
+    ///
+    /// let path = env::temp_dir().join("rl-sys-persist-load-doctest");
+    /// fs::write(&path, "ls -al\npwd\n").unwrap();
+    ///
+    /// listmgmt::clear();
+    /// let mut file = HistoryFile::new(&path);
+    /// assert_eq!(file.load().unwrap(), 2);
+    /// assert_eq!(listinfo::list().unwrap()[1].line, "pwd");
+    /// fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn load(&mut self) -> Result<i32, ::HistoryError> {
+        let _lock = try!(Lock::acquire(&self.lock_path));
+
+        let count = try!(count_entries(&self.path));
+        if count > 0 {
+            try!(histfile::read(Some(&self.path)));
+        }
+
+        try!(self.remember(count));
+        Ok(count)
+    }
+
+    /// Append the entries added to the history list since the file was last read or written, and
+    /// add the entries other programs have appended to the file since then to the history list.
+    /// Returns the number of entries read from the file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::{listinfo, listmgmt};
+    /// use rl_sys::history::persist::HistoryFile;
+    /// use std::env;
+    /// use std::fs::{self, OpenOptions};
+    /// use std::io::Write;
+    ///
+    /// let path = env::temp_dir().join("rl-sys-persist-append-doctest");
+    /// let _ = fs::remove_file(&path);
+    ///
+    /// listmgmt::clear();
+    /// let mut file = HistoryFile::new(&path);
+    /// assert_eq!(file.load().unwrap(), 0);
+    ///
+    /// // Another terminal appends an entry...
+    /// OpenOptions::new().create(true).append(true).open(&path).unwrap()
+    ///                   .write_all(b"echo other\n").unwrap();
+    ///
+    /// // ...while this one adds its own.
+    /// assert!(listmgmt::add("echo mine").is_ok());
+    /// assert_eq!(file.append().unwrap(), 1);
+    ///
+    /// assert_eq!(fs::read_to_string(&path).unwrap(), "echo other\necho mine\n");
+    /// let lines: Vec<String> = listinfo::list().unwrap().into_iter().map(|e| e.line).collect();
+    /// assert_eq!(lines, vec!["echo mine", "echo other"]);
+    /// fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn append(&mut self) -> Result<i32, ::HistoryError> {
+        let _lock = try!(Lock::acquire(&self.lock_path));
+
+        let added = self.added();
+        if added > 0 {
+            // `append_history` won't create the file.
+            try!(OpenOptions::new().append(true).create(true).mode(0o600).open(&self.path));
+            try!(histfile::append(Some(&self.path), added));
+        }
+
+        let count = try!(count_entries(&self.path));
+        let others = count - added - self.seen;
+        if others > 0 {
+            try!(histfile::read_range(Some(&self.path), self.seen, self.seen + others));
+        }
+
+        try!(self.remember(count));
+        Ok(cmp::max(others, 0))
+    }
+
+    /// Append the entries added to the history list since the file was last read or written, and
+    /// add the entries other programs have appended to the file since then to the end of the
+    /// history list with `listmgmt::add`. Entries already on the history list are left alone.
+    /// Returns the number of entries read from the file.
+    ///
+    /// Only the bytes appended to the file since it was last read or written are read, so other
+    /// programs sharing the file should only append to it. If the file has been replaced or
+    /// truncated since, the entries added to it are skipped rather than read twice.
+    ///
+    /// The current history offset is kept on the same entry, or at the end of the list if it was
+    /// there.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::{listinfo, listmgmt};
+    /// use rl_sys::history::persist::HistoryFile;
+    /// use std::env;
+    /// use std::fs::{self, OpenOptions};
+    /// use std::io::Write;
+    ///
+    /// let path = env::temp_dir().join("rl-sys-persist-sync-doctest");
+    /// fs::write(&path, "ls\n").unwrap();
+    ///
+    /// listmgmt::clear();
+    /// let mut file = HistoryFile::new(&path);
+    /// assert_eq!(file.load().unwrap(), 1);
+    ///
+    /// OpenOptions::new().append(true).open(&path).unwrap().write_all(b"pwd\n").unwrap();
+    /// assert!(listmgmt::add("make").is_ok());
+    ///
+    /// assert_eq!(file.sync().unwrap(), 1);
+    /// assert_eq!(file.sync().unwrap(), 0);
+    ///
+    /// assert_eq!(fs::read_to_string(&path).unwrap(), "ls\npwd\nmake\n");
+    /// let lines: Vec<String> = listinfo::list().unwrap().into_iter().map(|e| e.line).collect();
+    /// assert_eq!(lines, vec!["ls", "make", "pwd"]);
+    /// fs::remove_file(&path).unwrap();
+    /// ```
+    #[cfg_attr(feature = "clippy", allow(cast_possible_truncation))]
+    pub fn sync(&mut self) -> Result<i32, ::HistoryError> {
+        let _lock = try!(Lock::acquire(&self.lock_path));
+
+        // Read what the other programs have appended before appending our own entries after it.
+        let mut buf = Vec::new();
+        let mut rewritten = false;
+        if let Some(md) = try!(stat(&self.path)) {
+            let id = (md.dev(), md.ino());
+            rewritten = md.len() < self.offset || (self.id.is_some() && self.id != Some(id));
+            if !rewritten && md.len() > self.offset {
+                let mut file = try!(File::open(&self.path));
+                try!(file.seek(SeekFrom::Start(self.offset)));
+                try!(file.take(md.len() - self.offset).read_to_end(&mut buf));
+            }
+        }
+
+        let added = self.added();
+        if added > 0 {
+            try!(OpenOptions::new().append(true).create(true).mode(0o600).open(&self.path));
+            try!(histfile::append(Some(&self.path), added));
+        }
+
+        let entries = parse_entries(&buf);
+        let (pos, length, base) = unsafe {
+            (listinfo::offset(), vars::history_length, vars::history_base)
+        };
+
+        for &(ref line, timestamp) in &entries {
+            try!(listmgmt::add(line));
+            if let Some(t) = timestamp {
+                try!(listmgmt::add_time(t));
+            }
+        }
+
+        if pos >= length {
+            move_::set_pos(unsafe { vars::history_length });
+        } else {
+            let dropped = unsafe { vars::history_base } - base;
+            move_::set_pos(cmp::max(pos - dropped, 0));
+        }
+
+        let seen = if rewritten {
+            try!(count_entries(&self.path))
+        } else {
+            self.seen + added + entries.len() as i32
+        };
+        try!(self.remember(seen));
+        Ok(entries.len() as i32)
+    }
+
+    /// Replace the history file with the history list, after first adding the entries other
+    /// programs have appended to the file since it was last read or written. The file is written
+    /// to a temporary file which is renamed into place.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::listmgmt;
+    /// use rl_sys::history::persist::HistoryFile;
+    /// use std::env;
+    /// use std::fs;
+    ///
+    /// let path = env::temp_dir().join("rl-sys-persist-save-doctest");
+    ///
+    /// listmgmt::clear();
+    /// assert!(listmgmt::add("cargo build").is_ok());
+    ///
+    /// let mut file = HistoryFile::new(&path);
+    /// assert!(file.save().is_ok());
+    /// assert_eq!(fs::read_to_string(&path).unwrap(), "cargo build\n");
+    /// fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn save(&mut self) -> Result<(), ::HistoryError> {
+        let _lock = try!(Lock::acquire(&self.lock_path));
+
+        let count = try!(count_entries(&self.path));
+        if count > self.seen {
+            try!(histfile::read_range(Some(&self.path), self.seen, count));
+        }
+
+        let tmp = self.temp_path();
+        try!(histfile::write(Some(&tmp)).and_then(|_| self.replace_with(&tmp)));
+
+        try!(self.remember(unsafe { vars::history_length }));
+        Ok(())
+    }
+
+    /// Truncate the history file, leaving only the last `n` entries. The truncated file is written
+    /// to a temporary file which is renamed into place.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::persist::HistoryFile;
+    /// use std::env;
+    /// use std::fs;
+    ///
+    /// let path = env::temp_dir().join("rl-sys-persist-truncate-doctest");
+    /// fs::write(&path, "one\ntwo\nthree\n").unwrap();
+    ///
+    /// let mut file = HistoryFile::new(&path);
+    /// assert!(file.truncate(1).is_ok());
+    /// assert_eq!(fs::read_to_string(&path).unwrap(), "three\n");
+    /// fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn truncate(&mut self, n: i32) -> Result<(), ::HistoryError> {
+        let _lock = try!(Lock::acquire(&self.lock_path));
+
+        // There's nothing to truncate if the file hasn't been written yet.
+        let before_len = match try!(stat(&self.path)) {
+            Some(md) => md.len(),
+            None => return Ok(()),
+        };
+        let before = try!(count_entries(&self.path));
+        let tmp = self.temp_path();
+        try!(fs::copy(&self.path, &tmp));
+        try!(histfile::truncate(Some(&tmp), n).and_then(|_| self.replace_with(&tmp)));
+
+        // The entries that were dropped were the oldest, whether or not they had been seen.
+        let after = try!(count_entries(&self.path));
+        let seen = cmp::max(self.seen - (before - after), 0);
+        let offset = self.offset;
+        try!(self.remember(seen));
+
+        // Any bytes appended since the file was last read are still at the end of it.
+        let dropped = before_len.saturating_sub(self.offset);
+        self.offset = offset.saturating_sub(dropped);
+        Ok(())
+    }
+
+    /// Record that the file has just been read or written, and now has `seen` entries that are on
+    /// the history list.
+    fn remember(&mut self, seen: i32) -> io::Result<()> {
+        let md = try!(stat(&self.path));
+        self.seen = seen;
+        self.mark = list_end();
+        self.offset = md.as_ref().map_or(0, |md| md.len());
+        self.id = md.map(|md| (md.dev(), md.ino()));
+        Ok(())
+    }
+
+    /// The number of entries added to the history list since the file was last read or written.
+    fn added(&self) -> i32 {
+        let length = unsafe { vars::history_length };
+        let added = list_end() - self.mark;
+
+        if added < 0 {
+            0
+        } else if added > length {
+            length
+        } else {
+            added
+        }
+    }
+
+    /// A temporary file in the same directory as the history file, so it can be renamed over it.
+    fn temp_path(&self) -> PathBuf {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(format!(".{}.tmp", process::id()));
+        PathBuf::from(tmp)
+    }
+
+    /// Flush `tmp` to disk and rename it over the history file. `tmp` is removed on failure.
+    fn replace_with(&self, tmp: &Path) -> Result<(), ::HistoryError> {
+        let res = File::open(tmp)
+                      .and_then(|f| f.sync_all())
+                      .and_then(|_| fs::rename(tmp, &self.path));
+
+        match res {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(tmp);
+                Err(::HistoryError::from(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use history::{listinfo, listmgmt};
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::*;
+
+    #[test]
+    fn test_count_entries() {
+        let path = env::temp_dir().join(format!("rl-sys-count-test-{}", process::id()));
+        assert_eq!(count_entries(&path).unwrap(), 0);
+
+        fs::write(&path, "one\n#1500000000\ntwo\nthree").unwrap();
+        vars::set_comment_char('#');
+        assert_eq!(count_entries(&path).unwrap(), 3);
+        vars::set_comment_char('\u{0}');
+        assert_eq!(count_entries(&path).unwrap(), 4);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_truncate_missing() {
+        let path = env::temp_dir().join(format!("rl-sys-truncate-test-{}", process::id()));
+        let mut file = HistoryFile::new(&path);
+        assert!(file.truncate(1).is_ok());
+        assert!(!path.exists());
+        fs::remove_file(&file.lock_path).unwrap();
+    }
+
+    #[test]
+    fn test_other_session() {
+        let path = env::temp_dir().join(format!("rl-sys-session-test-{}", process::id()));
+        let other = |line: &str| {
+            let mut f = OpenOptions::new().append(true).create(true).open(&path).unwrap();
+            f.write_all(line.as_bytes()).unwrap();
+        };
+        let lines = || listinfo::list().unwrap().into_iter().map(|e| e.line).collect::<Vec<_>>();
+        let _ = fs::remove_file(&path);
+
+        listmgmt::clear();
+        let mut file = HistoryFile::new(&path);
+        assert_eq!(file.load().unwrap(), 0);
+
+        assert!(listmgmt::add("mine").is_ok());
+        other("theirs\n");
+        assert_eq!(file.append().unwrap(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "theirs\nmine\n");
+        assert_eq!(lines(), vec!["mine", "theirs"]);
+
+        assert!(listmgmt::add("later").is_ok());
+        other("more\n");
+        assert!(file.save().is_ok());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "mine\ntheirs\nlater\nmore\n");
+        assert_eq!(lines(), vec!["mine", "theirs", "later", "more"]);
+
+        assert!(file.truncate(2).is_ok());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "later\nmore\n");
+        assert!(!file.temp_path().exists());
+
+        other("last\n");
+        assert_eq!(file.append().unwrap(), 1);
+        assert_eq!(lines().last().map(|l| &l[..]), Some("last"));
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&file.lock_path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        vars::set_comment_char('#');
+        let entries = parse_entries(b"one\n#1500000000\ntwo\n\nthree");
+        vars::set_comment_char('\u{0}');
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        assert_eq!(entries,
+                   vec![(String::from("one"), None),
+                        (String::from("two"), Some(time)),
+                        (String::from("three"), None)]);
+    }
+
+    #[test]
+    fn test_sync() {
+        let path = env::temp_dir().join(format!("rl-sys-sync-test-{}", process::id()));
+        let other = |line: &str| {
+            let mut f = OpenOptions::new().append(true).create(true).open(&path).unwrap();
+            f.write_all(line.as_bytes()).unwrap();
+        };
+        let _ = fs::remove_file(&path);
+
+        listmgmt::clear();
+        let mut file = HistoryFile::new(&path);
+        assert_eq!(file.sync().unwrap(), 0);
+
+        other("one\ntwo\n");
+        assert_eq!(file.sync().unwrap(), 2);
+        assert_eq!(listinfo::offset(), 2);
+
+        // The list is now in step with the file, so only new bytes are read.
+        other("three\n");
+        assert!(move_::set_pos(1));
+        assert_eq!(file.sync().unwrap(), 1);
+        assert_eq!(listinfo::current().unwrap().line, "two");
+
+        // The offset follows its entry when a stifled list drops the oldest.
+        other("four\n");
+        listmgmt::stifle(3);
+        assert_eq!(file.sync().unwrap(), 1);
+        assert_eq!(listinfo::current().unwrap().line, "two");
+        listmgmt::unstifle();
+
+        // A file replaced by another program isn't read again.
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        assert_eq!(file.sync().unwrap(), 0);
+        assert_eq!(file.seen, 3);
+        assert_eq!(listinfo::list().unwrap().len(), 3);
+
+        // Appending again picks up after the replacement.
+        other("four\n");
+        assert_eq!(file.append().unwrap(), 1);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&file.lock_path).unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! History Policies
+//!
+//! `listmgmt::add` adds every line it is given. A `HistoryPolicy` decides which lines are worth
+//! keeping first, along the lines of bash's `HISTCONTROL` and `HISTIGNORE` variables. Lines are
+//! added with `HistoryPolicy::add`, which goes through `listmgmt::add`, so stifling and
+//! `timestamps::enable` still apply.
+use history::{listinfo, listmgmt, vars};
+use history::mgmt::init;
+use std::ffi::CStr;
+
+mod ext_policy {
+    use history::HistoryEntry;
+
+    extern "C" {
+        pub fn history_list() -> *mut *mut HistoryEntry;
+    }
+}
+
+/// Rules for which lines are added to the history list. The default policy adds every line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HistoryPolicy {
+    /// Don't add a line that matches the most recent entry (bash's `ignoredups`).
+    pub ignore_dups: bool,
+    /// Remove all earlier entries that match a line before it is added (bash's `erasedups`).
+    pub erase_dups: bool,
+    /// Don't add lines that begin with a space (bash's `ignorespace`).
+    pub ignore_space: bool,
+    /// Don't add lines that match any of these glob patterns, as with bash's `HISTIGNORE`. A
+    /// pattern must match the whole line. `*` matches any string, `?` matches any character,
+    /// `[...]` matches any of the enclosed characters (or ranges, or any character not enclosed if
+    /// the first is `!` or `^`), and `\` matches the following character literally.
+    pub ignore_patterns: Vec<String>,
+    /// Don't add lines longer than this many characters.
+    pub max_line_length: Option<usize>,
+}
+
+impl HistoryPolicy {
+    /// Returns true if `line` would be added to the history list by `add`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::policy::HistoryPolicy;
+    ///
+    /// let policy = HistoryPolicy {
+    ///     ignore_space: true,
+    ///     ignore_patterns: vec![String::from("ls"), String::from("cd *")],
+    ///     max_line_length: Some(20),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert!(policy.accepts("make"));
+    /// assert!(policy.accepts("ls -al"));
+    /// assert!(!policy.accepts("ls"));
+    /// assert!(!policy.accepts("cd /tmp"));
+    /// assert!(!policy.accepts(" secret"));
+    /// assert!(!policy.accepts("echo a very long command"));
+    /// ```
+    pub fn accepts(&self, line: &str) -> bool {
+        if self.ignore_space && line.starts_with(' ') {
+            return false;
+        }
+
+        if let Some(max) = self.max_line_length {
+            if line.chars().count() > max {
+                return false;
+            }
+        }
+
+        if self.ignore_patterns.iter().any(|p| glob_match(p, line)) {
+            return false;
+        }
+
+        !(self.ignore_dups && last_line().as_ref().map(|l| &l[..]) == Some(line))
+    }
+
+    /// Add `line` to the end of the history list if the policy accepts it, first removing any
+    /// earlier copies if `erase_dups` is set. Returns whether the line was added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::{listinfo, listmgmt};
+    /// use rl_sys::history::policy::HistoryPolicy;
+    ///
+    /// let policy = HistoryPolicy {
+    ///     ignore_dups: true,
+    ///     erase_dups: true,
+    ///     ..Default::default()
+    /// };
+    ///
+    /// listmgmt::clear();
+    /// for line in &["make", "make", "make test", "make"] {
+    ///     assert!(policy.add(line).is_ok());
+    /// }
+    ///
+    /// let lines: Vec<String> = listinfo::list().unwrap().into_iter().map(|e| e.line).collect();
+    /// assert_eq!(lines, vec!["make test", "make"]);
+    /// ```
+    pub fn add(&self, line: &str) -> Result<bool, ::HistoryError> {
+        if !self.accepts(line) {
+            return Ok(false);
+        }
+
+        if self.erase_dups {
+            // Remove from the end, so the earlier offsets stay put.
+            for offset in dup_offsets(line).into_iter().rev() {
+                try!(listmgmt::remove(offset));
+            }
+        }
+
+        try!(listmgmt::add(line));
+        Ok(true)
+    }
+}
+
+/// The line of the most recent history entry.
+fn last_line() -> Option<String> {
+    let last = unsafe { vars::history_base + vars::history_length - 1 };
+    listinfo::get(last).ok().map(|entry| entry.line)
+}
+
+/// The offsets of the entries on the history list whose line is `line`.
+fn dup_offsets(line: &str) -> Vec<i32> {
+    init();
+    let mut offsets = Vec::new();
+    unsafe {
+        let list = ext_policy::history_list();
+        if !list.is_null() {
+            for i in 0.. {
+                let entry = *list.offset(i as isize);
+                if entry.is_null() {
+                    break;
+                }
+                if !(*entry).line.is_null() &&
+                   CStr::from_ptr((*entry).line).to_bytes() == line.as_bytes() {
+                    offsets.push(i);
+                }
+            }
+        }
+    }
+    offsets
+}
+
+/// Match `text` against the glob `pattern`. See `HistoryPolicy::ignore_patterns`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // The pattern after the last `*` seen, and how much of the text that `*` has matched so far.
+    let mut star = None;
+
+    loop {
+        if pattern[p..].starts_with('*') {
+            p += 1;
+            star = Some((p, t));
+            continue;
+        }
+
+        match text[t..].chars().next() {
+            Some(c) => {
+                if let Some(len) = token_match(&pattern[p..], c) {
+                    p += len;
+                    t += c.len_utf8();
+                    continue;
+                }
+            }
+            None if p == pattern.len() => return true,
+            None => {}
+        }
+
+        // On a mismatch, let the last `*` match one more character and try again from there.
+        match star {
+            Some((after, matched)) if matched < text.len() => {
+                let skip = text[matched..].chars().next().map_or(1, char::len_utf8);
+                star = Some((after, matched + skip));
+                p = after;
+                t = matched + skip;
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Match `c` against the first token of `pattern`, which is not `*`. Returns the length of the
+/// token if it matched.
+fn token_match(pattern: &str, c: char) -> Option<usize> {
+    let mut chars = pattern.chars();
+    match chars.next() {
+        None => None,
+        Some('?') => Some(1),
+        Some('[') => {
+            match class_match(&pattern[1..], c) {
+                Some((true, len)) => Some(1 + len),
+                Some((false, _)) => None,
+                // An unterminated class is matched as a literal `[`.
+                None if c == '[' => Some(1),
+                None => None,
+            }
+        }
+        Some('\\') => {
+            match chars.next() {
+                Some(escaped) if escaped == c => Some(1 + c.len_utf8()),
+                Some(_) => None,
+                None if c == '\\' => Some(1),
+                None => None,
+            }
+        }
+        Some(p) if p == c => Some(c.len_utf8()),
+        Some(_) => None,
+    }
+}
+
+/// Match `c` against the body of a `[...]` class at the start of `pattern`. Returns whether it
+/// matched and the length of the class body including the closing `]`, or `None` if the class is
+/// unterminated.
+fn class_match(pattern: &str, c: char) -> Option<(bool, usize)> {
+    let mut chars = pattern.char_indices().peekable();
+    let negated = match chars.peek() {
+        Some(&(_, '!' | '^')) => {
+            chars.next();
+            true
+        }
+        _ => false,
+    };
+    let mut matched = false;
+    let mut first = true;
+
+    while let Some((i, lo)) = chars.next() {
+        // A `]` straight after the opening bracket is part of the class.
+        if lo == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+
+        let mut ahead = chars.clone();
+        match (ahead.next(), ahead.next()) {
+            (Some((_, '-')), Some((_, hi))) if hi != ']' => {
+                matched |= lo <= c && c <= hi;
+                chars = ahead;
+            }
+            _ => matched |= lo == c,
+        }
+        first = false;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use history::{listinfo, listmgmt};
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("ls", "ls"));
+        assert!(!glob_match("ls", "ls -al"));
+        assert!(glob_match("ls*", "ls -al"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("g?t *", "git status"));
+        assert!(!glob_match("g?t", "gt"));
+        assert!(glob_match("[bf]g", "fg"));
+        assert!(!glob_match("[!bf]g", "fg"));
+        assert!(glob_match("[a-c]d", "bd"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("[x", "[x"));
+        assert!(glob_match("\\*", "*"));
+        assert!(!glob_match("\\*", "a"));
+        assert!(glob_match("*.rs", "src/lib.rs"));
+        assert!(glob_match("*a*b*c", "xaybzabc"));
+        assert!(!glob_match("*a*b*c", "xaybzab"));
+        assert!(glob_match("caf\u{e9}*", "caf\u{e9} cr\u{e8}me"));
+        assert!(glob_match("[\u{e0}-\u{ff}]?", "\u{e9}x"));
+        assert!(!glob_match(&"*a".repeat(20), &"a".repeat(19)));
+    }
+
+    #[test]
+    fn test_ignore_dups_with_stifle() {
+        let policy = HistoryPolicy { ignore_dups: true, ..Default::default() };
+
+        listmgmt::clear();
+        listmgmt::stifle(2);
+        assert_eq!(policy.add("one").unwrap(), true);
+        assert_eq!(policy.add("one").unwrap(), false);
+        assert_eq!(policy.add("two").unwrap(), true);
+        assert_eq!(policy.add("three").unwrap(), true);
+        assert_eq!(policy.add("three").unwrap(), false);
+        listmgmt::unstifle();
+
+        let lines: Vec<String> = listinfo::list().unwrap().into_iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec!["two", "three"]);
+    }
+}
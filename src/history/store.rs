@@ -0,0 +1,537 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! SQLite History Store
+//!
+//! Available with the `sqlite` cargo feature. A `HistoryStore` keeps history in an SQLite database
+//! rather than a flat file, along with each command's `format::Metadata`, so it can answer
+//! questions like "which commands are run most often in this directory" or "what was run last
+//! week".
+//!
+//! The store mirrors `listmgmt`, `search` and `histfile`. Lines added or removed through the store
+//! are added to or removed from readline's history list as well, so arrow-key navigation keeps
+//! working, and `load` fills the history list from the database at startup. Each entry's time
+//! stamp is kept to the second.
+use history::{self, Entry, listinfo, listmgmt, payload, vars};
+use history::format::{Metadata, Record};
+use rusqlite::{Connection, Row};
+use std::cell::Cell;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS history (
+                          id INTEGER PRIMARY KEY AUTOINCREMENT,
+                          line TEXT NOT NULL,
+                          timestamp INTEGER,
+                          exit_code INTEGER,
+                          cwd TEXT,
+                          duration_ms INTEGER
+                      );
+                      CREATE INDEX IF NOT EXISTS history_timestamp ON history (timestamp);
+                      CREATE INDEX IF NOT EXISTS history_cwd ON history (cwd);";
+
+/// The columns of a `Record`, in the order `record` expects them.
+const COLUMNS: &str = "line, timestamp, exit_code, cwd, duration_ms";
+
+/// A history store backed by an SQLite database.
+#[derive(Debug)]
+pub struct HistoryStore {
+    conn: Connection,
+    last_id: Cell<Option<i64>>,
+}
+
+#[cfg_attr(feature = "clippy", allow(cast_possible_wrap))]
+fn to_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+#[cfg_attr(feature = "clippy", allow(cast_possible_wrap))]
+fn to_millis(duration: Duration) -> i64 {
+    (duration.as_secs() * 1000 + u64::from(duration.subsec_millis())) as i64
+}
+
+/// A limit for a `LIMIT` clause, where -1 is no limit.
+#[cfg_attr(feature = "clippy", allow(cast_possible_wrap))]
+fn to_limit(limit: Option<usize>) -> i64 {
+    limit.map_or(-1, |n| n as i64)
+}
+
+/// An `INTEGER` column that should hold a count of seconds or milliseconds, or `None` if it is
+/// negative.
+#[cfg_attr(feature = "clippy", allow(cast_sign_loss))]
+fn unsigned(value: Option<i64>) -> Option<u64> {
+    value.and_then(|n| if n < 0 { None } else { Some(n as u64) })
+}
+
+/// Build a record from a row holding `COLUMNS`. A negative time stamp or duration, or a time stamp
+/// that `SystemTime` can't represent, is read as not set.
+fn record(row: &Row) -> ::rusqlite::Result<Record> {
+    let timestamp: Option<i64> = try!(row.get(1));
+    let cwd: Option<String> = try!(row.get(3));
+    let duration_ms: Option<i64> = try!(row.get(4));
+
+    Ok(Record {
+        line: try!(row.get(0)),
+        timestamp: unsigned(timestamp)
+                       .and_then(|s| UNIX_EPOCH.checked_add(Duration::from_secs(s))),
+        meta: Metadata {
+            exit_code: try!(row.get(2)),
+            cwd: cwd.map(PathBuf::from),
+            duration: unsigned(duration_ms).map(Duration::from_millis),
+        },
+    })
+}
+
+impl HistoryStore {
+    /// Open the store at `path`, creating the database if it does not exist.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::store::HistoryStore;
+    /// use std::env;
+    /// use std::fs;
+    ///
+    /// let path = env::temp_dir().join("rl-sys-store-doctest.db");
+    /// let store = HistoryStore::open(&path).unwrap();
+    /// assert_eq!(store.len().unwrap(), 0);
+    /// fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn open(path: &Path) -> Result<HistoryStore, ::HistoryError> {
+        HistoryStore::with_connection(try!(Connection::open(path)))
+    }
+
+    /// Open a store that is kept in memory, and lost when it is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::store::HistoryStore;
+    ///
+    /// assert!(HistoryStore::open_in_memory().is_ok());
+    /// ```
+    pub fn open_in_memory() -> Result<HistoryStore, ::HistoryError> {
+        HistoryStore::with_connection(try!(Connection::open_in_memory()))
+    }
+
+    fn with_connection(conn: Connection) -> Result<HistoryStore, ::HistoryError> {
+        try!(conn.execute_batch(SCHEMA));
+        Ok(HistoryStore {
+            conn,
+            last_id: Cell::new(None),
+        })
+    }
+
+    /// The number of entries in the store.
+    #[cfg_attr(feature = "clippy", allow(cast_sign_loss, cast_possible_truncation))]
+    pub fn len(&self) -> Result<usize, ::HistoryError> {
+        let count: i64 = try!(self.conn
+                                  .query_row("SELECT count(*) FROM history", [], |row| row.get(0)));
+        Ok(count as usize)
+    }
+
+    /// Returns true if the store has no entries.
+    pub fn is_empty(&self) -> Result<bool, ::HistoryError> {
+        self.len().map(|n| n == 0)
+    }
+
+    /// Replace the history list with the newest `limit` entries in the store, or all of them if
+    /// `limit` is `None`, along with their time stamps and metadata. This is the store's
+    /// equivalent of `histfile::read`. Returns the number of entries loaded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::{listinfo, listmgmt};
+    /// use rl_sys::history::store::HistoryStore;
+    ///
+    /// let store = HistoryStore::open_in_memory().unwrap();
+    /// for line in &["ls", "pwd", "make"] {
+    ///     assert!(store.add(line).is_ok());
+    /// }
+    ///
+    /// listmgmt::clear();
+    /// assert_eq!(store.load(Some(2)).unwrap(), 2);
+    /// let lines: Vec<String> = listinfo::list().unwrap().into_iter().map(|e| e.line).collect();
+    /// assert_eq!(lines, vec!["pwd", "make"]);
+    /// ```
+    pub fn load(&self, limit: Option<usize>) -> Result<usize, ::HistoryError> {
+        let sql = format!("SELECT {} FROM history ORDER BY id DESC LIMIT ?1", COLUMNS);
+        let mut records = try!(self.query(&sql, (to_limit(limit),)));
+        records.reverse();
+
+        listmgmt::clear();
+        for r in &records {
+            try!(history::add_record(r));
+        }
+        Ok(records.len())
+    }
+
+    /// Add `line` to the store and to the end of the history list, stamped with the current time
+    /// and directory. Use `finish` to record how the command went once it has run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::{listinfo, listmgmt};
+    /// use rl_sys::history::format::Metadata;
+    /// use rl_sys::history::store::HistoryStore;
+    /// use std::env;
+    ///
+    /// let store = HistoryStore::open_in_memory().unwrap();
+    /// listmgmt::clear();
+    /// assert!(store.add("cargo build").is_ok());
+    ///
+    /// let entry = &listinfo::list().unwrap()[0];
+    /// assert_eq!(entry.line, "cargo build");
+    /// assert_eq!(entry.get_payload::<Metadata>().unwrap().cwd, env::current_dir().ok());
+    /// ```
+    pub fn add(&self, line: &str) -> Result<(), ::HistoryError> {
+        self.add_record(&Record {
+            line: String::from(line),
+            timestamp: Some(SystemTime::now()),
+            meta: Metadata { cwd: env::current_dir().ok(), ..Default::default() },
+        })
+    }
+
+    /// Add `record` to the store and to the end of the history list, as it is. This can be used to
+    /// import a history file read with a `format::HistoryFormat`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::format::{HistoryFormat, ZshExtended};
+    /// use rl_sys::history::store::HistoryStore;
+    ///
+    /// let store = HistoryStore::open_in_memory().unwrap();
+    /// for record in ZshExtended.read(b": 1500000000:4;make\n").unwrap() {
+    ///     assert!(store.add_record(&record).is_ok());
+    /// }
+    /// assert_eq!(store.len().unwrap(), 1);
+    /// ```
+    pub fn add_record(&self, record: &Record) -> Result<(), ::HistoryError> {
+        try!(self.conn.execute(&format!("INSERT INTO history ({}) VALUES (?1, ?2, ?3, ?4, ?5)",
+                                        COLUMNS),
+                               (&record.line,
+                                record.timestamp.map(to_secs),
+                                record.meta.exit_code,
+                                record.meta.cwd.as_ref().map(|p| p.to_string_lossy().into_owned()),
+                                record.meta.duration.map(to_millis))));
+        self.last_id.set(Some(self.conn.last_insert_rowid()));
+        history::add_record(record)
+    }
+
+    /// Record the exit status and running time of the command last added with `add` or
+    /// `add_record`, in the store and in the payload of the most recent history entry. Returns an
+    /// Err if nothing has been added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::listinfo;
+    /// use rl_sys::history::format::Metadata;
+    /// use rl_sys::history::store::HistoryStore;
+    /// use std::time::Duration;
+    ///
+    /// let store = HistoryStore::open_in_memory().unwrap();
+    /// assert!(store.finish(0, Duration::from_secs(1)).is_err());
+    ///
+    /// assert!(store.add("false").is_ok());
+    /// assert!(store.finish(1, Duration::from_millis(3)).is_ok());
+    /// let entry = listinfo::list().unwrap().pop().unwrap();
+    /// assert_eq!(entry.get_payload::<Metadata>().unwrap().exit_code, Some(1));
+    /// ```
+    pub fn finish(&self, exit_code: i32, duration: Duration) -> Result<(), ::HistoryError> {
+        let id = match self.last_id.get() {
+            Some(id) => id,
+            None => return Err(::HistoryError::NothingAdded),
+        };
+        try!(self.conn.execute("UPDATE history SET exit_code = ?1, duration_ms = ?2 WHERE id = ?3",
+                               (exit_code, to_millis(duration), id)));
+
+        let newest = unsafe { vars::history_base + vars::history_length - 1 };
+        let mut meta = match listinfo::get(newest) {
+            Ok(entry) => entry.get_payload::<Metadata>().cloned().unwrap_or_default(),
+            Err(_) => Metadata::default(),
+        };
+        meta.exit_code = Some(exit_code);
+        meta.duration = Some(duration);
+        payload::set_last(meta)
+    }
+
+    /// Remove the history entry at the absolute offset `offset` from the history list, and the
+    /// newest matching entry from the store. The removed entry is returned. In the case of an
+    /// invalid offset, an Err is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::listmgmt;
+    /// use rl_sys::history::store::HistoryStore;
+    ///
+    /// let store = HistoryStore::open_in_memory().unwrap();
+    /// listmgmt::clear();
+    /// assert!(store.add("ls").is_ok());
+    /// assert!(store.add("rm -rf build").is_ok());
+    ///
+    /// assert_eq!(store.remove(1).unwrap().line, "rm -rf build");
+    /// assert_eq!(store.len().unwrap(), 1);
+    /// assert!(store.remove(1).is_err());
+    /// ```
+    pub fn remove(&self, offset: i32) -> Result<Entry, ::HistoryError> {
+        let entry = try!(listmgmt::remove(offset));
+        try!(self.conn.execute("DELETE FROM history WHERE id = (SELECT max(id) FROM history
+                                    WHERE line = ?1 AND timestamp IS ?2)",
+                               (&entry.line, entry.timestamp.map(to_secs))));
+        Ok(entry)
+    }
+
+    /// Remove every entry from the store and from the history list.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::{listinfo, vars};
+    /// use rl_sys::history::store::HistoryStore;
+    ///
+    /// let store = HistoryStore::open_in_memory().unwrap();
+    /// assert!(store.add("ls").is_ok());
+    /// assert!(store.clear().is_ok());
+    /// assert!(store.is_empty().unwrap());
+    /// assert!(listinfo::list().unwrap().is_empty());
+    /// ```
+    pub fn clear(&self) -> Result<(), ::HistoryError> {
+        try!(self.conn.execute("DELETE FROM history", []));
+        self.last_id.set(None);
+        listmgmt::clear();
+        Ok(())
+    }
+
+    /// Remove all but the newest `n` entries from the store. Like `histfile::truncate`, this does
+    /// not change the history list. Returns the number of entries removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::store::HistoryStore;
+    ///
+    /// let store = HistoryStore::open_in_memory().unwrap();
+    /// for line in &["ls", "pwd", "make"] {
+    ///     assert!(store.add(line).is_ok());
+    /// }
+    /// assert_eq!(store.truncate(1).unwrap(), 2);
+    /// assert_eq!(store.len().unwrap(), 1);
+    /// ```
+    pub fn truncate(&self, n: usize) -> Result<usize, ::HistoryError> {
+        Ok(try!(self.conn.execute("DELETE FROM history WHERE id NOT IN
+                                       (SELECT id FROM history ORDER BY id DESC LIMIT ?1)",
+                                  (to_limit(Some(n)),))))
+    }
+
+    /// Return the newest `limit` entries (or all of them if `limit` is `None`) that contain
+    /// `text`, newest first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::store::HistoryStore;
+    ///
+    /// let store = HistoryStore::open_in_memory().unwrap();
+    /// for line in &["git status", "ls", "git log -p"] {
+    ///     assert!(store.add(line).is_ok());
+    /// }
+    ///
+    /// let found = store.search("git", None).unwrap();
+    /// assert_eq!(found.len(), 2);
+    /// assert_eq!(found[0].line, "git log -p");
+    /// assert_eq!(store.search("%", None).unwrap().len(), 0);
+    /// ```
+    pub fn search(&self, text: &str, limit: Option<usize>) -> Result<Vec<Record>, ::HistoryError> {
+        let sql = format!("SELECT {} FROM history WHERE instr(line, ?1) > 0
+                           ORDER BY id DESC LIMIT ?2",
+                          COLUMNS);
+        self.query(&sql, (text, to_limit(limit)))
+    }
+
+    /// Return the newest `limit` entries (or all of them if `limit` is `None`) that begin with
+    /// `prefix`, newest first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::store::HistoryStore;
+    ///
+    /// let store = HistoryStore::open_in_memory().unwrap();
+    /// for line in &["git status", "ls", "echo git"] {
+    ///     assert!(store.add(line).is_ok());
+    /// }
+    /// assert_eq!(store.search_prefix("git", Some(5)).unwrap()[0].line, "git status");
+    /// assert_eq!(store.search_prefix("git", Some(5)).unwrap().len(), 1);
+    /// ```
+    pub fn search_prefix(&self,
+                         prefix: &str,
+                         limit: Option<usize>)
+                         -> Result<Vec<Record>, ::HistoryError> {
+        let sql = format!("SELECT {} FROM history WHERE substr(line, 1, length(?1)) = ?1
+                           ORDER BY id DESC LIMIT ?2",
+                          COLUMNS);
+        self.query(&sql, (prefix, to_limit(limit)))
+    }
+
+    /// Return the entries stamped at or after `start` and before `end`, oldest first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::store::HistoryStore;
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// let store = HistoryStore::open_in_memory().unwrap();
+    /// assert!(store.add("make").is_ok());
+    ///
+    /// let week = Duration::from_secs(7 * 24 * 60 * 60);
+    /// let now = SystemTime::now() + Duration::from_secs(1);
+    /// assert_eq!(store.between(now - week, now).unwrap().len(), 1);
+    /// assert!(store.between(now - week * 2, now - week).unwrap().is_empty());
+    /// ```
+    pub fn between(&self,
+                   start: SystemTime,
+                   end: SystemTime)
+                   -> Result<Vec<Record>, ::HistoryError> {
+        let sql = format!("SELECT {} FROM history WHERE timestamp >= ?1 AND timestamp < ?2
+                           ORDER BY id",
+                          COLUMNS);
+        self.query(&sql, (to_secs(start), to_secs(end)))
+    }
+
+    /// Return the `limit` most frequently run lines, with the number of times each was run, most
+    /// frequent first. If `cwd` is given, only commands run in that directory are counted. Ties go
+    /// to the line run most recently.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::history::format::{Metadata, Record};
+    /// use rl_sys::history::store::HistoryStore;
+    /// use std::path::{Path, PathBuf};
+    ///
+    /// let store = HistoryStore::open_in_memory().unwrap();
+    /// for &(line, dir) in &[("make", "/src"), ("ls", "/src"), ("make", "/src"), ("ls", "/tmp")] {
+    ///     let record = Record {
+    ///         line: String::from(line),
+    ///         meta: Metadata { cwd: Some(PathBuf::from(dir)), ..Default::default() },
+    ///         ..Default::default()
+    ///     };
+    ///     assert!(store.add_record(&record).is_ok());
+    /// }
+    ///
+    /// let top = store.most_frequent(Some(Path::new("/src")), 1).unwrap();
+    /// assert_eq!(top, vec![(String::from("make"), 2)]);
+    /// assert_eq!(store.most_frequent(None, 2).unwrap()[1].1, 2);
+    /// ```
+    #[cfg_attr(feature = "clippy", allow(cast_sign_loss))]
+    pub fn most_frequent(&self,
+                         cwd: Option<&Path>,
+                         limit: usize)
+                         -> Result<Vec<(String, u64)>, ::HistoryError> {
+        let mut stmt = try!(self.conn.prepare("SELECT line, count(*) AS runs FROM history
+                                               WHERE ?1 IS NULL OR cwd = ?1
+                                               GROUP BY line ORDER BY runs DESC, max(id) DESC
+                                               LIMIT ?2"));
+        let cwd = cwd.map(|p| p.to_string_lossy().into_owned());
+        let rows = try!(stmt.query_map((cwd, to_limit(Some(limit))), |row| {
+            let runs: i64 = try!(row.get(1));
+            Ok((try!(row.get(0)), runs as u64))
+        }));
+
+        let mut lines = Vec::new();
+        for row in rows {
+            lines.push(try!(row));
+        }
+        Ok(lines)
+    }
+
+    fn query<P: ::rusqlite::Params>(&self,
+                                    sql: &str,
+                                    params: P)
+                                    -> Result<Vec<Record>, ::HistoryError> {
+        let mut stmt = try!(self.conn.prepare(sql));
+        let rows = try!(stmt.query_map(params, record));
+
+        let mut records = Vec::new();
+        for row in rows {
+            records.push(try!(row));
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use history::{listinfo, listmgmt};
+    use history::format::Metadata;
+    use std::time::Duration;
+    use super::*;
+
+    fn lines() -> Vec<String> {
+        listinfo::list().unwrap().into_iter().map(|e| e.line).collect()
+    }
+
+    #[test]
+    fn test_store_mirrors_history_list() {
+        let store = HistoryStore::open_in_memory().unwrap();
+        listmgmt::clear();
+        assert!(store.add("one").is_ok());
+        assert!(store.add("two").is_ok());
+        assert!(store.finish(2, Duration::from_millis(1500)).is_ok());
+        assert!(store.add("three").is_ok());
+        assert!(store.remove(0).is_ok());
+        assert_eq!(lines(), vec!["two", "three"]);
+
+        // Reloading gives the same list, with the metadata set by finish.
+        listmgmt::clear();
+        assert_eq!(store.load(None).unwrap(), 2);
+        assert_eq!(lines(), vec!["two", "three"]);
+        let entry = &listinfo::list().unwrap()[0];
+        let meta = entry.get_payload::<Metadata>().unwrap();
+        assert_eq!(meta.exit_code, Some(2));
+        assert_eq!(meta.duration, Some(Duration::from_millis(1500)));
+        assert!(entry.timestamp.is_some());
+
+        assert!(store.clear().is_ok());
+        assert_eq!(store.load(None).unwrap(), 0);
+        assert!(lines().is_empty());
+    }
+
+    #[test]
+    fn test_out_of_range_columns() {
+        let store = HistoryStore::open_in_memory().unwrap();
+        store.conn
+             .execute_batch("INSERT INTO history (line, timestamp, duration_ms)
+                             VALUES ('neg', -1, -5), ('huge', 9223372036854775807, NULL)")
+             .unwrap();
+
+        let records = store.search("neg", None).unwrap();
+        assert_eq!(records[0].timestamp, None);
+        assert_eq!(records[0].meta.duration, None);
+        assert_eq!(store.search("huge", None).unwrap().len(), 1);
+
+        listmgmt::clear();
+        assert_eq!(store.load(None).unwrap(), 2);
+    }
+}
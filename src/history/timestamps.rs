@@ -0,0 +1,156 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! History Timestamps
+//!
+//! Readline keeps a time stamp with each history entry as a string made up of
+//! `history_comment_char` and the seconds since the epoch. The stamps are only written to the
+//! history file if `history_write_timestamps` is set, and are only recognized when the file is read
+//! back if `history_comment_char` is set to the same character. `enable` sets both, and has
+//! `listmgmt::add` stamp every new entry with the current time.
+//!
+//! The stamps themselves are available as `Entry::timestamp`.
+use history::{self, Entry, vars};
+use history::mgmt::init;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Stamp each entry added with `listmgmt::add`, and save the stamps to the history file using
+/// `comment_char` to introduce them. This should be called before any history is added or read,
+/// as existing stamps keep the comment character they were created with.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::{histfile, listinfo, listmgmt, timestamps};
+/// use std::env;
+/// use std::fs;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// timestamps::enable('#');
+/// assert!(timestamps::is_enabled());
+///
+/// let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+/// assert!(listmgmt::add("make").is_ok());
+/// assert!(listmgmt::add_time(time).is_ok());
+///
+/// let path = env::temp_dir().join("rl-sys-timestamps-doctest");
+/// assert!(histfile::write(Some(&path)).is_ok());
+/// listmgmt::clear();
+/// assert!(histfile::read(Some(&path)).is_ok());
+/// fs::remove_file(&path).unwrap();
+///
+/// let entries = listinfo::list().unwrap();
+/// assert_eq!(entries[0].line, "make");
+/// assert_eq!(entries[0].timestamp, Some(time));
+/// ```
+pub fn enable(comment_char: char) {
+    init();
+    vars::set_comment_char(comment_char);
+    vars::set_write_timestamps(1);
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Stop stamping new entries, and restore the default `history_comment_char` and
+/// `history_write_timestamps`.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::{timestamps, vars};
+///
+/// timestamps::enable('#');
+/// timestamps::disable();
+/// assert!(!timestamps::is_enabled());
+/// assert_eq!(vars::get_write_timestamps(), 0);
+/// ```
+pub fn disable() {
+    init();
+    vars::set_comment_char('\u{0}');
+    vars::set_write_timestamps(0);
+    ENABLED.store(false, Ordering::SeqCst);
+}
+
+/// Returns true if `enable` has been called, and `disable` has not been called since.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::timestamps;
+///
+/// assert!(!timestamps::is_enabled());
+/// ```
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Return copies of the entries stamped at or after `start` and before `end`, oldest first, with
+/// their logical indices. Entries without a time stamp are skipped.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::history::{listmgmt, timestamps};
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let day = Duration::from_secs(24 * 60 * 60);
+///
+/// listmgmt::clear();
+/// for (i, line) in ["ls", "pwd", "make"].iter().enumerate() {
+///     assert!(listmgmt::add(line).is_ok());
+///     assert!(listmgmt::add_time(UNIX_EPOCH + day * (i as u32 + 1)).is_ok());
+/// }
+///
+/// let found = timestamps::between(UNIX_EPOCH + day * 2, UNIX_EPOCH + day * 3);
+/// assert_eq!(found.len(), 1);
+/// assert_eq!(found[0].1.line, "pwd");
+/// ```
+pub fn between(start: SystemTime, end: SystemTime) -> Vec<(i32, Entry)> {
+    history::iter()
+        .filter(|(_, entry)| match entry.timestamp {
+            Some(t) => start <= t && t < end,
+            None => false,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use history::listmgmt;
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::*;
+
+    #[test]
+    fn test_between() {
+        let secs = |s| UNIX_EPOCH + Duration::from_secs(s);
+
+        listmgmt::clear();
+        assert!(listmgmt::add("one").is_ok());
+        assert!(listmgmt::add_time(secs(100)).is_ok());
+        assert!(listmgmt::add("two").is_ok());
+        assert!(listmgmt::add_time(secs(200)).is_ok());
+
+        let lines = |found: Vec<(i32, Entry)>| {
+            found.into_iter().map(|(_, e)| e.line).collect::<Vec<String>>()
+        };
+        assert_eq!(lines(between(secs(0), secs(1000))), vec!["one", "two"]);
+        assert_eq!(lines(between(secs(100), secs(200))), vec!["one"]);
+        assert!(between(secs(200), secs(100)).is_empty());
+    }
+}
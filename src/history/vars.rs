@@ -46,7 +46,7 @@ extern "C" {
 ///
 /// The current timestamp format uses the value of `history_comment_char` to delimit timestamp
 /// entries in the history file. If that variable does not have a value (the default),
-/// timestamps will not be written.
+/// timestamps will not be written. `history::timestamps::enable` sets both variables.
 ///
 /// # Examples
 ///
@@ -223,7 +223,7 @@ pub fn remove_word_delimiter(c: char) -> Result<(), HistoryError> {
         let ptr = history_word_delimiters;
 
         if ptr.is_null() {
-            Err(HistoryError::new("Null Pointer", "Unable to read history_word_delimiters"))
+            Err(HistoryError::NullPointer { func: String::from("history_word_delimiters") })
         } else {
             let wds = Vec::from(CStr::from_ptr(ptr).to_bytes());
             let (_, rest): (Vec<_>, Vec<_>) = wds.into_iter().partition(|&n| n == (c as u8));
@@ -250,7 +250,7 @@ pub fn get_word_delimiters() -> Result<Vec<char>, HistoryError> {
         let ptr = history_word_delimiters;
 
         if ptr.is_null() {
-            Err(HistoryError::new("Null Pointer", "Unable to read word delimiters!"))
+            Err(HistoryError::NullPointer { func: String::from("history_word_delimiters") })
         } else {
             let all_chars = try!(CStr::from_ptr(ptr).to_str());
             Ok(all_chars.chars().collect())
@@ -320,7 +320,7 @@ pub fn remove_search_delimiter_char(c: char) -> Result<(), HistoryError> {
         let ptr = history_search_delimiter_chars;
 
         if ptr.is_null() {
-            Err(HistoryError::new("Null Pointer", "Unable to read search delimiter chars"))
+            Err(HistoryError::NullPointer { func: String::from("history_search_delimiter_chars") })
         } else {
             let wds = Vec::from(CStr::from_ptr(ptr).to_bytes());
             let (_, rest): (Vec<_>, Vec<_>) = wds.into_iter().partition(|&n| n == (c as u8));
@@ -425,7 +425,7 @@ pub fn remove_no_expand_char(c: char) -> Result<(), HistoryError> {
         let ptr = history_no_expand_chars;
 
         if ptr.is_null() {
-            Err(HistoryError::new("Null Pointer", "Unable to read search delimiter chars"))
+            Err(HistoryError::NullPointer { func: String::from("history_no_expand_chars") })
         } else {
             let wds = Vec::from(CStr::from_ptr(ptr).to_bytes());
             let (_, rest): (Vec<_>, Vec<_>) = wds.into_iter().partition(|&n| n == (c as u8));
@@ -29,19 +29,20 @@
 //! # Examples
 //!
 //! ```
-//! use rl_sys::readline;
+//! use rl_sys::readline::{self, Outcome};
 //! use rl_sys::history::{listmgmt, mgmt};
 //!
 //! loop {
 //!     let input = match readline::readline("$ ") {
-//!         Ok(Some(s)) => match &*s {
+//!         Ok(Outcome::Line(s)) => match &*s {
 //!             "clear" => {
 //!                 listmgmt::clear();
 //!                 continue;
 //!             }
 //!             _ => s
 //!         },
-//!         Ok(None) => break,  // EOF encountered
+//!         Ok(Outcome::Eof) => break,
+//!         Ok(_) => continue,  // Interrupted, or not valid UTF-8
 //!         Err(e) => {
 //!             println!("{}", e);
 //!             continue;
@@ -68,9 +69,10 @@ extern crate errno;
 extern crate libc;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
 #[cfg(test)]
 extern crate sodium_sys;
-extern crate time;
 
 pub use error::{HistoryError, ReadlineError};
 pub use version::version;
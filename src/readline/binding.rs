@@ -29,7 +29,9 @@
 //! function assigned to the `rl_startup_hook` variable (see section [2.3 Readline Variables]).
 //! [2.3 readline variables]: https://goo.gl/E1D6om
 use libc::c_char;
-use readline::{CommandFunction, Keymap};
+use libc::strdup;
+use readline::{CommandFunction, keymap};
+use readline::keymap::KeymapRef;
 use self::BindType::{Func, Kmap, Macr};
 use std::ffi::CString;
 use std::path::Path;
@@ -40,21 +42,21 @@ pub type BindResult = Result<i32, ::ReadlineError>;
 
 /// Use for calls to `generic_bind`.
 #[derive(Debug, PartialEq)]
-pub enum BindType {
+pub enum BindType<'a> {
     /// Generate a function binding.
     Func(Option<CommandFunction>),
     /// Generate a keymap binding.
-    Kmap(Keymap),
+    Kmap(KeymapRef<'a>),
     /// Generate a macro binding.
     Macr(*const c_char),
 }
 
-impl From<i32> for BindType {
-    fn from(i: i32) -> BindType {
+impl<'a> From<i32> for BindType<'a> {
+    fn from(i: i32) -> BindType<'a> {
         if i == 0 {
             Func(None)
         } else if i == 1 {
-            Kmap(ptr::null_mut())
+            Kmap(unsafe { KeymapRef::from_raw(ptr::null_mut()) })
         } else if i == 2 {
             Macr(ptr::null())
         } else {
@@ -105,6 +107,16 @@ fn genresult(res: i32, err: &str) -> BindResult {
     }
 }
 
+fn bindresult<T>(res: i32, keyseq: T) -> BindResult
+    where T: ToString
+{
+    if res == 0 {
+        Ok(res)
+    } else {
+        Err(::ReadlineError::BindFailed { keyseq: keyseq.to_string() })
+    }
+}
+
 /// Binds `key` to `f` in the currently active keymap. Returns non-zero in the case of an invalid
 /// key.
 ///
@@ -131,8 +143,8 @@ fn genresult(res: i32, err: &str) -> BindResult {
 /// ```
 pub fn bind_key(key: char, f: CommandFunction) -> BindResult {
     unsafe {
-        genresult(ext_binding::rl_bind_key(key as i32, f),
-                  "Unable to bind key!")
+        bindresult(ext_binding::rl_bind_key(key as i32, f),
+                   key)
     }
 }
 
@@ -155,16 +167,18 @@ pub fn bind_key(key: char, f: CommandFunction) -> BindResult {
 ///
 /// let km = keymap::create_empty().unwrap();
 ///
-/// match binding::bind_key_in_map('\t', km, test_cmd_func) {
+/// match binding::bind_key_in_map('\t', &km, test_cmd_func) {
 ///     Ok(res) => assert!(res == 0),
 ///     Err(_)  => assert!(false),
 /// }
 /// # }
 /// ```
-pub fn bind_key_in_map(key: char, map: Keymap, f: CommandFunction) -> BindResult {
+pub fn bind_key_in_map<'a, M>(key: char, map: M, f: CommandFunction) -> BindResult
+    where M: Into<KeymapRef<'a>>
+{
     unsafe {
-        genresult(ext_binding::rl_bind_key_in_map(key as i32, f, map),
-                  "Unable to bind key in map!")
+        bindresult(ext_binding::rl_bind_key_in_map(key as i32, f, map.into().as_ptr()),
+                   key)
     }
 }
 
@@ -190,7 +204,7 @@ pub fn bind_key_in_map(key: char, map: Keymap, f: CommandFunction) -> BindResult
 ///
 /// // Create an empty keymap to ensure ';' doesn't collide on first bind.
 /// let keymap = keymap::create_empty().unwrap();
-/// keymap::set(keymap);
+/// keymap::set(&keymap);
 ///
 /// match binding::bind_key_if_unbound(';', test_cmd_func) {
 ///     Ok(res) => assert!(res == 0),
@@ -205,8 +219,8 @@ pub fn bind_key_in_map(key: char, map: Keymap, f: CommandFunction) -> BindResult
 /// ```
 pub fn bind_key_if_unbound(key: char, f: CommandFunction) -> BindResult {
     unsafe {
-        genresult(ext_binding::rl_bind_key_if_unbound(key as i32, f),
-                  "Unable to bind key!")
+        bindresult(ext_binding::rl_bind_key_if_unbound(key as i32, f),
+                   key)
     }
 }
 
@@ -230,21 +244,23 @@ pub fn bind_key_if_unbound(key: char, f: CommandFunction) -> BindResult {
 ///
 /// let km = keymap::create_empty().unwrap();
 ///
-/// match binding::bind_key_if_unbound_in_map('\t', km, test_cmd_func) {
+/// match binding::bind_key_if_unbound_in_map('\t', &km, test_cmd_func) {
 ///     Ok(res) => assert!(res == 0),
 ///     Err(_)  => assert!(false),
 /// }
 ///
-/// match binding::bind_key_if_unbound_in_map('\t', km, test_cmd_func) {
+/// match binding::bind_key_if_unbound_in_map('\t', &km, test_cmd_func) {
 ///     Ok(_)  => assert!(false),
 ///     Err(_) => assert!(true),
 /// }
 /// # }
 /// ```
-pub fn bind_key_if_unbound_in_map(key: char, map: Keymap, f: CommandFunction) -> BindResult {
+pub fn bind_key_if_unbound_in_map<'a, M>(key: char, map: M, f: CommandFunction) -> BindResult
+    where M: Into<KeymapRef<'a>>
+{
     unsafe {
-        genresult(ext_binding::rl_bind_key_if_unbound_in_map(key as i32, f, map),
-                  "Unable to bind key in map!")
+        bindresult(ext_binding::rl_bind_key_if_unbound_in_map(key as i32, f, map.into().as_ptr()),
+                   key)
     }
 }
 
@@ -270,7 +286,7 @@ pub fn bind_key_if_unbound_in_map(key: char, map: Keymap, f: CommandFunction) ->
 ///
 /// let km = keymap::create_empty().unwrap();
 ///
-/// match binding::bind_key_if_unbound_in_map('\t', km, test_cmd_func) {
+/// match binding::bind_key_if_unbound_in_map('\t', &km, test_cmd_func) {
 ///     Ok(res) => assert!(res == 0),
 ///     Err(_)  => assert!(false),
 /// }
@@ -307,20 +323,22 @@ pub fn unbind_key(key: char) -> BindResult {
 ///
 /// let km = keymap::create_empty().unwrap();
 ///
-/// match binding::bind_key_if_unbound_in_map('\t', km, test_cmd_func) {
+/// match binding::bind_key_if_unbound_in_map('\t', &km, test_cmd_func) {
 ///     Ok(res) => assert!(res == 0),
 ///     Err(_)  => assert!(false),
 /// }
 ///
-/// match binding::unbind_key_in_map('\t', km) {
+/// match binding::unbind_key_in_map('\t', &km) {
 ///     Ok(res)  => assert!(res == 0),
 ///     Err(_) => assert!(false),
 /// }
 /// # }
 /// ```
-pub fn unbind_key_in_map(key: char, map: Keymap) -> BindResult {
+pub fn unbind_key_in_map<'a, M>(key: char, map: M) -> BindResult
+    where M: Into<KeymapRef<'a>>
+{
     unsafe {
-        genresult(ext_binding::rl_unbind_key_in_map(key as i32, map),
+        genresult(ext_binding::rl_unbind_key_in_map(key as i32, map.into().as_ptr()),
                   "Unable to unbind key in map!")
     }
 }
@@ -344,19 +362,21 @@ pub fn unbind_key_in_map(key: char, map: Keymap) -> BindResult {
 ///
 /// let km = keymap::create_empty().unwrap();
 ///
-/// match binding::bind_key_if_unbound_in_map('\t', km, test_cmd_func) {
+/// match binding::bind_key_if_unbound_in_map('\t', &km, test_cmd_func) {
 ///     Ok(res) => assert!(res == 0),
 ///     Err(_)  => assert!(false),
 /// }
 ///
-/// match binding::unbind_function_in_map(test_cmd_func, km) {
+/// match binding::unbind_function_in_map(test_cmd_func, &km) {
 ///     Ok(res)  => assert!(res == 1),
 ///     Err(_) => assert!(false),
 /// }
 /// # }
 /// ```
-pub fn unbind_function_in_map(f: CommandFunction, map: Keymap) -> BindResult {
-    let res = unsafe { ext_binding::rl_unbind_function_in_map(f, map) };
+pub fn unbind_function_in_map<'a, M>(f: CommandFunction, map: M) -> BindResult
+    where M: Into<KeymapRef<'a>>
+{
+    let res = unsafe { ext_binding::rl_unbind_function_in_map(f, map.into().as_ptr()) };
     if res == 1 {
         Ok(res)
     } else {
@@ -373,16 +393,18 @@ pub fn unbind_function_in_map(f: CommandFunction, map: Keymap) -> BindResult {
 ///
 /// util::init();
 ///
-/// let km = keymap::get().unwrap();
+/// let km = unsafe { keymap::get() }.unwrap();
 ///
 /// match binding::unbind_command_in_map("kill-line", km) {
 ///     Ok(res)  => assert!(res == 1),
 ///     Err(_) => assert!(false),
 /// }
 /// ```
-pub fn unbind_command_in_map(cmd: &str, map: Keymap) -> BindResult {
+pub fn unbind_command_in_map<'a, M>(cmd: &str, map: M) -> BindResult
+    where M: Into<KeymapRef<'a>>
+{
     let cscmd = try!(CString::new(cmd));
-    let res = unsafe { ext_binding::rl_unbind_command_in_map(cscmd.as_ptr(), map) };
+    let res = unsafe { ext_binding::rl_unbind_command_in_map(cscmd.as_ptr(), map.into().as_ptr()) };
     if res == 1 {
         Ok(res)
     } else {
@@ -418,8 +440,8 @@ pub fn unbind_command_in_map(cmd: &str, map: Keymap) -> BindResult {
 pub fn bind_keyseq(keyseq: &str, f: CommandFunction) -> BindResult {
     let cskeyseq = try!(CString::new(keyseq));
     unsafe {
-        genresult(ext_binding::rl_bind_keyseq(cskeyseq.as_ptr(), f),
-                  "Unable to bind key sequence!")
+        bindresult(ext_binding::rl_bind_keyseq(cskeyseq.as_ptr(), f),
+                   keyseq)
     }
 }
 
@@ -435,7 +457,6 @@ pub fn bind_keyseq(keyseq: &str, f: CommandFunction) -> BindResult {
 /// # fn main() {
 /// use libc::c_int;
 /// use rl_sys::readline::{binding, keymap, util};
-/// use std::ptr;
 ///
 /// util::init();
 ///
@@ -443,21 +464,21 @@ pub fn bind_keyseq(keyseq: &str, f: CommandFunction) -> BindResult {
 ///   0
 /// }
 ///
-/// let km = keymap::create_empty().unwrap_or(ptr::null_mut());
-///
-/// assert!(!km.is_null());
+/// let km = keymap::create_empty().unwrap();
 ///
-/// match binding::bind_keyseq_in_map("C-z", test_cmd_func, km) {
+/// match binding::bind_keyseq_in_map("C-z", test_cmd_func, &km) {
 ///     Ok(res) => assert!(res == 0),
 ///     Err(_)  => assert!(false),
 /// }
 /// # }
 /// ```
-pub fn bind_keyseq_in_map(keyseq: &str, f: CommandFunction, map: Keymap) -> BindResult {
+pub fn bind_keyseq_in_map<'a, M>(keyseq: &str, f: CommandFunction, map: M) -> BindResult
+    where M: Into<KeymapRef<'a>>
+{
     let cskeyseq = try!(CString::new(keyseq));
     unsafe {
-        genresult(ext_binding::rl_bind_keyseq_in_map(cskeyseq.as_ptr(), f, map),
-                  "Unable to bind key sequence!")
+        bindresult(ext_binding::rl_bind_keyseq_in_map(cskeyseq.as_ptr(), f, map.into().as_ptr()),
+                   keyseq)
     }
 }
 
@@ -471,7 +492,6 @@ pub fn bind_keyseq_in_map(keyseq: &str, f: CommandFunction, map: Keymap) -> Bind
 /// # fn main() {
 /// use libc::c_int;
 /// use rl_sys::readline::{binding, keymap, util};
-/// use std::ptr;
 ///
 /// util::init();
 ///
@@ -479,21 +499,21 @@ pub fn bind_keyseq_in_map(keyseq: &str, f: CommandFunction, map: Keymap) -> Bind
 ///   0
 /// }
 ///
-/// let km = keymap::create_empty().unwrap_or(ptr::null_mut());
-///
-/// assert!(!km.is_null());
+/// let km = keymap::create_empty().unwrap();
 ///
-/// match binding::set_key("C-z", test_cmd_func, km) {
+/// match binding::set_key("C-z", test_cmd_func, &km) {
 ///     Ok(res) => assert!(res == 0),
 ///     Err(_)  => assert!(false),
 /// }
 /// # }
 /// ```
-pub fn set_key(keyseq: &str, f: CommandFunction, map: Keymap) -> BindResult {
+pub fn set_key<'a, M>(keyseq: &str, f: CommandFunction, map: M) -> BindResult
+    where M: Into<KeymapRef<'a>>
+{
     let cskeyseq = try!(CString::new(keyseq));
     unsafe {
-        genresult(ext_binding::rl_set_key(cskeyseq.as_ptr(), f, map),
-                  "Unable to bind key sequence!")
+        bindresult(ext_binding::rl_set_key(cskeyseq.as_ptr(), f, map.into().as_ptr()),
+                   keyseq)
     }
 }
 
@@ -529,8 +549,8 @@ pub fn set_key(keyseq: &str, f: CommandFunction, map: Keymap) -> BindResult {
 pub fn bind_keyseq_if_unbound(keyseq: &str, f: CommandFunction) -> BindResult {
     let cskeyseq = try!(CString::new(keyseq));
     unsafe {
-        genresult(ext_binding::rl_bind_keyseq_if_unbound(cskeyseq.as_ptr(), f),
-                  "Unable to bind key sequence!")
+        bindresult(ext_binding::rl_bind_keyseq_if_unbound(cskeyseq.as_ptr(), f),
+                   keyseq)
     }
 }
 
@@ -545,7 +565,6 @@ pub fn bind_keyseq_if_unbound(keyseq: &str, f: CommandFunction) -> BindResult {
 /// # fn main() {
 /// use libc::c_int;
 /// use rl_sys::readline::{binding, keymap, util};
-/// use std::ptr;
 ///
 /// util::init();
 ///
@@ -553,26 +572,31 @@ pub fn bind_keyseq_if_unbound(keyseq: &str, f: CommandFunction) -> BindResult {
 ///   0
 /// }
 ///
-/// let km = keymap::create_empty().unwrap_or(ptr::null_mut());
-///
-/// assert!(!km.is_null());
+/// let km = keymap::create_empty().unwrap();
 ///
-/// match binding::bind_keyseq_if_unbound_in_map("C-z", test_cmd_func, km) {
+/// match binding::bind_keyseq_if_unbound_in_map("C-z", test_cmd_func, &km) {
 ///     Ok(res) => assert!(res == 0),
 ///     Err(_)  => assert!(false),
 /// }
 ///
-/// match binding::bind_keyseq_if_unbound_in_map("C-z", test_cmd_func, km) {
+/// match binding::bind_keyseq_if_unbound_in_map("C-z", test_cmd_func, &km) {
 ///     Ok(_)  => assert!(false),
 ///     Err(_) => assert!(true),
 /// }
 /// # }
 /// ```
-pub fn bind_keyseq_if_unbound_in_map(keyseq: &str, f: CommandFunction, map: Keymap) -> BindResult {
+pub fn bind_keyseq_if_unbound_in_map<'a, M>(keyseq: &str,
+                                            f: CommandFunction,
+                                            map: M)
+                                            -> BindResult
+    where M: Into<KeymapRef<'a>>
+{
     let cskeyseq = try!(CString::new(keyseq));
     unsafe {
-        genresult(ext_binding::rl_bind_keyseq_if_unbound_in_map(cskeyseq.as_ptr(), f, map),
-                  "Unable to bind key sequence!")
+        bindresult(ext_binding::rl_bind_keyseq_if_unbound_in_map(cskeyseq.as_ptr(),
+                                                                 f,
+                                                                 map.into().as_ptr()),
+                   keyseq)
     }
 }
 
@@ -581,6 +605,9 @@ pub fn bind_keyseq_if_unbound_in_map(keyseq: &str, f: CommandFunction, map: Keym
 /// keymap (`Kmap`). This makes new keymaps as necessary. The initial keymap in which to do
 /// bindings is `map`.
 ///
+/// Readline frees the data of a binding along with `map`, so the keymap of a `Kmap` binding and the
+/// text of a `Macr` binding are copied.
+///
 /// # Examples
 ///
 /// ```rust
@@ -591,7 +618,6 @@ pub fn bind_keyseq_if_unbound_in_map(keyseq: &str, f: CommandFunction, map: Keym
 /// use rl_sys::readline::{binding, keymap, util};
 /// use rl_sys::readline::binding::BindType;
 /// use std::ffi::CString;
-/// use std::ptr;
 ///
 /// util::init();
 ///
@@ -599,33 +625,33 @@ pub fn bind_keyseq_if_unbound_in_map(keyseq: &str, f: CommandFunction, map: Keym
 ///   0
 /// }
 ///
-/// let km = keymap::create_empty().unwrap_or(ptr::null_mut());
-///
-/// assert!(!km.is_null());
+/// let km = keymap::create_empty().unwrap();
 ///
-/// match binding::generic_bind("C-z", BindType::Func(Some(test_cmd_func)), km) {
+/// match binding::generic_bind("C-z", BindType::Func(Some(test_cmd_func)), &km) {
 ///     Ok(res) => assert!(res == 0),
 ///     Err(_)  => assert!(false),
 /// }
 ///
-/// let km2 = keymap::create_empty().unwrap_or(ptr::null_mut());
-/// assert!(!km2.is_null());
+/// let km2 = keymap::create_empty().unwrap();
 ///
-/// match binding::generic_bind("C-k", BindType::Kmap(km2), km) {
+/// match binding::generic_bind("C-k", BindType::Kmap(km2.as_map()), &km) {
 ///     Ok(res) => assert!(res == 0),
 ///     Err(_)  => assert!(false),
 /// }
 ///
 /// let macr = CString::new("\\C-e | less\\C-m").unwrap();
 ///
-/// match binding::generic_bind("C-M-l", BindType::Macr(macr.as_ptr()), km) {
+/// match binding::generic_bind("C-M-l", BindType::Macr(macr.as_ptr()), &km) {
 ///     Ok(res) => assert!(res == 0),
 ///     Err(_)  => assert!(false),
 /// }
 /// # }
 /// ```
-pub fn generic_bind(keyseq: &str, bind_type: BindType, map: Keymap) -> BindResult {
+pub fn generic_bind<'a, M>(keyseq: &str, bind_type: BindType, map: M) -> BindResult
+    where M: Into<KeymapRef<'a>>
+{
     let cskeyseq = try!(CString::new(keyseq));
+    let map = map.into().as_ptr();
 
     match bind_type {
         Func(func_ptr) => {
@@ -635,17 +661,30 @@ pub fn generic_bind(keyseq: &str, bind_type: BindType, map: Keymap) -> BindResul
                 ::std::ptr::null_mut() as *mut i8
             };
             unsafe {
-                genresult(ext_binding::rl_generic_bind(0, cskeyseq.as_ptr(), fn_ptr, map),
-                          "Unable to bind to function!")
+                bindresult(ext_binding::rl_generic_bind(0, cskeyseq.as_ptr(), fn_ptr, map),
+                           keyseq)
             }
         }
-        Kmap(km) => unsafe {
-            genresult(ext_binding::rl_generic_bind(1, cskeyseq.as_ptr(), km as *mut i8, map),
-                      "Unable to bind to keymap!")
-        },
+        Kmap(km) => {
+            let copy = try!(keymap::copy(km));
+            let res = unsafe {
+                bindresult(ext_binding::rl_generic_bind(1,
+                                                        cskeyseq.as_ptr(),
+                                                        copy.as_ptr() as *mut i8,
+                                                        map),
+                           keyseq)
+            };
+            if res.is_ok() {
+                // The copy now belongs to `map`.
+                copy.into_raw();
+            }
+            res
+        }
         Macr(m) => unsafe {
-            genresult(ext_binding::rl_generic_bind(2, cskeyseq.as_ptr(), m as *mut i8, map),
-                      "Unable to bind to macro!")
+            // Readline keeps the macro text and frees it along with the keymap, so it gets a copy.
+            let m = if m.is_null() { ptr::null_mut() } else { strdup(m) };
+            bindresult(ext_binding::rl_generic_bind(2, cskeyseq.as_ptr(), m, map),
+                       keyseq)
         },
     }
 }
@@ -0,0 +1,244 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Event Loop Integration
+//!
+//! The callback interface (`readline::callback_handler_install` and friends) lets an application
+//! interleave line editing with other I/O, but its handler is a bare C function pointer that must
+//! free each line itself. A `CallbackReader` owns the handler installation instead. It delivers
+//! each completed line to a Rust closure or a channel as an `Option<String>`, where `None` means
+//! `EOF`, and removes the handler when dropped.
+//!
+//! `CallbackReader` implements `AsRawFd`, so the input descriptor can be registered with `poll`,
+//! `mio` or a similar event loop. Call `read_char` whenever it becomes readable.
+//!
+//! Readline only supports one callback handler at a time, so only one `CallbackReader` may exist.
+//! The handler closure is stored per-thread, so the reader should be created and used on a single
+//! thread.
+use libc::{self, c_char, c_void, free};
+use readline::{HandlerFunction, ffi_guard};
+use std::cell::{Cell, RefCell};
+use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+
+mod ext_callback {
+    use libc::{FILE, c_char};
+    use readline::HandlerFunction;
+
+    extern "C" {
+        pub static rl_instream: *mut FILE;
+        pub fn rl_callback_handler_install(p: *const c_char, lhandler: Option<HandlerFunction>);
+        pub fn rl_callback_read_char();
+        pub fn rl_callback_handler_remove();
+    }
+}
+
+type Handler = Box<dyn FnMut(Option<String>)>;
+
+thread_local!(static HANDLER: RefCell<Option<Handler>> = RefCell::new(None));
+// Bumped whenever a handler is installed or removed, so `line_trampoline` can tell whether to put
+// back the closure it took.
+thread_local!(static GENERATION: Cell<u64> = Cell::new(0));
+
+/// Put `handler` in the slot.
+fn replace_handler(handler: Option<Handler>) {
+    GENERATION.with(|g| g.set(g.get() + 1));
+    HANDLER.with(|h| *h.borrow_mut() = handler);
+}
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// The trampoline installed as the callback line handler.
+unsafe extern "C" fn line_trampoline(line: *mut c_char) {
+    let line = if line.is_null() {
+        None
+    } else {
+        let s = CStr::from_ptr(line).to_string_lossy().into_owned();
+        free(line as *mut c_void);
+        Some(s)
+    };
+
+    let handler = HANDLER.with(|h| h.borrow_mut().take());
+    let generation = GENERATION.with(|g| g.get());
+
+    if let Some(mut f) = handler {
+        ffi_guard((), || f(line));
+        // Unless the reader was dropped or replaced while the handler ran, put it back.
+        if GENERATION.with(|g| g.get()) == generation {
+            HANDLER.with(|h| *h.borrow_mut() = Some(f));
+        }
+    }
+}
+
+/// An installed callback line handler. See the module documentation.
+pub struct CallbackReader {
+    // The handler is thread-local, so the reader must stay on the thread that created it.
+    _marker: PhantomData<*mut ()>,
+}
+
+impl CallbackReader {
+    /// Install a callback handler with prompt `prompt` that passes each completed line to `f`.
+    /// `f` is called with `None` at `EOF`. Returns an error if a `CallbackReader` already exists.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::readline::{input, util};
+    /// use rl_sys::readline::callback::CallbackReader;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    ///
+    /// util::init();
+    ///
+    /// let mut keys = b"hello\n".to_vec().into_iter();
+    /// let _input = input::set_getc(move || keys.next());
+    ///
+    /// let lines = Rc::new(RefCell::new(Vec::new()));
+    /// let seen = lines.clone();
+    /// let reader = CallbackReader::new("> ", move |line| seen.borrow_mut().push(line)).unwrap();
+    ///
+    /// // Only one reader may be installed at a time.
+    /// assert!(CallbackReader::new("> ", |_| {}).is_err());
+    ///
+    /// while lines.borrow().is_empty() {
+    ///     reader.read_char();
+    /// }
+    /// assert_eq!(lines.borrow()[0], Some(String::from("hello")));
+    /// ```
+    pub fn new<F>(prompt: &str, f: F) -> Result<CallbackReader, ::ReadlineError>
+        where F: FnMut(Option<String>) + 'static
+    {
+        let csprompt = try!(CString::new(prompt));
+
+        if INSTALLED.swap(true, Ordering::SeqCst) {
+            return Err(::ReadlineError::new("Callback Error",
+                                            "A callback handler is already installed!"));
+        }
+
+        replace_handler(Some(Box::new(f)));
+        unsafe {
+            ext_callback::rl_callback_handler_install(csprompt.as_ptr(),
+                                                      Some(line_trampoline as HandlerFunction));
+        }
+
+        Ok(CallbackReader { _marker: PhantomData })
+    }
+
+    /// Install a callback handler with prompt `prompt` that sends each completed line down the
+    /// returned channel. `None` is sent at `EOF`. Returns an error if a `CallbackReader` already
+    /// exists.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::readline::{input, util};
+    /// use rl_sys::readline::callback::CallbackReader;
+    ///
+    /// util::init();
+    ///
+    /// let mut keys = b"one\ntwo\n".to_vec().into_iter();
+    /// let _input = input::set_getc(move || keys.next());
+    ///
+    /// let (reader, lines) = CallbackReader::with_channel("> ").unwrap();
+    /// let mut received = Vec::new();
+    ///
+    /// while received.len() < 3 {
+    ///     reader.read_char();
+    ///     received.extend(lines.try_iter());
+    /// }
+    ///
+    /// assert_eq!(received,
+    ///            vec![Some(String::from("one")), Some(String::from("two")), None]);
+    /// ```
+    pub fn with_channel(prompt: &str)
+                        -> Result<(CallbackReader, Receiver<Option<String>>), ::ReadlineError> {
+        let (tx, rx) = mpsc::channel();
+        let reader = try!(CallbackReader::new(prompt, move |line| {
+            // The receiver may have been dropped, in which case there is no one to tell.
+            let _ = tx.send(line);
+        }));
+        Ok((reader, rx))
+    }
+
+    /// Read the next character from the current input source, calling the handler if it completes
+    /// the line. Call this whenever the input descriptor is readable.
+    pub fn read_char(&self) {
+        unsafe { ext_callback::rl_callback_read_char() }
+    }
+}
+
+impl AsRawFd for CallbackReader {
+    /// The descriptor behind `rl_instream`, or standard input if it is unset.
+    fn as_raw_fd(&self) -> RawFd {
+        unsafe {
+            if ext_callback::rl_instream.is_null() {
+                libc::STDIN_FILENO
+            } else {
+                libc::fileno(ext_callback::rl_instream)
+            }
+        }
+    }
+}
+
+impl Drop for CallbackReader {
+    fn drop(&mut self) {
+        unsafe { ext_callback::rl_callback_handler_remove() };
+        replace_handler(None);
+        INSTALLED.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use readline::{capture_outstream, util};
+    use std::rc::Rc;
+    use super::*;
+
+    #[test]
+    fn test_single_instance() {
+        util::init();
+        capture_outstream(|| {
+            let reader = CallbackReader::new("", |_| {}).unwrap();
+            assert!(CallbackReader::with_channel("").is_err());
+            assert_eq!(reader.as_raw_fd(), libc::STDIN_FILENO);
+            drop(reader);
+
+            let (reader, _rx) = CallbackReader::with_channel("").unwrap();
+            drop(reader);
+        })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_handler_drops_reader() {
+        util::init();
+        capture_outstream(|| {
+            let slot = Rc::new(RefCell::new(None));
+            let inner = slot.clone();
+            let reader = CallbackReader::new("", move |_| drop(inner.borrow_mut().take())).unwrap();
+            *slot.borrow_mut() = Some(reader);
+
+            unsafe { line_trampoline(::std::ptr::null_mut()) };
+            assert!(slot.borrow().is_none());
+            assert!(HANDLER.with(|h| h.borrow().is_none()));
+        })
+            .unwrap();
+    }
+}
@@ -17,13 +17,34 @@
 
 //! [2.4.8 Character Input]
 //! [2.4.8 character input]: https://goo.gl/yNtf60
+//!
+//! `read_key`, `getc`, `stuff_char` and `execute_next` work a byte at a time, the way readline
+//! does. The `_utf8` variants read or push a complete UTF-8 sequence instead, so commands that
+//! take a key argument can handle non-ASCII input. While the continuation bytes of a sequence are
+//! being read, `RL_STATE_MOREINPUT` is set, as it is when readline reads the rest of a multi-key
+//! command.
+//!
+//! Readline can't take back bytes inserted with `rl_stuff_char()`, so when `stuff_char_utf8` or
+//! `execute_next_utf8` has to rearrange them, it drains them through `rl_read_key()`. Knowing when
+//! the buffer is empty relies on `_rl_pushed_input_available`, which isn't part of readline's
+//! documented interface. While draining, pending input and any executing macro are set aside,
+//! `RL_STATE_MACRODEF` is cleared so the drained keys aren't recorded into a keyboard macro being
+//! defined, and `rl_key_sequence_length` is restored afterwards.
+use libc::c_int;
 use readline::IOFile;
+use readline::vars::{self, RL_STATE_MACRODEF, RL_STATE_MOREINPUT};
+use std::{ptr, str};
 
 mod ext_charin {
-    use libc::c_int;
+    use libc::{c_char, c_int};
     use readline::IOFile;
 
     extern "C" {
+        pub static mut rl_pending_input: c_int;
+        pub static mut rl_executing_macro: *mut c_char;
+        pub static mut rl_key_sequence_length: c_int;
+
+        pub fn _rl_pushed_input_available() -> c_int;
         pub fn rl_read_key() -> c_int;
         pub fn rl_getc(stream: *mut IOFile) -> c_int;
         pub fn rl_stuff_char(c: c_int) -> c_int;
@@ -38,20 +59,165 @@ mod ext_charin {
 /// `rl_stuff_char()`, macros, and characters read from the keyboard. While waiting for input, this
 /// function will call any function assigned to the `rl_event_hook` variable.
 /// [2.3 readline variables]: https://goo.gl/E1D6om
+///
+/// The key is returned a byte at a time; use `read_key_utf8` to read a complete UTF-8 sequence.
 #[cfg_attr(feature = "clippy", allow(cast_possible_truncation, cast_sign_loss))]
 pub fn read_key() -> Result<char, ::ReadlineError> {
     unsafe { Ok((ext_charin::rl_read_key() as u8) as char) }
 }
 
 /// Return the next character available from `stream`, which is assumed to be the keyboard.
+///
+/// The key is returned a byte at a time; use `getc_utf8` to read a complete UTF-8 sequence.
 #[cfg_attr(feature = "clippy", allow(cast_possible_truncation, cast_sign_loss))]
 pub fn getc(stream: *mut IOFile) -> Result<char, ::ReadlineError> {
     unsafe { Ok((ext_charin::rl_getc(stream) as u8) as char) }
 }
 
+/// Return the number of bytes in a UTF-8 sequence that starts with `lead`, or `None` if `lead`
+/// can't start a sequence.
+fn utf8_len(lead: u8) -> Option<usize> {
+    match lead {
+        0x00..=0x7f => Some(1),
+        0xc2..=0xdf => Some(2),
+        0xe0..=0xef => Some(3),
+        0xf0..=0xf4 => Some(4),
+        _ => None,
+    }
+}
+
+/// Decode a UTF-8 sequence that starts with `lead`, calling `next` for each continuation byte. A
+/// byte that can't continue the sequence is handed to `unread` rather than being consumed.
+#[cfg_attr(feature = "clippy", allow(cast_possible_truncation, cast_sign_loss))]
+fn read_utf8<F, U>(lead: c_int, mut next: F, unread: U) -> Result<char, ::ReadlineError>
+    where F: FnMut() -> c_int,
+          U: FnOnce(c_int)
+{
+    if lead < 0 {
+        return Err(::ReadlineError::new("Charin Error", "End of input!"));
+    }
+
+    let mut buf = [lead as u8, 0, 0, 0];
+    let len = match utf8_len(buf[0]) {
+        Some(len) => len,
+        None => return Err(::ReadlineError::new("Charin Error", "Invalid UTF-8 lead byte!")),
+    };
+
+    if len > 1 {
+        // Leave the flag alone if our caller was already reading more input.
+        let was_set = vars::is_state(RL_STATE_MOREINPUT);
+        vars::set_state(RL_STATE_MOREINPUT);
+
+        let mut res = Ok(());
+        for b in buf.iter_mut().take(len).skip(1) {
+            let c = next();
+            if c < 0 {
+                res = Err(::ReadlineError::new("Charin Error", "Incomplete UTF-8 sequence!"));
+                break;
+            } else if c & 0xc0 != 0x80 {
+                unread(c);
+                res = Err(::ReadlineError::new("Charin Error", "Incomplete UTF-8 sequence!"));
+                break;
+            }
+            *b = c as u8;
+        }
+
+        if !was_set {
+            vars::unset_state(RL_STATE_MOREINPUT);
+        }
+        try!(res);
+    }
+
+    let s = try!(str::from_utf8(&buf[..len]));
+    s.chars().next().ok_or_else(|| ::ReadlineError::new("Charin Error", "Empty UTF-8 sequence!"))
+}
+
+/// Like `read_key`, but reads a complete UTF-8 sequence and returns it as a single `char`. If the
+/// bytes read are not valid UTF-8, an error is returned. A byte that can't continue the sequence is
+/// left as pending input, so the next `rl_read_key()` returns it; the bytes before it are consumed.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::charin;
+///
+/// assert!(charin::stuff_char_utf8('é').is_ok());
+/// assert_eq!(charin::read_key_utf8().unwrap(), 'é');
+/// ```
+pub fn read_key_utf8() -> Result<char, ::ReadlineError> {
+    unsafe {
+        read_utf8(ext_charin::rl_read_key(),
+                  || ext_charin::rl_read_key(),
+                  |c| {
+                      ext_charin::rl_execute_next(c);
+                  })
+    }
+}
+
+/// Like `getc`, but reads a complete UTF-8 sequence from `stream` and returns it as a single
+/// `char`. Invalid input is handled as in `read_key_utf8`: a byte that can't continue the sequence
+/// is left as pending input for the next `rl_read_key()`, since it can't be pushed back onto
+/// `stream`.
+///
+/// # Safety
+///
+/// `stream` must be a valid, open stream, such as `rl_instream`.
+pub unsafe fn getc_utf8(stream: *mut IOFile) -> Result<char, ::ReadlineError> {
+    read_utf8(ext_charin::rl_getc(stream),
+              || ext_charin::rl_getc(stream),
+              |c| {
+                  ext_charin::rl_execute_next(c);
+              })
+}
+
+/// Stuff each of `bytes` in turn, stopping at the first one that doesn't fit. Returns the number of
+/// bytes stuffed.
+unsafe fn stuff_bytes<I>(bytes: I) -> usize
+    where I: IntoIterator<Item = c_int>
+{
+    let mut count = 0;
+    for b in bytes {
+        if ext_charin::rl_stuff_char(b) != 1 {
+            break;
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Take every byte inserted with `rl_stuff_char()` back out of the input buffer, oldest first.
+/// Readline has no call for this, so `rl_read_key()` drains the buffer until the private
+/// `_rl_pushed_input_available()` reports it empty. Pending input and any executing macro are set
+/// aside so only stuffed bytes are read, and the macro definition flag and key sequence length are
+/// restored so the drained keys leave no trace.
+unsafe fn take_stuffed() -> Vec<c_int> {
+    let pending = ext_charin::rl_pending_input;
+    let executing = ext_charin::rl_executing_macro;
+    let seq_len = ext_charin::rl_key_sequence_length;
+    let defining = vars::is_state(RL_STATE_MACRODEF);
+    ext_charin::rl_pending_input = 0;
+    ext_charin::rl_executing_macro = ptr::null_mut();
+    vars::unset_state(RL_STATE_MACRODEF);
+
+    let mut stuffed = Vec::new();
+    while ext_charin::_rl_pushed_input_available() != 0 {
+        stuffed.push(ext_charin::rl_read_key());
+    }
+
+    if defining {
+        vars::set_state(RL_STATE_MACRODEF);
+    }
+    ext_charin::rl_key_sequence_length = seq_len;
+    ext_charin::rl_pending_input = pending;
+    ext_charin::rl_executing_macro = executing;
+    stuffed
+}
+
 /// Insert `c` into the Readline input stream. It will be "read" before Readline attempts to read
 /// characters from the terminal with `rl_read_key()`. Up to 512 characters may be pushed back.
 /// `rl_stuff_char` returns 1 if the character was successfully inserted; 0 otherwise.
+///
+/// Only the low byte of `c` is inserted; use `stuff_char_utf8` for non-ASCII characters.
 pub fn stuff_char(c: char) -> Result<i32, ::ReadlineError> {
     unsafe {
         let res = ext_charin::rl_stuff_char(c as i32);
@@ -64,9 +230,43 @@ pub fn stuff_char(c: char) -> Result<i32, ::ReadlineError> {
     }
 }
 
+/// Insert the UTF-8 encoding of `c` into the Readline input stream. Returns the number of bytes
+/// inserted. If the input buffer can't hold all of them, none are inserted and an error is
+/// returned.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::charin;
+///
+/// assert_eq!(charin::stuff_char_utf8('€').unwrap(), 3);
+/// assert_eq!(charin::read_key_utf8().unwrap(), '€');
+/// ```
+pub fn stuff_char_utf8(c: char) -> Result<i32, ::ReadlineError> {
+    let mut buf = [0; 4];
+    let bytes = c.encode_utf8(&mut buf).as_bytes();
+
+    unsafe {
+        let count = stuff_bytes(bytes.iter().map(|&b| c_int::from(b)));
+
+        if count < bytes.len() {
+            // Take back the part of the sequence that did fit.
+            let mut stuffed = take_stuffed();
+            let keep = stuffed.len() - count;
+            stuffed.truncate(keep);
+            stuff_bytes(stuffed);
+            return Err(::ReadlineError::new("Charin Error", "Unable to stuff character!"));
+        }
+    }
+
+    Ok(bytes.len() as i32)
+}
+
 /// Make `c` be the next command to be executed when `rl_read_key()` is called. This sets
 /// `rl_pending_input`.
 ///
+/// Only the low byte of `c` is used; use `execute_next_utf8` for non-ASCII characters.
+///
 /// # Examples
 ///
 /// ```
@@ -86,6 +286,44 @@ pub fn execute_next(c: char) -> Result<i32, ::ReadlineError> {
     }
 }
 
+/// Like `execute_next`, but for any `char`. `rl_pending_input` holds a single byte, so it is set to
+/// the first byte of the UTF-8 encoding of `c` and the remaining bytes are inserted into the input
+/// stream ahead of anything already inserted there, so the whole sequence is read next. If the
+/// input buffer can't hold them, nothing is changed and an error is returned.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::charin;
+///
+/// assert!(charin::execute_next_utf8('ü').is_ok());
+/// assert_eq!(charin::read_key_utf8().unwrap(), 'ü');
+/// ```
+pub fn execute_next_utf8(c: char) -> Result<i32, ::ReadlineError> {
+    let mut buf = [0; 4];
+    let bytes = c.encode_utf8(&mut buf).as_bytes();
+
+    unsafe {
+        if bytes.len() > 1 {
+            let stuffed = take_stuffed();
+            let rest = bytes[1..].iter().map(|&b| c_int::from(b));
+
+            if stuff_bytes(rest.chain(stuffed.iter().cloned())) < bytes.len() - 1 + stuffed.len() {
+                // Put the buffer back the way it was.
+                take_stuffed();
+                stuff_bytes(stuffed);
+                return Err(::ReadlineError::new("Charin Error", "Unable to stuff character!"));
+            }
+        }
+
+        if ext_charin::rl_execute_next(c_int::from(bytes[0])) != 0 {
+            return Err(::ReadlineError::new("Charin Error", "Unable to execute next character!"));
+        }
+    }
+
+    Ok(0)
+}
+
 /// Unset `rl_pending_input`, effectively negating the effect of any previous call to
 /// `rl_execute_next()`. This works only if the pending input has not already been read with
 /// `rl_read_key()`.
@@ -133,3 +371,71 @@ pub fn set_keyboard_input_timeout(us: i32) -> Result<i32, ::ReadlineError> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use readline::vars::{self, RL_STATE_MOREINPUT};
+    use super::*;
+
+    #[test]
+    fn test_read_utf8() {
+        let mut rest = vec![0x82, 0xac].into_iter();
+        let c = read_utf8(0xe2,
+                          || {
+                              assert!(vars::is_state(RL_STATE_MOREINPUT));
+                              rest.next().map_or(-1, i32::from)
+                          },
+                          |_| panic!("nothing to unread"));
+        assert_eq!(c.unwrap(), '€');
+        assert!(!vars::is_state(RL_STATE_MOREINPUT));
+
+        assert_eq!(read_utf8('a' as i32, || -1, |_| ()).unwrap(), 'a');
+        assert!(read_utf8(0xff, || 0x80, |_| ()).is_err());
+        assert!(read_utf8(0xc3, || -1, |_| ()).is_err());
+
+        let mut unread = None;
+        assert!(read_utf8(0xc3, || 'a' as i32, |c| unread = Some(c)).is_err());
+        assert_eq!(unread, Some('a' as i32));
+        assert!(!vars::is_state(RL_STATE_MOREINPUT));
+    }
+
+    #[test]
+    fn test_execute_next_utf8_order() {
+        assert!(stuff_char('x').is_ok());
+        assert!(execute_next_utf8('ü').is_ok());
+        assert_eq!(read_key_utf8().unwrap(), 'ü');
+        assert_eq!(read_key().unwrap(), 'x');
+    }
+
+    #[test]
+    fn test_take_stuffed_leaves_no_trace() {
+        assert!(stuff_char('a').is_ok());
+        assert!(stuff_char('b').is_ok());
+        vars::set_state(RL_STATE_MACRODEF);
+        let stuffed = unsafe { take_stuffed() };
+        assert!(vars::is_state(RL_STATE_MACRODEF));
+        vars::unset_state(RL_STATE_MACRODEF);
+        assert_eq!(stuffed, vec!['a' as i32, 'b' as i32]);
+        assert_eq!(unsafe { ext_charin::_rl_pushed_input_available() }, 0);
+    }
+
+    #[test]
+    fn test_stuff_char_utf8_all_or_none() {
+        let mut count = 0;
+        while stuff_char('a').is_ok() {
+            count += 1;
+        }
+        assert!(stuff_char('a').is_err());
+
+        // Make room for part of a three-byte sequence only.
+        for _ in 0..2 {
+            assert_eq!(read_key().unwrap(), 'a');
+        }
+        assert!(stuff_char_utf8('€').is_err());
+        for _ in 2..count {
+            assert_eq!(read_key().unwrap(), 'a');
+        }
+        assert!(stuff_char_utf8('€').is_ok());
+        assert_eq!(read_key_utf8().unwrap(), '€');
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Closure-backed Command Functions
+//!
+//! Readline command functions are plain `extern "C" fn(count, key)` pointers with no room for user
+//! data, so they can't capture state. This module keeps a registry of Rust closures and hands
+//! readline a generated trampoline for each one. The trampolines are ordinary `CommandFunction`s,
+//! so once a closure is registered by name it can be bound with any of the functions in
+//! `readline::binding`, looked up with `funmap::named_function`, or referred to from an inputrc
+//! file.
+//!
+//! Any panic raised by a closure is caught before it reaches readline, and the command returns
+//! non-zero as if it had failed.
+//!
+//! The trampolines and readline's funmap are shared by the whole process, but closures are not
+//! `Send`, so each command belongs to the thread that registered it. Commands should be registered
+//! on the thread that calls `readline()`: invoking a command from any other thread does nothing and
+//! returns non-zero, and a name registered on one thread can't be registered on another. There are
+//! a fixed number of trampolines (`MAX_COMMANDS`).
+use libc::c_int;
+use readline::{CommandFunction, binding, ffi_guard, funmap_cname};
+use std::cell::RefCell;
+use std::char;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+
+mod ext_command {
+    use libc::{c_char, c_int};
+    use readline::CommandFunction;
+
+    extern "C" {
+        pub fn rl_add_defun(name: *const c_char, f: CommandFunction, key: c_int) -> c_int;
+        pub fn rl_add_funmap_entry(name: *const c_char, f: CommandFunction) -> c_int;
+    }
+}
+
+/// The result type returned by registered command closures.
+pub type CommandResult = Result<(), ::ReadlineError>;
+
+type Command = Box<dyn FnMut(i32, char) -> CommandResult>;
+
+struct Slot {
+    /// The closure backing the trampoline. `None` while the closure is executing, or after the
+    /// command has been removed.
+    command: Option<Command>,
+    /// Bumped whenever the closure is replaced or removed, so `dispatch` can tell whether to put
+    /// back the closure it took.
+    generation: u64,
+}
+
+/// The name registered behind each trampoline, and the thread whose closure it runs. Slots are
+/// never freed, since readline keeps the name in its funmap.
+static OWNERS: Mutex<Vec<(String, ThreadId)>> = Mutex::new(Vec::new());
+
+// The closures registered on this thread, by trampoline slot.
+thread_local!(static REGISTRY: RefCell<HashMap<usize, Slot>> = RefCell::new(HashMap::new()));
+
+/// Look up the slot registered as `name`, and whether it belongs to the current thread.
+fn find_slot(owners: &[(String, ThreadId)], name: &str) -> Option<(usize, bool)> {
+    owners.iter()
+          .position(|&(ref n, _)| n == name)
+          .map(|slot| (slot, owners[slot].1 == thread::current().id()))
+}
+
+fn dispatch(slot: usize, count: c_int, key: c_int) -> c_int {
+    let owner = OWNERS.lock().unwrap_or_else(|e| e.into_inner()).get(slot).map(|o| o.1);
+    if owner != Some(thread::current().id()) {
+        debug!("Command slot {} was not registered on this thread", slot);
+        return 1;
+    }
+
+    // Take the closure out of its slot while it runs, so a command that re-enters readline can't
+    // trigger a second mutable borrow of the registry.
+    let cmd = REGISTRY.with(|r| {
+        r.borrow_mut().get_mut(&slot).and_then(|s| s.command.take().map(|c| (c, s.generation)))
+    });
+
+    match cmd {
+        Some((mut f, generation)) => {
+            let key = char::from_u32(key as u32).unwrap_or('\u{0}');
+            let res = ffi_guard(Err(::ReadlineError::new("Command Error", "Command panicked!")),
+                                || f(count, key));
+
+            // Unless the command was replaced or removed while it ran, put it back.
+            REGISTRY.with(|r| {
+                if let Some(s) = r.borrow_mut().get_mut(&slot) {
+                    if s.generation == generation {
+                        s.command = Some(f);
+                    }
+                }
+            });
+
+            match res {
+                Ok(()) => 0,
+                Err(e) => {
+                    debug!("{}", e);
+                    1
+                }
+            }
+        }
+        None => 1,
+    }
+}
+
+macro_rules! trampolines {
+    ($($slot:expr => $name:ident),+) => {
+        $(
+            extern "C" fn $name(count: c_int, key: c_int) -> c_int {
+                dispatch($slot, count, key)
+            }
+        )+
+
+        static TRAMPOLINES: &'static [CommandFunction] = &[$($name),+];
+    }
+}
+
+trampolines!(0 => trampoline_0, 1 => trampoline_1, 2 => trampoline_2, 3 => trampoline_3,
+             4 => trampoline_4, 5 => trampoline_5, 6 => trampoline_6, 7 => trampoline_7,
+             8 => trampoline_8, 9 => trampoline_9, 10 => trampoline_10, 11 => trampoline_11,
+             12 => trampoline_12, 13 => trampoline_13, 14 => trampoline_14, 15 => trampoline_15,
+             16 => trampoline_16, 17 => trampoline_17, 18 => trampoline_18, 19 => trampoline_19,
+             20 => trampoline_20, 21 => trampoline_21, 22 => trampoline_22, 23 => trampoline_23,
+             24 => trampoline_24, 25 => trampoline_25, 26 => trampoline_26, 27 => trampoline_27,
+             28 => trampoline_28, 29 => trampoline_29, 30 => trampoline_30, 31 => trampoline_31);
+
+/// The maximum number of closures that may be registered at once.
+pub const MAX_COMMANDS: usize = 32;
+
+/// Store `f` in the registry under `name`. Returns the trampoline and whether the name is new, and
+/// so still needs adding to the readline funmap.
+fn register(name: &str, f: Command) -> Result<(CommandFunction, bool), ::ReadlineError> {
+    let (slot, is_new) = {
+        let mut owners = OWNERS.lock().unwrap_or_else(|e| e.into_inner());
+
+        match find_slot(&owners, name) {
+            Some((slot, true)) => (slot, false),
+            Some((_, false)) => {
+                let detail = format!("{:?} was registered on another thread!", name);
+                return Err(::ReadlineError::new("Command Error", detail));
+            }
+            None if owners.len() >= TRAMPOLINES.len() => {
+                return Err(::ReadlineError::new("Command Error", "No command slots remaining!"));
+            }
+            None => {
+                owners.push((name.to_string(), thread::current().id()));
+                (owners.len() - 1, true)
+            }
+        }
+    };
+
+    REGISTRY.with(|r| {
+        let mut registry = r.borrow_mut();
+        let s = registry.entry(slot).or_insert(Slot {
+            command: None,
+            generation: 0,
+        });
+        s.command = Some(f);
+        s.generation += 1;
+    });
+    Ok((TRAMPOLINES[slot], is_new))
+}
+
+/// Register the closure `f` as the readline command `name`, and if `key` is not `None`, bind it to
+/// `key` in the currently active keymap (via `rl_add_defun`). `f` is called with the numeric
+/// argument and the key that invoked the command. Registering a name that is already registered
+/// replaces its closure. Returns the trampoline for use with the functions in `readline::binding`.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{command, modtext, util};
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+///
+/// util::init();
+///
+/// let invoked = Rc::new(Cell::new(0));
+/// let counter = invoked.clone();
+///
+/// let f = command::add_func("count-invocations", None, move |_count, _key| {
+///     counter.set(counter.get() + 1);
+///     modtext::insert_text("!").map(|_| ())
+/// }).unwrap();
+///
+/// assert_eq!(f(1, 0), 0);
+/// assert_eq!(invoked.get(), 1);
+/// ```
+pub fn add_func<F>(name: &str, key: Option<char>, f: F) -> Result<CommandFunction, ::ReadlineError>
+    where F: FnMut(i32, char) -> CommandResult + 'static
+{
+    let csname = try!(funmap_cname(name));
+    let (tramp, is_new) = try!(register(name, Box::new(f)));
+
+    match (is_new, key) {
+        (true, _) => {
+            let key = key.map_or(-1, |k| k as c_int);
+            let res = unsafe { ext_command::rl_add_defun(csname, tramp, key) };
+            if res == 0 {
+                Ok(tramp)
+            } else {
+                Err(::ReadlineError::new("Command Error", "Unable to add command!"))
+            }
+        }
+        (false, Some(k)) => binding::bind_key(k, tramp).map(|_| tramp),
+        (false, None) => Ok(tramp),
+    }
+}
+
+/// Register the closure `f` as the bindable readline command `name` (via `rl_add_funmap_entry`),
+/// without binding it to any key. Registering a name that is already registered replaces its
+/// closure.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{binding, command, funmap, util};
+///
+/// util::init();
+///
+/// let f = command::add_funmap_entry("say-hello", |_, _| Ok(())).unwrap();
+/// assert!(funmap::named_function("say-hello").is_ok());
+/// assert!(binding::bind_keyseq("\\C-xh", f).is_ok());
+/// ```
+pub fn add_funmap_entry<F>(name: &str, f: F) -> Result<CommandFunction, ::ReadlineError>
+    where F: FnMut(i32, char) -> CommandResult + 'static
+{
+    let csname = try!(funmap_cname(name));
+    let (tramp, is_new) = try!(register(name, Box::new(f)));
+
+    let res = if is_new {
+        unsafe { ext_command::rl_add_funmap_entry(csname, tramp) }
+    } else {
+        0
+    };
+
+    if res >= 0 {
+        Ok(tramp)
+    } else {
+        Err(::ReadlineError::new("Command Error", "Unable to add funmap entry!"))
+    }
+}
+
+/// Drop the closure registered as `name`. Readline has no way to remove a funmap entry, so the name
+/// stays bindable, but invoking it does nothing and returns non-zero until a new closure is
+/// registered under the same name. Returns false if `name` was never registered, or was registered
+/// on another thread.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{command, util};
+///
+/// util::init();
+///
+/// let f = command::add_funmap_entry("short-lived", |_, _| Ok(())).unwrap();
+/// assert!(command::remove("short-lived"));
+/// assert_eq!(f(1, 0), 1);
+/// ```
+pub fn remove(name: &str) -> bool {
+    let slot = match find_slot(&OWNERS.lock().unwrap_or_else(|e| e.into_inner()), name) {
+        Some((slot, true)) => slot,
+        _ => return false,
+    };
+
+    REGISTRY.with(|r| {
+        if let Some(s) = r.borrow_mut().get_mut(&slot) {
+            s.command = None;
+            s.generation += 1;
+        }
+    });
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use readline::{funmap, util};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn test_add_funmap_entry() {
+        util::init();
+        let seen = Rc::new(Cell::new((0, '\u{0}')));
+        let inner = seen.clone();
+
+        let f = add_funmap_entry("test-closure-cmd", move |count, key| {
+            inner.set((count, key));
+            Ok(())
+        })
+            .unwrap();
+
+        assert_eq!(f(4, 'x' as i32), 0);
+        assert_eq!(seen.get(), (4, 'x'));
+        assert!(funmap::named_function("test-closure-cmd").is_ok());
+
+        // Re-registering the name reuses the same trampoline.
+        let g = add_funmap_entry("test-closure-cmd", |_, _| {
+            Err(::ReadlineError::new("Test Error", "failed"))
+        })
+            .unwrap();
+        assert_eq!(f as usize, g as usize);
+        assert_eq!(g(1, 0), 1);
+    }
+
+    #[test]
+    fn test_remove_while_running() {
+        util::init();
+        let f = add_funmap_entry("test-self-removing-cmd", |_, _| {
+            assert!(remove("test-self-removing-cmd"));
+            Ok(())
+        })
+            .unwrap();
+
+        assert_eq!(f(1, 0), 0);
+        assert_eq!(f(1, 0), 1);
+    }
+
+    #[test]
+    fn test_commands_on_two_threads() {
+        util::init();
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let main_ran = ran.clone();
+        let a = add_funmap_entry("test-thread-cmd-a", move |_, _| {
+            main_ran.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+            .unwrap();
+
+        let other_ran = ran.clone();
+        let b = thread::spawn(move || {
+            let b = add_funmap_entry("test-thread-cmd-b", move |_, _| {
+                other_ran.fetch_add(10, Ordering::SeqCst);
+                Ok(())
+            })
+                .unwrap();
+
+            // A command registered on the main thread is neither run nor taken over here.
+            assert_eq!(a(1, 0), 1);
+            assert!(add_funmap_entry("test-thread-cmd-a", |_, _| Ok(())).is_err());
+            assert!(!remove("test-thread-cmd-a"));
+
+            assert_eq!(b(1, 0), 0);
+            b
+        })
+            .join()
+            .unwrap();
+        assert_eq!(ran.load(Ordering::SeqCst), 10);
+
+        assert_eq!(b(1, 0), 1);
+        assert_eq!(a(1, 0), 0);
+        assert_eq!(ran.load(Ordering::SeqCst), 11);
+    }
+
+    #[test]
+    fn test_panic_is_caught() {
+        util::init();
+        let f = add_funmap_entry("test-panicking-cmd", |_, _| panic!("boom")).unwrap();
+        assert_eq!(f(1, 0), 1);
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! [2.6 Custom Completers]
+//! [2.6 custom completers]: https://cnswww.cns.cwru.edu/php/chet/readline/readline.html
+//!
+//! Typically, a program that reads commands from the user has a way of disambiguating commands and
+//! data. If your program is one of these, then it can provide completion for commands, data, or
+//! both.
+//!
+//! Readline calls `rl_attempted_completion_function` with the text of the word being completed and
+//! the start and end offsets of that word in `rl_line_buffer`. This module lets a Rust closure fill
+//! that role. The closure returns a `Completion`, which carries the candidate matches along with
+//! the settings readline should use for `rl_attempted_completion_over`,
+//! `rl_completion_append_character` and `rl_completion_suppress_append`. The `char **` array
+//! readline expects is built with `rl_completion_matches`, so the lowest common denominator of the
+//! matches is computed by readline itself.
+//!
+//! The closure is stored per-thread, so it should be registered on the thread that calls
+//! `readline()`.
+use libc::{c_char, c_int, strdup};
+use readline::{CompletionFunction, ffi_guard};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+mod ext_completion {
+    use libc::{c_char, c_int};
+    use readline::{CompentryFunction, CompletionFunction};
+
+    extern "C" {
+        pub static mut rl_attempted_completion_function: Option<CompletionFunction>;
+        pub static mut rl_attempted_completion_over: c_int;
+        pub static mut rl_completion_append_character: c_int;
+        pub static mut rl_completion_suppress_append: c_int;
+        pub fn rl_completion_matches(text: *const c_char,
+                                     entry_func: Option<CompentryFunction>)
+                                     -> *mut *mut c_char;
+    }
+}
+
+type Completer = Box<dyn FnMut(&str, i32, i32) -> Completion>;
+
+thread_local!(static COMPLETER: RefCell<Option<Completer>> = RefCell::new(None));
+thread_local!(static MATCHES: RefCell<VecDeque<CString>> = RefCell::new(VecDeque::new()));
+
+/// The result of a completion attempt.
+///
+/// `From<Vec<String>>` is implemented, so a completer may simply return its candidates and accept
+/// the readline defaults for everything else.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+    /// The candidate matches for the word being completed.
+    pub matches: Vec<String>,
+    /// If true, readline will not fall back to its default filename completion when `matches` is
+    /// empty. Sets `rl_attempted_completion_over`.
+    pub attempted_over: bool,
+    /// The character appended after a single unique match, or `None` to append nothing. Sets
+    /// `rl_completion_append_character`. The readline default is a space.
+    pub append_character: Option<char>,
+    /// If true, no character is appended after a unique match regardless of `append_character`.
+    /// Sets `rl_completion_suppress_append`.
+    pub suppress_append: bool,
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        Completion {
+            matches: Vec::new(),
+            attempted_over: false,
+            append_character: Some(' '),
+            suppress_append: false,
+        }
+    }
+}
+
+impl From<Vec<String>> for Completion {
+    fn from(matches: Vec<String>) -> Completion {
+        Completion { matches, ..Default::default() }
+    }
+}
+
+/// Generator handed to `rl_completion_matches`. Returns the queued matches one at a time, each
+/// allocated with `malloc`, as readline frees them.
+unsafe extern "C" fn match_generator(_text: *const c_char, _state: c_int) -> *mut c_char {
+    ffi_guard(ptr::null_mut(), || {
+        MATCHES.with(|m| {
+            match m.borrow_mut().pop_front() {
+                Some(cs) => unsafe { strdup(cs.as_ptr()) },
+                None => ptr::null_mut(),
+            }
+        })
+    })
+}
+
+/// The trampoline installed as `rl_attempted_completion_function`.
+unsafe extern "C" fn attempted_completion(text: *const c_char,
+                                          start: c_int,
+                                          end: c_int)
+                                          -> *mut *mut c_char {
+    ffi_guard(ptr::null_mut(), || {
+        let text_str = unsafe { CStr::from_ptr(text).to_string_lossy().into_owned() };
+        let completion = COMPLETER.with(|c| {
+            c.borrow_mut().as_mut().map(|f| f(&text_str, start, end))
+        });
+
+        let completion = match completion {
+            Some(c) => c,
+            None => return ptr::null_mut(),
+        };
+
+        unsafe {
+            ext_completion::rl_attempted_completion_over = completion.attempted_over as c_int;
+            ext_completion::rl_completion_append_character = match completion.append_character {
+                Some(c) => c as c_int,
+                None => 0,
+            };
+            ext_completion::rl_completion_suppress_append = completion.suppress_append as c_int;
+        }
+
+        MATCHES.with(|m| {
+            let mut matches = m.borrow_mut();
+            matches.clear();
+            // Candidates with interior NULs can't be handed to readline, so they are skipped.
+            matches.extend(completion.matches.into_iter().filter_map(|s| CString::new(s).ok()));
+        });
+
+        let arr_ptr = unsafe { ext_completion::rl_completion_matches(text, Some(match_generator)) };
+        MATCHES.with(|m| m.borrow_mut().clear());
+        arr_ptr
+    })
+}
+
+/// Install `f` as the attempted completion function. `f` is called with the text of the word being
+/// completed and the `start` and `end` offsets of that word in `rl_line_buffer`. It returns either
+/// a `Vec<String>` of candidates or a `Completion` when more control is needed. Any previously
+/// installed closure is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{completion, util};
+/// use rl_sys::readline::completion::Completion;
+///
+/// util::init();
+///
+/// let commands = vec!["help", "history", "exit"];
+///
+/// completion::set_attempted_completion_function(move |text, _start, _end| {
+///     Completion {
+///         matches: commands.iter()
+///                          .filter(|c| c.starts_with(text))
+///                          .map(|c| c.to_string())
+///                          .collect(),
+///         attempted_over: true,
+///         ..Default::default()
+///     }
+/// });
+///
+/// completion::clear_attempted_completion_function();
+/// ```
+pub fn set_attempted_completion_function<F, R>(mut f: F)
+    where F: FnMut(&str, i32, i32) -> R + 'static,
+          R: Into<Completion>
+{
+    let completer: Completer = Box::new(move |text, start, end| f(text, start, end).into());
+    COMPLETER.with(|c| *c.borrow_mut() = Some(completer));
+    unsafe {
+        ext_completion::rl_attempted_completion_function =
+            Some(attempted_completion as CompletionFunction);
+    }
+}
+
+/// Remove the attempted completion function installed by `set_attempted_completion_function`, so
+/// readline goes back to its default completion behavior.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::completion;
+///
+/// completion::set_attempted_completion_function(|_, _, _| vec![String::from("exit")]);
+/// completion::clear_attempted_completion_function();
+/// ```
+pub fn clear_attempted_completion_function() {
+    unsafe {
+        ext_completion::rl_attempted_completion_function = None;
+    }
+    COMPLETER.with(|c| *c.borrow_mut() = None);
+}
+
+#[cfg(test)]
+mod test {
+    use libc::{c_void, free};
+    use readline::util;
+    use std::ffi::{CStr, CString};
+    use super::*;
+    use super::{attempted_completion, ext_completion};
+
+    fn collect_matches(text: &str) -> Vec<String> {
+        let cstext = CString::new(text).unwrap();
+        let mut res = Vec::new();
+        unsafe {
+            let arr_ptr = attempted_completion(cstext.as_ptr(), 0, text.len() as i32);
+            if arr_ptr.is_null() {
+                return res;
+            }
+            for i in 0.. {
+                let entry_ptr = *arr_ptr.offset(i);
+                if entry_ptr.is_null() {
+                    break;
+                }
+                res.push(CStr::from_ptr(entry_ptr).to_string_lossy().into_owned());
+                free(entry_ptr as *mut c_void);
+            }
+            free(arr_ptr as *mut c_void);
+        }
+        res
+    }
+
+    #[test]
+    fn test_attempted_completion() {
+        util::init();
+        set_attempted_completion_function(|text, _, _| {
+            vec!["alpha", "albert", "beta"]
+                .into_iter()
+                .filter(|c| c.starts_with(text))
+                .map(String::from)
+                .collect::<Vec<String>>()
+        });
+
+        // Element 0 is the lowest common denominator of the matches.
+        assert_eq!(collect_matches("al"), vec!["al", "alpha", "albert"]);
+        // A unique match is substituted directly.
+        assert_eq!(collect_matches("b"), vec!["beta"]);
+        assert!(collect_matches("z").is_empty());
+        clear_attempted_completion_function();
+    }
+
+    #[test]
+    fn test_completion_settings() {
+        util::init();
+        set_attempted_completion_function(|_, _, _| {
+            Completion {
+                matches: Vec::new(),
+                attempted_over: true,
+                append_character: Some('/'),
+                suppress_append: true,
+            }
+        });
+
+        assert!(collect_matches("x").is_empty());
+        unsafe {
+            assert_eq!(ext_completion::rl_attempted_completion_over, 1);
+            assert_eq!(ext_completion::rl_completion_append_character, '/' as i32);
+            assert_eq!(ext_completion::rl_completion_suppress_append, 1);
+            ext_completion::rl_attempted_completion_over = 0;
+            ext_completion::rl_completion_append_character = ' ' as i32;
+            ext_completion::rl_completion_suppress_append = 0;
+        }
+        clear_attempted_completion_function();
+    }
+}
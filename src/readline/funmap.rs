@@ -21,9 +21,12 @@
 //! These functions allow you to find out what keys invoke named functions and the functions invoked
 //! by a particular key sequence. You may also associate a new function name with an arbitrary
 //! function.
-use readline::{CommandFunction, Keymap};
+use readline::{CommandFunction, funmap_cname};
 use readline::binding::BindType;
+use libc::{c_void, free};
+use readline::keymap::KeymapRef;
 use std::ffi::{CStr, CString};
+use std::fmt;
 use std::ptr;
 
 mod ext_funmap {
@@ -40,26 +43,33 @@ mod ext_funmap {
         pub fn rl_invoking_keyseqs_in_map(f: CommandFunction, map: Keymap) -> *mut *mut c_char;
         pub fn rl_function_dumper(readable: c_int) -> ();
         pub fn rl_list_funmap_names() -> ();
+        pub fn rl_funmap_names() -> *mut *const c_char;
         pub fn rl_add_funmap_entry(name: *const c_char, f: CommandFunction) -> c_int;
     }
 }
 
-/// Return the function with name `name`.
+/// Return the function with name `name`, or `ReadlineError::UnknownFunction` if there is none.
 ///
 /// # Examples
 ///
 /// ```
+/// use rl_sys::ReadlineError;
 /// use rl_sys::readline::{funmap, util};
 ///
 /// util::init();
 ///
-/// assert!(funmap::named_function("self-insert").is_ok())
+/// assert!(funmap::named_function("self-insert").is_ok());
+///
+/// match funmap::named_function("no-such-function") {
+///     Err(ReadlineError::UnknownFunction { name }) => assert_eq!(name, "no-such-function"),
+///     _ => panic!("expected an unknown function"),
+/// }
 /// ```
 pub fn named_function(name: &str) -> Result<CommandFunction, ::ReadlineError> {
     let csname = try!(CString::new(name));
     let func_ptr = unsafe { ext_funmap::rl_named_function(csname.as_ptr()) };
     if func_ptr.is_none() {
-        Err(::ReadlineError::new("Funmap Error", "Unable to find name function!"))
+        Err(::ReadlineError::UnknownFunction { name: name.to_string() })
     } else {
         Ok(func_ptr.expect("Unable to get function pointer"))
     }
@@ -85,13 +95,13 @@ pub fn named_function(name: &str) -> Result<CommandFunction, ::ReadlineError> {
 /// ```
 pub fn function_of_keyseq
     (keyseq: &str,
-     map: Option<Keymap>,
+     map: Option<KeymapRef>,
      add_type: bool)
-     -> Result<(Option<CommandFunction>, Option<BindType>), ::ReadlineError> {
+     -> Result<(Option<CommandFunction>, Option<BindType<'static>>), ::ReadlineError> {
 
     let cskeyseq = try!(CString::new(keyseq));
     let km = match map {
-        Some(km) => km,
+        Some(km) => km.as_ptr(),
         None => ptr::null_mut(),
     };
     let bind_type: *mut i32 = if add_type { &mut 1 } else { ptr::null_mut() };
@@ -152,15 +162,17 @@ pub fn invoking_keyseqs(f: CommandFunction) -> Result<Vec<String>, ::ReadlineErr
 /// util::init();
 ///
 /// let cmd = funmap::named_function("self-insert").unwrap();
-/// let km = keymap::get().unwrap();
+/// let km = unsafe { keymap::get() }.unwrap();
 /// let names = funmap::invoking_keyseqs_in_map(cmd, km).unwrap();
 /// assert!(names.len() > 0);
 /// ```
-pub fn invoking_keyseqs_in_map(f: CommandFunction,
-                               map: Keymap)
-                               -> Result<Vec<String>, ::ReadlineError> {
+pub fn invoking_keyseqs_in_map<'a, M>(f: CommandFunction,
+                                      map: M)
+                                      -> Result<Vec<String>, ::ReadlineError>
+    where M: Into<KeymapRef<'a>>
+{
     unsafe {
-        let arr_ptr = ext_funmap::rl_invoking_keyseqs_in_map(f, map);
+        let arr_ptr = ext_funmap::rl_invoking_keyseqs_in_map(f, map.into().as_ptr());
 
         if arr_ptr.is_null() {
             Err(::ReadlineError::new("Funmap Error", "Unable to find invoking key seqs in map!"))
@@ -183,6 +195,8 @@ pub fn invoking_keyseqs_in_map(f: CommandFunction,
 /// `rl_outstream`. If `readable` is true, the list is formatted in such a way that it can be made
 /// part of an `inputrc` file and re-read.
 ///
+/// To get the output as data rather than printing it, use `function_bindings`.
+///
 /// # Examples
 ///
 /// ```
@@ -199,6 +213,8 @@ pub fn function_dumper(readable: bool) -> () {
 
 /// Print the names of all bindable Readline functions to `rl_outstream`.
 ///
+/// To get the list as data rather than printing it, use `funmap_names`.
+///
 /// # Examples
 ///
 /// ```
@@ -212,6 +228,100 @@ pub fn list_funmap_names() -> () {
     unsafe { ext_funmap::rl_list_funmap_names() }
 }
 
+/// Return the names of all bindable Readline functions, in alphabetical order. This is the list
+/// `list_funmap_names` prints.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{funmap, util};
+///
+/// util::init();
+///
+/// let names = funmap::funmap_names().unwrap();
+/// assert!(names.iter().any(|n| n == "self-insert"));
+/// ```
+pub fn funmap_names() -> Result<Vec<String>, ::ReadlineError> {
+    unsafe {
+        let arr_ptr = ext_funmap::rl_funmap_names();
+
+        if arr_ptr.is_null() {
+            Err(::ReadlineError::NullPointer { func: String::from("rl_funmap_names") })
+        } else {
+            let mut names = Vec::new();
+            for i in 0.. {
+                let name_ptr = *arr_ptr.offset(i);
+                if name_ptr.is_null() {
+                    break;
+                } else {
+                    names.push(CStr::from_ptr(name_ptr).to_string_lossy().into_owned());
+                }
+            }
+            // The array is allocated for us, but the names belong to the funmap.
+            free(arr_ptr as *mut c_void);
+            Ok(names)
+        }
+    }
+}
+
+/// A bindable function and the key sequences that invoke it in the current keymap.
+///
+/// `Display` formats the binding as `inputrc` lines, one per key sequence, or as a comment if the
+/// function is not bound.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionBinding {
+    /// The function name, as used in the funmap.
+    pub name: String,
+    /// The key sequences bound to the function, in `inputrc` syntax.
+    pub keyseqs: Vec<String>,
+}
+
+impl fmt::Display for FunctionBinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.keyseqs.is_empty() {
+            return write!(f, "# {} (not bound)", self.name);
+        }
+
+        for (i, keyseq) in self.keyseqs.iter().enumerate() {
+            if i > 0 {
+                try!(writeln!(f));
+            }
+            try!(write!(f, "\"{}\": {}", keyseq, self.name));
+        }
+        Ok(())
+    }
+}
+
+/// Return every bindable function along with the key sequences bound to it in the current keymap.
+/// This is the data `function_dumper` prints.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{funmap, util};
+///
+/// util::init();
+///
+/// let bindings = funmap::function_bindings().unwrap();
+/// let bol = bindings.iter().find(|b| b.name == "beginning-of-line").unwrap();
+/// assert!(bol.keyseqs.iter().any(|k| k == "\\C-a"));
+/// assert!(bol.to_string().contains("\"\\C-a\": beginning-of-line"));
+/// ```
+pub fn function_bindings() -> Result<Vec<FunctionBinding>, ::ReadlineError> {
+    let names = try!(funmap_names());
+    let mut bindings = Vec::with_capacity(names.len());
+
+    for name in names {
+        let keyseqs = match named_function(&name) {
+            Ok(f) => invoking_keyseqs(f).unwrap_or_default(),
+            Err(_) => continue,
+        };
+        bindings.push(FunctionBinding { name, keyseqs });
+    }
+
+    Ok(bindings)
+}
+
 /// Add `name` to the list of bindable Readline command names, and make function `f` the function to
 /// be called when `name` is invoked.
 ///
@@ -237,8 +347,8 @@ pub fn list_funmap_names() -> () {
 /// # }
 /// ```
 pub fn add_funmap_entry(name: &str, cmd: CommandFunction) -> Result<i32, ::ReadlineError> {
-    let csname = try!(CString::new(name));
-    let res = unsafe { ext_funmap::rl_add_funmap_entry(csname.as_ptr(), cmd) };
+    let csname = try!(funmap_cname(name));
+    let res = unsafe { ext_funmap::rl_add_funmap_entry(csname, cmd) };
     if res >= 0 {
         Ok(res)
     } else {
@@ -252,6 +362,13 @@ mod test {
     use readline::binding::BindType;
     use super::*;
 
+    #[test]
+    fn test_funmap_cname_reused() {
+        let first = funmap_cname("test-kept-name").unwrap();
+        assert_eq!(funmap_cname("test-kept-name").unwrap(), first);
+        assert!(funmap_cname("test\0name").is_err());
+    }
+
     // #[test]
     // fn test_function_dumper() {
     //     util::init();
@@ -264,6 +381,21 @@ mod test {
     //     list_funmap_names();
     // }
 
+    #[test]
+    fn test_function_binding_display() {
+        let bound = FunctionBinding {
+            name: String::from("kill-line"),
+            keyseqs: vec![String::from("\\C-k"), String::from("\\e[F")],
+        };
+        assert_eq!(bound.to_string(), "\"\\C-k\": kill-line\n\"\\e[F\": kill-line");
+
+        let unbound = FunctionBinding {
+            name: String::from("kill-line"),
+            keyseqs: Vec::new(),
+        };
+        assert_eq!(unbound.to_string(), "# kill-line (not bound)");
+    }
+
     #[test]
     fn test_function_of_keyseq() {
         util::init();
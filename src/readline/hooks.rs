@@ -0,0 +1,334 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Closure-backed Hooks
+//!
+//! Readline calls `rl_startup_hook`, `rl_pre_input_hook`, `rl_event_hook`, `rl_signal_event_hook`
+//! and `rl_input_available_hook` at various points while reading a line (see section
+//! [2.3 Readline Variables]). The functions in this module install a Rust closure as one of these
+//! hooks, and return a `HookGuard` that puts the previous hook back when it is dropped. Guards for
+//! the same hook should be dropped in the reverse order they were created.
+//! [2.3 readline variables]: https://goo.gl/E1D6om
+//!
+//! Any panic raised by a closure is caught before it reaches readline.
+//!
+//! The closures are stored per-thread, so hooks should be installed on the thread that calls
+//! `readline()`.
+use libc::c_int;
+use readline::{HookFunction, ffi_guard};
+use std::cell::{Cell, RefCell};
+use std::mem;
+
+mod ext_hooks {
+    use readline::HookFunction;
+
+    extern "C" {
+        pub static mut rl_startup_hook: Option<HookFunction>;
+        pub static mut rl_pre_input_hook: Option<HookFunction>;
+        pub static mut rl_event_hook: Option<HookFunction>;
+        pub static mut rl_signal_event_hook: Option<HookFunction>;
+        pub static mut rl_input_available_hook: Option<HookFunction>;
+    }
+}
+
+/// The result type returned by hook closures. An error is logged, but otherwise ignored, as
+/// readline ignores the value returned by these hooks.
+pub type HookResult = Result<(), ::ReadlineError>;
+
+type Hook = Box<dyn FnMut() -> c_int>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Startup,
+    PreInput,
+    Event,
+    SignalEvent,
+    InputAvailable,
+}
+
+thread_local!(static HOOKS: RefCell<[Option<Hook>; 5]> = RefCell::new([None, None, None, None,
+                                                                        None]));
+// Bumped whenever a hook is installed or restored, so `dispatch` can tell whether to put back the
+// closure it took.
+thread_local!(static GENERATIONS: Cell<[u64; 5]> = Cell::new([0; 5]));
+
+/// Put `hook` in the slot for `kind`, returning the closure it replaces.
+fn replace_hook(kind: Kind, hook: Option<Hook>) -> Option<Hook> {
+    GENERATIONS.with(|g| {
+        let mut generations = g.get();
+        generations[kind as usize] += 1;
+        g.set(generations);
+    });
+    HOOKS.with(|h| mem::replace(&mut h.borrow_mut()[kind as usize], hook))
+}
+
+fn get_var(kind: Kind) -> Option<HookFunction> {
+    unsafe {
+        match kind {
+            Kind::Startup => ext_hooks::rl_startup_hook,
+            Kind::PreInput => ext_hooks::rl_pre_input_hook,
+            Kind::Event => ext_hooks::rl_event_hook,
+            Kind::SignalEvent => ext_hooks::rl_signal_event_hook,
+            Kind::InputAvailable => ext_hooks::rl_input_available_hook,
+        }
+    }
+}
+
+fn set_var(kind: Kind, f: Option<HookFunction>) {
+    unsafe {
+        match kind {
+            Kind::Startup => ext_hooks::rl_startup_hook = f,
+            Kind::PreInput => ext_hooks::rl_pre_input_hook = f,
+            Kind::Event => ext_hooks::rl_event_hook = f,
+            Kind::SignalEvent => ext_hooks::rl_signal_event_hook = f,
+            Kind::InputAvailable => ext_hooks::rl_input_available_hook = f,
+        }
+    }
+}
+
+fn dispatch(kind: Kind) -> c_int {
+    // Take the closure out of its slot while it runs, so a hook that re-enters readline can't
+    // trigger a second mutable borrow.
+    let hook = HOOKS.with(|h| h.borrow_mut()[kind as usize].take());
+    let generation = GENERATIONS.with(|g| g.get()[kind as usize]);
+
+    match hook {
+        Some(mut f) => {
+            let res = ffi_guard(0, || f());
+            // Unless the hook was replaced or restored while it ran, put it back.
+            if GENERATIONS.with(|g| g.get()[kind as usize]) == generation {
+                HOOKS.with(|h| h.borrow_mut()[kind as usize] = Some(f));
+            }
+            res
+        }
+        None => 0,
+    }
+}
+
+extern "C" fn startup_trampoline() -> c_int {
+    dispatch(Kind::Startup)
+}
+
+extern "C" fn pre_input_trampoline() -> c_int {
+    dispatch(Kind::PreInput)
+}
+
+extern "C" fn event_trampoline() -> c_int {
+    dispatch(Kind::Event)
+}
+
+extern "C" fn signal_event_trampoline() -> c_int {
+    dispatch(Kind::SignalEvent)
+}
+
+extern "C" fn input_available_trampoline() -> c_int {
+    dispatch(Kind::InputAvailable)
+}
+
+/// Restores the hook that was installed before the guard's closure when dropped.
+#[must_use]
+pub struct HookGuard {
+    kind: Kind,
+    previous_fn: Option<HookFunction>,
+    previous: Option<Hook>,
+}
+
+impl Drop for HookGuard {
+    fn drop(&mut self) {
+        set_var(self.kind, self.previous_fn);
+        let previous = self.previous.take();
+        drop(replace_hook(self.kind, previous));
+    }
+}
+
+fn install(kind: Kind, trampoline: HookFunction, hook: Hook) -> HookGuard {
+    let previous = replace_hook(kind, Some(hook));
+    let previous_fn = get_var(kind);
+    set_var(kind, Some(trampoline));
+
+    HookGuard {
+        kind,
+        previous_fn,
+        previous,
+    }
+}
+
+fn wrap<F>(mut f: F) -> Hook
+    where F: FnMut() -> HookResult + 'static
+{
+    Box::new(move || {
+        match f() {
+            Ok(()) => 0,
+            Err(e) => {
+                debug!("{}", e);
+                -1
+            }
+        }
+    })
+}
+
+/// Install `f` as `rl_startup_hook`, called just before `readline()` prints the first prompt.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{hooks, misc};
+///
+/// let _guard = hooks::set_startup_hook(|| misc::variable_bind("bell-style", "none").map(|_| ()));
+/// ```
+pub fn set_startup_hook<F>(f: F) -> HookGuard
+    where F: FnMut() -> HookResult + 'static
+{
+    install(Kind::Startup, startup_trampoline, wrap(f))
+}
+
+/// Install `f` as `rl_pre_input_hook`, called after the first prompt has been printed and just
+/// before `readline()` starts reading input characters.
+///
+/// # Examples
+///
+/// Pre-fill the line with the previous command so it can be edited:
+///
+/// ```
+/// use rl_sys::readline::{hooks, modtext};
+///
+/// let previous = String::from("ls -l");
+/// let guard = hooks::set_pre_input_hook(move || modtext::insert_text(&previous).map(|_| ()));
+///
+/// // let line = readline::readline("$ ");
+///
+/// drop(guard);
+/// ```
+pub fn set_pre_input_hook<F>(f: F) -> HookGuard
+    where F: FnMut() -> HookResult + 'static
+{
+    install(Kind::PreInput, pre_input_trampoline, wrap(f))
+}
+
+/// Install `f` as `rl_event_hook`, called periodically while readline is waiting for terminal
+/// input. By default, this will be called at most ten times a second if there is no keyboard
+/// input (see `charin::set_keyboard_input_timeout`).
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::hooks;
+///
+/// let _guard = hooks::set_event_hook(|| {
+///     // Poll for background work here.
+///     Ok(())
+/// });
+/// ```
+pub fn set_event_hook<F>(f: F) -> HookGuard
+    where F: FnMut() -> HookResult + 'static
+{
+    install(Kind::Event, event_trampoline, wrap(f))
+}
+
+/// Install `f` as `rl_signal_event_hook`, called if a read system call is interrupted while
+/// readline is reading terminal input.
+pub fn set_signal_event_hook<F>(f: F) -> HookGuard
+    where F: FnMut() -> HookResult + 'static
+{
+    install(Kind::SignalEvent, signal_event_trampoline, wrap(f))
+}
+
+/// Install `f` as `rl_input_available_hook`. `f` should return true if there is input available
+/// on the current input source. An application that reads input through `rl_getc_function` should
+/// generally set this as well.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::hooks;
+///
+/// let _guard = hooks::set_input_available_hook(|| false);
+/// ```
+pub fn set_input_available_hook<F>(mut f: F) -> HookGuard
+    where F: FnMut() -> bool + 'static
+{
+    install(Kind::InputAvailable,
+            input_available_trampoline,
+            Box::new(move || f() as c_int))
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use super::*;
+    use super::ext_hooks;
+
+    fn call_pre_input() -> i32 {
+        unsafe { ext_hooks::rl_pre_input_hook.map_or(0, |f| f()) }
+    }
+
+    #[test]
+    fn test_guard_restores_previous() {
+        let calls = Rc::new(Cell::new(0));
+        let outer_calls = calls.clone();
+        let inner_calls = calls.clone();
+
+        let outer = set_pre_input_hook(move || {
+            outer_calls.set(outer_calls.get() + 1);
+            Ok(())
+        });
+        call_pre_input();
+        assert_eq!(calls.get(), 1);
+
+        {
+            let _inner = set_pre_input_hook(move || {
+                inner_calls.set(inner_calls.get() + 10);
+                Err(::ReadlineError::new("Test Error", "failed"))
+            });
+            assert_eq!(call_pre_input(), -1);
+            assert_eq!(calls.get(), 11);
+        }
+
+        assert_eq!(call_pre_input(), 0);
+        assert_eq!(calls.get(), 12);
+
+        drop(outer);
+        unsafe { assert!(ext_hooks::rl_pre_input_hook.is_none()) };
+    }
+
+    #[test]
+    fn test_guard_dropped_while_running() {
+        let slot: Rc<RefCell<Option<HookGuard>>> = Rc::new(RefCell::new(None));
+        let inner = slot.clone();
+        *slot.borrow_mut() = Some(set_startup_hook(move || {
+            drop(inner.borrow_mut().take());
+            Ok(())
+        }));
+
+        assert_eq!(dispatch(Kind::Startup), 0);
+        assert!(HOOKS.with(|h| h.borrow()[Kind::Startup as usize].is_none()));
+        unsafe { assert!(ext_hooks::rl_startup_hook.is_none()) };
+    }
+
+    #[test]
+    fn test_panic_is_caught() {
+        let _guard = set_event_hook(|| panic!("boom"));
+        unsafe { assert_eq!(ext_hooks::rl_event_hook.map(|f| f()), Some(0)) };
+    }
+
+    #[test]
+    fn test_input_available() {
+        let _guard = set_input_available_hook(|| true);
+        unsafe { assert_eq!(ext_hooks::rl_input_available_hook.map(|f| f()), Some(1)) };
+    }
+}
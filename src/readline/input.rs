@@ -0,0 +1,211 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Custom Input Sources
+//!
+//! Readline gets each character of input by calling `rl_getc_function`, which by default reads
+//! from `rl_instream`. The functions in this module install a Rust closure or `Read` in its place,
+//! so readline can be run over a socket, a pty master, or a scripted sequence of keystrokes in a
+//! test. The returned `InputGuard` restores the previous input function when dropped.
+//!
+//! Input is still echoed to `rl_outstream`.
+//!
+//! The input source is stored per-thread, so it should be installed on the thread that calls
+//! `readline()`.
+use libc::{EOF, c_int};
+use readline::{GetcFunction, IOFile, ffi_guard};
+use readline::hooks::{self, HookGuard};
+use std::cell::{Cell, RefCell};
+use std::io::{BufReader, ErrorKind, Read};
+use std::mem;
+use std::rc::Rc;
+
+mod ext_input {
+    use readline::GetcFunction;
+
+    extern "C" {
+        pub static mut rl_getc_function: Option<GetcFunction>;
+    }
+}
+
+type Getc = Box<dyn FnMut() -> Option<u8>>;
+
+thread_local!(static GETC: RefCell<Option<Getc>> = RefCell::new(None));
+// Bumped whenever an input function is installed or restored, so `getc_trampoline` can tell
+// whether to put back the closure it took.
+thread_local!(static GENERATION: Cell<u64> = Cell::new(0));
+
+/// Put `getc` in the slot, returning the closure it replaces.
+fn replace_getc(getc: Option<Getc>) -> Option<Getc> {
+    GENERATION.with(|g| g.set(g.get() + 1));
+    GETC.with(|g| mem::replace(&mut *g.borrow_mut(), getc))
+}
+
+/// The trampoline installed as `rl_getc_function`.
+unsafe extern "C" fn getc_trampoline(_stream: *mut IOFile) -> c_int {
+    let getc = GETC.with(|g| g.borrow_mut().take());
+    let generation = GENERATION.with(|g| g.get());
+
+    match getc {
+        Some(mut f) => {
+            let res = ffi_guard(None, || f());
+            // Unless the input function was replaced or restored while it ran, put it back.
+            if GENERATION.with(|g| g.get()) == generation {
+                GETC.with(|g| *g.borrow_mut() = Some(f));
+            }
+            res.map_or(EOF, c_int::from)
+        }
+        None => EOF,
+    }
+}
+
+/// Restores the input function that was installed before the guard's source when dropped.
+#[must_use]
+pub struct InputGuard {
+    previous_fn: Option<GetcFunction>,
+    previous: Option<Getc>,
+    _available: Option<HookGuard>,
+}
+
+impl Drop for InputGuard {
+    fn drop(&mut self) {
+        unsafe { ext_input::rl_getc_function = self.previous_fn };
+        replace_getc(self.previous.take());
+    }
+}
+
+fn install(getc: Getc, available: Option<HookGuard>) -> InputGuard {
+    let previous = replace_getc(Some(getc));
+    let previous_fn = unsafe {
+        let previous_fn = ext_input::rl_getc_function;
+        ext_input::rl_getc_function = Some(getc_trampoline as GetcFunction);
+        previous_fn
+    };
+
+    InputGuard {
+        previous_fn,
+        previous,
+        _available: available,
+    }
+}
+
+/// Install `f` as readline's input function. `f` is called each time readline needs another byte
+/// of input, and returns `None` at end of input.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{self, Outcome, input};
+///
+/// let mut keys = b"hi\n".to_vec().into_iter();
+/// let _guard = input::set_getc(move || keys.next());
+///
+/// assert_eq!(readline::readline("").unwrap(), Outcome::Line(String::from("hi")));
+/// ```
+pub fn set_getc<F>(f: F) -> InputGuard
+    where F: FnMut() -> Option<u8> + 'static
+{
+    install(Box::new(f), None)
+}
+
+/// Install `reader` as readline's input source. The reader is buffered, and readline is told that
+/// input is available whenever there are buffered bytes left, so multi-byte key sequences such as
+/// arrow keys are recognized. A read error is treated as the end of input.
+///
+/// Only the buffer is checked for available input: `reader` isn't polled, since a `Read` has no
+/// way to say whether a read would block. A key sequence that is split across two reads, as can
+/// happen with a socket or pipe, is seen as separate keys if its first part is ambiguous, such as
+/// a lone `ESC`. Use `set_getc` with a source that can be polled if that matters.
+///
+/// # Examples
+///
+/// Feed scripted keystrokes to `readline()`. Here `C-a` moves to the start of the first line
+/// before `X` is typed, and the left arrow key moves back a character on the second:
+///
+/// ```
+/// use rl_sys::readline::{self, Outcome, input, util};
+/// use std::io::Cursor;
+///
+/// util::init();
+///
+/// let _guard = input::set_reader(Cursor::new(b"hello\x01X\nac\x1b[Db\n".to_vec()));
+///
+/// assert_eq!(readline::readline("> ").unwrap(), Outcome::Line(String::from("Xhello")));
+/// assert_eq!(readline::readline("> ").unwrap(), Outcome::Line(String::from("abc")));
+/// assert_eq!(readline::readline("> ").unwrap(), Outcome::Eof);
+/// ```
+pub fn set_reader<R>(reader: R) -> InputGuard
+    where R: Read + 'static
+{
+    let reader = Rc::new(RefCell::new(BufReader::new(reader)));
+    let available = reader.clone();
+
+    let getc = move || {
+        let mut reader = reader.borrow_mut();
+        let mut byte = [0];
+        loop {
+            match reader.read(&mut byte) {
+                Ok(0) => return None,
+                Ok(_) => return Some(byte[0]),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    debug!("{}", e);
+                    return None;
+                }
+            }
+        }
+    };
+
+    let hook = hooks::set_input_available_hook(move || {
+        available.try_borrow_mut().map(|r| !r.buffer().is_empty()).unwrap_or(false)
+    });
+
+    install(Box::new(getc), Some(hook))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_guard_dropped_while_reading() {
+        let outer = set_getc(|| Some(b'a'));
+        let inner = Rc::new(RefCell::new(None));
+        let slot = inner.clone();
+        *inner.borrow_mut() = Some(set_getc(move || {
+            // Restore the outer function from inside the inner one.
+            drop(slot.borrow_mut().take());
+            Some(b'b')
+        }));
+
+        assert_eq!(unsafe { getc_trampoline(::std::ptr::null_mut()) }, 'b' as c_int);
+        assert_eq!(unsafe { getc_trampoline(::std::ptr::null_mut()) }, 'a' as c_int);
+        drop(outer);
+    }
+
+    #[test]
+    fn test_guard_restores_previous() {
+        let outer = set_getc(|| Some(b'a'));
+        {
+            let _inner = set_getc(|| Some(b'b'));
+            assert_eq!(unsafe { getc_trampoline(::std::ptr::null_mut()) }, 'b' as c_int);
+        }
+        assert_eq!(unsafe { getc_trampoline(::std::ptr::null_mut()) }, 'a' as c_int);
+        drop(outer);
+        assert_eq!(unsafe { getc_trampoline(::std::ptr::null_mut()) }, EOF);
+    }
+}
@@ -21,8 +21,21 @@
 //! Key bindings take place on a `keymap`. The keymap is the association between the keys that the
 //! user types and the functions that get run. You can make your own keymaps, copy existing keymaps,
 //! and tell Readline which keymap to use.
-use readline::Keymap;
+//!
+//! Keymaps created here are returned as a `KeymapHandle`, which owns the underlying storage and
+//! frees it (along with any subordinate keymaps and macros) when dropped. The keymaps owned by
+//! readline itself, such as `emacs` or `vi-insert`, are returned as a `KeymapRef<'static>`. Every
+//! function that takes a keymap accepts either, so a handle can't be used after it has been freed.
+//!
+//! The bindings in a keymap can be listed with `bindings`, which walks the keymap and any prefix
+//! keymaps reachable from it.
+use libc::{c_char, c_int, strdup};
+use readline::{Keymap, funmap};
+use std::collections::{HashMap, HashSet};
 use std::ffi::{CStr, CString};
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
 
 mod ext_keymap {
     use libc::c_char;
@@ -32,17 +45,149 @@ mod ext_keymap {
         pub fn rl_make_bare_keymap() -> Keymap;
         pub fn rl_copy_keymap(map: Keymap) -> Keymap;
         pub fn rl_make_keymap() -> Keymap;
-        pub fn rl_discard_keymap(map: Keymap) -> ();
         pub fn rl_free_keymap(map: Keymap) -> ();
         pub fn rl_get_keymap() -> Keymap;
         pub fn rl_set_keymap(map: Keymap) -> ();
+        pub fn rl_set_keymap_from_edit_mode() -> ();
         pub fn rl_get_keymap_by_name(name: *const c_char) -> Keymap;
         pub fn rl_get_keymap_name(map: Keymap) -> *const c_char;
     }
 }
 
-/// Returns a new, empty keymap. The space for the keymap is allocated with `malloc()`; the caller
-/// should free it by calling `rl_free_keymap()` when done.
+/// An owned keymap. The keymap is freed with `rl_free_keymap` when the handle is dropped. If it is
+/// still the active keymap at that point, the keymap for the current editing mode is made active
+/// first.
+#[derive(Debug)]
+pub struct KeymapHandle {
+    map: Keymap,
+}
+
+impl KeymapHandle {
+    /// Take ownership of a raw keymap allocated by readline.
+    ///
+    /// # Safety
+    ///
+    /// `map` must be a keymap allocated by readline that nothing else will free.
+    pub unsafe fn from_raw(map: Keymap) -> KeymapHandle {
+        KeymapHandle { map }
+    }
+
+    /// Borrow this keymap.
+    pub fn as_map(&self) -> KeymapRef<'_> {
+        KeymapRef::from(self)
+    }
+
+    /// Get the raw keymap pointer. The pointer is only valid while the handle is alive.
+    pub fn as_ptr(&self) -> Keymap {
+        self.map
+    }
+
+    /// Release ownership of the keymap. The keymap will no longer be freed on drop.
+    pub fn into_raw(self) -> Keymap {
+        let map = self.map;
+        mem::forget(self);
+        map
+    }
+}
+
+impl Drop for KeymapHandle {
+    fn drop(&mut self) {
+        unsafe {
+            if ext_keymap::rl_get_keymap() == self.map {
+                ext_keymap::rl_set_keymap_from_edit_mode();
+            }
+            ext_keymap::rl_free_keymap(self.map);
+        }
+    }
+}
+
+/// A borrowed keymap, either from a `KeymapHandle` or one of the keymaps owned by readline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeymapRef<'a> {
+    map: Keymap,
+    marker: PhantomData<&'a KeymapHandle>,
+}
+
+impl<'a> KeymapRef<'a> {
+    fn new(map: Keymap) -> KeymapRef<'a> {
+        KeymapRef {
+            map,
+            marker: PhantomData,
+        }
+    }
+
+    /// Borrow a raw keymap.
+    ///
+    /// # Safety
+    ///
+    /// `map` must be null or a keymap allocated by readline that stays alive for `'a`.
+    pub unsafe fn from_raw(map: Keymap) -> KeymapRef<'a> {
+        KeymapRef::new(map)
+    }
+
+    /// Get the raw keymap pointer.
+    pub fn as_ptr(&self) -> Keymap {
+        self.map
+    }
+}
+
+impl<'a> From<&'a KeymapHandle> for KeymapRef<'a> {
+    fn from(handle: &'a KeymapHandle) -> KeymapRef<'a> {
+        KeymapRef::new(handle.map)
+    }
+}
+
+/// Entry types, as stored in `KeymapEntry::type_`.
+const ISFUNC: i8 = 0;
+const ISKMAP: i8 = 1;
+const ISMACR: i8 = 2;
+
+/// The index of the catch-all entry at the end of every keymap, which isn't a key.
+const ANYOTHERKEY: usize = 256;
+
+/// `rl_copy_keymap` only copies the top level of `map`, leaving the copy sharing its subordinate
+/// keymaps and macros. Freeing such a copy would free them out from under `map`, so they are
+/// copied as well.
+unsafe fn deep_copy(map: Keymap) -> Keymap {
+    let copy = ext_keymap::rl_copy_keymap(map);
+    if copy.is_null() {
+        return copy;
+    }
+
+    for entry in (*copy).iter_mut() {
+        let data = match entry.kfunc {
+            Some(f) => f as *mut c_char,
+            None => continue,
+        };
+
+        let new_data = match entry.type_ {
+            ISKMAP => deep_copy(data as Keymap) as *mut c_char,
+            ISMACR => strdup(data),
+            _ => continue,
+        };
+        entry.kfunc = mem::transmute::<*mut c_char, Option<extern "C" fn() -> c_int>>(new_data);
+    }
+
+    copy
+}
+
+fn owned(map: Keymap, func: &str) -> Result<KeymapHandle, ::ReadlineError> {
+    if map.is_null() {
+        Err(::ReadlineError::NullPointer { func: String::from(func) })
+    } else {
+        Ok(KeymapHandle { map })
+    }
+}
+
+fn borrowed(map: Keymap, func: &str) -> Result<KeymapRef<'static>, ::ReadlineError> {
+    if map.is_null() {
+        Err(::ReadlineError::NullPointer { func: String::from(func) })
+    } else {
+        Ok(KeymapRef::new(map))
+    }
+}
+
+/// Returns a new, empty keymap. The keymap is freed when the returned handle is dropped.
 ///
 /// # Examples
 ///
@@ -50,21 +195,15 @@ mod ext_keymap {
 /// use rl_sys::readline::keymap;
 ///
 /// let keymap = keymap::create_empty().unwrap();
-/// assert!(!keymap.is_null());
+/// assert!(!keymap.as_ptr().is_null());
 /// ```
-pub fn create_empty() -> Result<Keymap, ::ReadlineError> {
-    unsafe {
-        let keymap_ptr = ext_keymap::rl_make_bare_keymap();
-
-        if keymap_ptr.is_null() {
-            Err(::ReadlineError::new("Null Pointer", "rl_make_bare_keymap returned null pointer!"))
-        } else {
-            Ok(keymap_ptr)
-        }
-    }
+pub fn create_empty() -> Result<KeymapHandle, ::ReadlineError> {
+    owned(unsafe { ext_keymap::rl_make_bare_keymap() },
+          "rl_make_bare_keymap")
 }
 
-/// Return a new keymap which is a copy of map.
+/// Return a new keymap which is a copy of map. Subordinate keymaps and macros are copied too, so
+/// the copy is independent of `map`.
 ///
 /// # Examples
 ///
@@ -72,20 +211,16 @@ pub fn create_empty() -> Result<Keymap, ::ReadlineError> {
 /// use rl_sys::readline::keymap;
 ///
 /// let keymap = keymap::create_empty().unwrap();
-/// assert!(!keymap.is_null());
-/// let km_copy = keymap::copy(keymap).unwrap();
-/// assert!(!km_copy.is_null());
+/// let km_copy = keymap::copy(&keymap).unwrap();
+/// assert!(km_copy.as_ptr() != keymap.as_ptr());
+///
+/// let emacs_copy = keymap::copy(keymap::emacs().unwrap()).unwrap();
+/// assert!(!emacs_copy.as_ptr().is_null());
 /// ```
-pub fn copy(map: Keymap) -> Result<Keymap, ::ReadlineError> {
-    unsafe {
-        let keymap_ptr = ext_keymap::rl_copy_keymap(map);
-
-        if keymap_ptr.is_null() {
-            Err(::ReadlineError::new("Null Pointer", "rl_copy_keymap returned null pointer!"))
-        } else {
-            Ok(keymap_ptr)
-        }
-    }
+pub fn copy<'a, M>(map: M) -> Result<KeymapHandle, ::ReadlineError>
+    where M: Into<KeymapRef<'a>>
+{
+    owned(unsafe { deep_copy(map.into().as_ptr()) }, "rl_copy_keymap")
 }
 
 /// Return a new keymap with the printing characters bound to `rl_insert`, the lowercase Meta
@@ -98,112 +233,111 @@ pub fn copy(map: Keymap) -> Result<Keymap, ::ReadlineError> {
 /// use rl_sys::readline::keymap;
 ///
 /// let keymap = keymap::make().unwrap();
-/// assert!(!keymap.is_null());
+/// assert!(!keymap.as_ptr().is_null());
 /// ```
-pub fn make() -> Result<Keymap, ::ReadlineError> {
-    unsafe {
-        let keymap_ptr = ext_keymap::rl_make_keymap();
-
-        if keymap_ptr.is_null() {
-            Err(::ReadlineError::new("Null Pointer", "rl_make_keymap returned null pointer!"))
-        } else {
-            Ok(keymap_ptr)
-        }
-    }
+pub fn make() -> Result<KeymapHandle, ::ReadlineError> {
+    owned(unsafe { ext_keymap::rl_make_keymap() }, "rl_make_keymap")
 }
 
-/// Free the storage associated with the data in keymap. The caller should free keymap.
+/// Returns the currently active keymap.
+///
+/// # Safety
+///
+/// The active keymap may be owned by a `KeymapHandle` passed to `set`, in which case the returned
+/// reference must not be used after that handle is dropped. Prefer `KeymapHandle::as_map` when the
+/// handle is at hand.
 ///
 /// # Examples
 ///
 /// ```
 /// use rl_sys::readline::keymap;
 ///
-/// let keymap = keymap::make().unwrap();
-/// assert!(!keymap.is_null());
-/// keymap::discard(keymap);
+/// let keymap = unsafe { keymap::get() }.unwrap();
+/// assert!(!keymap.as_ptr().is_null());
 /// ```
-pub fn discard(map: Keymap) -> () {
-    unsafe { ext_keymap::rl_discard_keymap(map) }
+pub unsafe fn get() -> Result<KeymapRef<'static>, ::ReadlineError> {
+    borrowed(ext_keymap::rl_get_keymap(), "rl_get_keymap")
 }
 
-/// Free all storage associated with keymap. This calls `rl_discard_keymap` to free subordindate
-/// keymaps and macros.
+/// Makes keymap the currently active keymap. If `map` is a `KeymapHandle`, dropping the handle
+/// makes the keymap for the current editing mode active again.
 ///
 /// # Examples
 ///
 /// ```
 /// use rl_sys::readline::keymap;
 ///
-/// let keymap = keymap::make().unwrap();
-/// assert!(!keymap.is_null());
-/// keymap::free(keymap);
+/// let keymap = keymap::emacs().unwrap();
+/// keymap::set(keymap);
+///
+/// let custom = keymap::make().unwrap();
+/// keymap::set(&custom);
+/// assert_eq!(unsafe { keymap::get() }.unwrap(), custom.as_map());
 /// ```
-pub fn free(map: Keymap) -> () {
-    unsafe { ext_keymap::rl_free_keymap(map) }
+pub fn set<'a, M>(map: M) -> ()
+    where M: Into<KeymapRef<'a>>
+{
+    unsafe { ext_keymap::rl_set_keymap(map.into().as_ptr()) }
 }
 
-/// Returns the currently active keymap.
+/// Return the keymap matching `name`. `name` is one which would be supplied in a set keymap inputrc
+/// line (see section [1.3 Readline Init File]).
+/// [1.3 readline init file]: https://goo.gl/VtaCdx
 ///
 /// # Examples
 ///
 /// ```
 /// use rl_sys::readline::keymap;
 ///
-/// let keymap = keymap::get().unwrap();
-/// assert!(!keymap.is_null());
+/// let keymap = keymap::get_by_name("emacs").unwrap();
+/// assert!(!keymap.as_ptr().is_null());
 /// ```
-pub fn get() -> Result<Keymap, ::ReadlineError> {
-    unsafe {
-        let keymap_ptr = ext_keymap::rl_get_keymap();
-
-        if keymap_ptr.is_null() {
-            Err(::ReadlineError::new("Null Pointer", "rl_get_keymap returned null pointer!"))
-        } else {
-            Ok(keymap_ptr)
-        }
-    }
+pub fn get_by_name(name: &str) -> Result<KeymapRef<'static>, ::ReadlineError> {
+    let csname = try!(CString::new(name));
+    borrowed(unsafe { ext_keymap::rl_get_keymap_by_name(csname.as_ptr()) },
+             "rl_get_keymap_by_name")
 }
 
-/// Makes keymap the currently active keymap.
+/// Return the standard emacs keymap.
 ///
 /// # Examples
 ///
 /// ```
 /// use rl_sys::readline::keymap;
 ///
-/// let keymap = keymap::get().unwrap();
-/// assert!(!keymap.is_null());
-/// keymap::set(keymap);
+/// let keymap = keymap::emacs().unwrap();
+/// assert_eq!(keymap::get_name(keymap).unwrap(), "emacs");
 /// ```
-pub fn set(map: Keymap) -> () {
-    unsafe { ext_keymap::rl_set_keymap(map) }
+pub fn emacs() -> Result<KeymapRef<'static>, ::ReadlineError> {
+    get_by_name("emacs")
 }
 
-/// Return the keymap matching `name`. `name` is one which would be supplied in a set keymap inputrc
-/// line (see section [1.3 Readline Init File]).
-/// [1.3 readline init file]: https://goo.gl/VtaCdx
+/// Return the vi insertion keymap.
 ///
 /// # Examples
 ///
 /// ```
 /// use rl_sys::readline::keymap;
 ///
-/// let keymap = keymap::get_by_name("emacs").unwrap();
-/// assert!(!keymap.is_null());
+/// let keymap = keymap::vi_insert().unwrap();
+/// assert_eq!(keymap::get_name(keymap).unwrap(), "vi-insert");
 /// ```
-pub fn get_by_name(name: &str) -> Result<Keymap, ::ReadlineError> {
-    let csname = try!(CString::new(name));
-    unsafe {
-        let keymap_ptr = ext_keymap::rl_get_keymap_by_name(csname.as_ptr());
+pub fn vi_insert() -> Result<KeymapRef<'static>, ::ReadlineError> {
+    get_by_name("vi-insert")
+}
 
-        if keymap_ptr.is_null() {
-            Err(::ReadlineError::new("Null Pointer",
-                                     "rl_get_keymap_by_name returned null pointer!"))
-        } else {
-            Ok(keymap_ptr)
-        }
-    }
+/// Return the vi movement keymap.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::keymap;
+///
+/// let keymap = keymap::vi_command().unwrap();
+/// assert_eq!(keymap, keymap::get_by_name("vi-move").unwrap());
+/// ```
+pub fn vi_command() -> Result<KeymapRef<'static>, ::ReadlineError> {
+    get_by_name("vi-command")
 }
 
 /// Return the name matching `map`. `name` is one which would be supplied in a set keymap inputrc
@@ -215,36 +349,226 @@ pub fn get_by_name(name: &str) -> Result<Keymap, ::ReadlineError> {
 /// ```
 /// use rl_sys::readline::keymap;
 ///
-/// let keymap = keymap::get().unwrap();
-/// assert!(!keymap.is_null());
+/// let keymap = unsafe { keymap::get() }.unwrap();
 /// let name = keymap::get_name(keymap).unwrap();
 /// assert!(!name.is_empty());
 /// ```
-pub fn get_name(map: Keymap) -> Result<String, ::ReadlineError> {
+pub fn get_name<'a, M>(map: M) -> Result<String, ::ReadlineError>
+    where M: Into<KeymapRef<'a>>
+{
     unsafe {
-        let name_ptr = ext_keymap::rl_get_keymap_name(map);
+        let name_ptr = ext_keymap::rl_get_keymap_name(map.into().as_ptr());
 
         if name_ptr.is_null() {
-            Err(::ReadlineError::new("Null Pointer", "rl_get_keymap_name returned null pointer!"))
+            Err(::ReadlineError::NullPointer { func: String::from("rl_get_keymap_name") })
         } else {
             Ok(CStr::from_ptr(name_ptr).to_string_lossy().into_owned())
         }
     }
 }
 
+/// A sequence of keys, as the bytes readline reads for them.
+///
+/// `Display` formats the sequence the way it would be written in a double-quoted `inputrc` key
+/// binding, e.g. `\C-x\C-r` or `\ef`.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct KeySeq(pub Vec<u8>);
+
+impl fmt::Display for KeySeq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &b in &self.0 {
+            match b {
+                0x1b => try!(write!(f, "\\e")),
+                b'\\' => try!(write!(f, "\\\\")),
+                b'"' => try!(write!(f, "\\\"")),
+                0x7f => try!(write!(f, "\\C-?")),
+                0x00..=0x1f => {
+                    let c = (b + 0x40) as char;
+                    match c.to_ascii_lowercase() {
+                        '\\' => try!(write!(f, "\\C-\\\\")),
+                        c => try!(write!(f, "\\C-{}", c)),
+                    }
+                }
+                0x80..=0xff => try!(write!(f, "\\{:03o}", b)),
+                _ => try!(write!(f, "{}", b as char)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a key sequence is bound to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Binding<'a> {
+    /// A command function, named as it is in the funmap. The name is `None` if the function was
+    /// never added to the funmap.
+    Function(Option<String>),
+    /// A keyboard macro.
+    Macro(String),
+    /// A prefix keymap. Its bindings are also returned by the iterator, directly after this entry.
+    Keymap(KeymapRef<'a>),
+}
+
+/// Iterator over the bindings in a keymap, created by `bindings`.
+pub struct Bindings<'a> {
+    /// Map of function pointer to funmap name.
+    names: HashMap<usize, String>,
+    /// The keymaps being walked, with the key sequence leading to each and the next index to visit.
+    stack: Vec<(Keymap, Vec<u8>, usize)>,
+    /// Keymaps already walked, so each prefix keymap is only descended into once.
+    visited: HashSet<Keymap>,
+    marker: PhantomData<&'a KeymapHandle>,
+}
+
+impl<'a> Iterator for Bindings<'a> {
+    type Item = (KeySeq, Binding<'a>);
+
+    fn next(&mut self) -> Option<(KeySeq, Binding<'a>)> {
+        loop {
+            let (map, mut seq, idx) = match self.stack.pop() {
+                Some(top) => top,
+                None => return None,
+            };
+            if idx >= ANYOTHERKEY {
+                continue;
+            }
+
+            let entry = unsafe { (*map)[idx] };
+            self.stack.push((map, seq.clone(), idx + 1));
+            seq.push(idx as u8);
+
+            let data = match entry.kfunc {
+                Some(f) => f as *mut c_char,
+                None => continue,
+            };
+
+            let binding = match entry.type_ {
+                ISFUNC => Binding::Function(self.names.get(&(data as usize)).cloned()),
+                ISKMAP => {
+                    let submap = data as Keymap;
+                    if self.visited.insert(submap) {
+                        self.stack.push((submap, seq.clone(), 0));
+                    }
+                    Binding::Keymap(KeymapRef::new(submap))
+                }
+                ISMACR => {
+                    Binding::Macro(unsafe { CStr::from_ptr(data).to_string_lossy().into_owned() })
+                }
+                _ => continue,
+            };
+
+            return Some((KeySeq(seq), binding));
+        }
+    }
+}
+
+/// Build a reverse lookup of the funmap, from function pointer to name. Where a function has more
+/// than one name, the first in alphabetical order is used.
+fn function_names() -> HashMap<usize, String> {
+    let mut names = HashMap::new();
+    for name in funmap::funmap_names().unwrap_or_default() {
+        if let Ok(f) = funmap::named_function(&name) {
+            names.entry(f as usize).or_insert(name);
+        }
+    }
+    names
+}
+
+/// Return an iterator over every binding in `map`, as `(KeySeq, Binding)` pairs in key order.
+/// Unbound keys are skipped. When a key is bound to a prefix keymap (such as `ESC` or `C-x` in the
+/// emacs keymap), the bindings in that keymap follow it, with the prefix key at the start of their
+/// key sequences.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{binding, funmap, keymap, util};
+/// use rl_sys::readline::keymap::Binding;
+///
+/// util::init();
+///
+/// let km = keymap::create_empty().unwrap();
+/// let kill_line = funmap::named_function("kill-line").unwrap();
+/// binding::bind_keyseq_in_map("\\C-xk", kill_line, &km).unwrap();
+///
+/// let bindings = keymap::bindings(&km)
+///                    .map(|(seq, binding)| (seq.to_string(), binding))
+///                    .collect::<Vec<_>>();
+///
+/// assert_eq!(bindings.len(), 2);
+/// assert_eq!(bindings[0].0, "\\C-x");
+/// assert_eq!(bindings[1],
+///            (String::from("\\C-xk"), Binding::Function(Some(String::from("kill-line")))));
+/// ```
+pub fn bindings<'a, M>(map: M) -> Bindings<'a>
+    where M: Into<KeymapRef<'a>>
+{
+    let map = map.into().as_ptr();
+    let mut visited = HashSet::new();
+    visited.insert(map);
+
+    Bindings {
+        names: function_names(),
+        stack: vec![(map, Vec::new(), 0)],
+        visited,
+        marker: PhantomData,
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use std::ptr;
     use super::*;
 
     #[test]
     pub fn test_get_name() {
-        let keymap = get().unwrap_or(ptr::null_mut());
-        assert!(!keymap.is_null());
+        let keymap = unsafe { get() }.unwrap();
+        assert!(!keymap.as_ptr().is_null());
 
         match get_name(keymap) {
             Ok(n) => assert!(!n.is_empty()),
             Err(_) => assert!(false),
         }
     }
+
+    #[test]
+    pub fn test_keyseq_display() {
+        assert_eq!(KeySeq(vec![0x18, 0x12]).to_string(), "\\C-x\\C-r");
+        assert_eq!(KeySeq(vec![0x1b, b'f']).to_string(), "\\ef");
+        assert_eq!(KeySeq(vec![0x1c, 0x7f]).to_string(), "\\C-\\\\\\C-?");
+        assert_eq!(KeySeq(vec![b'"', b'\\', 0xe9]).to_string(), "\\\"\\\\\\351");
+    }
+
+    #[test]
+    pub fn test_emacs_bindings() {
+        ::readline::util::init();
+        let all = bindings(emacs().unwrap())
+            .map(|(seq, binding)| (seq.to_string(), binding))
+            .collect::<Vec<_>>();
+        let find = |seq: &str| {
+            all.iter().find(|&&(ref s, _)| s == seq).map(|&(_, ref b)| b.clone())
+        };
+
+        assert_eq!(find("\\C-a"),
+                   Some(Binding::Function(Some(String::from("beginning-of-line")))));
+        assert_eq!(find("\\ef"),
+                   Some(Binding::Function(Some(String::from("forward-word")))));
+        assert_eq!(find("\\C-x\\C-u"),
+                   Some(Binding::Function(Some(String::from("undo")))));
+        match find("\\e") {
+            Some(Binding::Keymap(_)) => {}
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    pub fn test_drop_active_keymap() {
+        let emacs = emacs().unwrap();
+        set(emacs);
+        {
+            let custom = make().unwrap();
+            set(&custom);
+            assert_eq!(unsafe { get() }.unwrap(), custom.as_map());
+        }
+        assert_eq!(unsafe { get() }.unwrap(), emacs);
+    }
 }
@@ -0,0 +1,312 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Character-aware Line Buffer
+//!
+//! `rl_point`, `rl_end` and `rl_mark`, and the offsets taken by the functions in
+//! `readline::modtext` and `undo::modifying`, are byte offsets into `rl_line_buffer`. Treating them
+//! as character indices splits multibyte UTF-8 sequences.
+//!
+//! A `LineBuffer` is a snapshot of the current line as a `&str`, with point, mark and end available
+//! as both byte offsets and character (Unicode scalar value) indices. Its editing operations take
+//! character indices, convert them to byte offsets, make the change through readline, and then
+//! refresh the snapshot.
+use libc::c_int;
+use readline::{modtext, undo, vars};
+use std::cmp;
+use std::slice;
+use std::str;
+
+mod ext_linebuf {
+    use libc::c_char;
+
+    extern "C" {
+        pub static rl_line_buffer: *mut c_char;
+    }
+}
+
+/// A snapshot of the readline line buffer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineBuffer {
+    text: String,
+    point: usize,
+    mark: usize,
+}
+
+fn out_of_range() -> ::ReadlineError {
+    ::ReadlineError::new("LineBuffer Error", "Character index out of range!")
+}
+
+#[cfg_attr(feature = "clippy", allow(cast_possible_truncation, cast_possible_wrap))]
+fn to_c_int(offset: usize) -> Result<c_int, ::ReadlineError> {
+    if offset > c_int::max_value() as usize {
+        Err(::ReadlineError::new("LineBuffer Error", "Offset too large!"))
+    } else {
+        Ok(offset as c_int)
+    }
+}
+
+impl LineBuffer {
+    /// Take a snapshot of the current line. Fails if readline has not allocated a line buffer yet,
+    /// or if the line is not valid UTF-8.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::readline::{modtext, util};
+    /// use rl_sys::readline::linebuf::LineBuffer;
+    ///
+    /// util::init();
+    ///
+    /// assert!(modtext::insert_text("naïve café").is_ok());
+    ///
+    /// let line = LineBuffer::current().unwrap();
+    /// assert_eq!(line.as_str(), "naïve café");
+    /// assert_eq!(line.end(), 12);
+    /// assert_eq!(line.end_char(), 10);
+    /// assert_eq!(line.point_char(), 10);
+    /// ```
+    #[cfg_attr(feature = "clippy", allow(cast_sign_loss))]
+    pub fn current() -> Result<LineBuffer, ::ReadlineError> {
+        unsafe {
+            if ext_linebuf::rl_line_buffer.is_null() {
+                return Err(::ReadlineError::NullPointer { func: String::from("rl_line_buffer") });
+            }
+
+            let end = cmp::max(vars::get_end(), 0) as usize;
+            let bytes = slice::from_raw_parts(ext_linebuf::rl_line_buffer as *const u8, end);
+            let text = try!(str::from_utf8(bytes)).to_string();
+
+            Ok(LineBuffer {
+                point: cmp::min(cmp::max(vars::get_point(), 0) as usize, end),
+                mark: cmp::min(cmp::max(vars::get_mark(), 0) as usize, end),
+                text,
+            })
+        }
+    }
+
+    /// Re-read the line from readline, e.g. after it was changed outside of this snapshot.
+    pub fn refresh(&mut self) -> Result<(), ::ReadlineError> {
+        *self = try!(LineBuffer::current());
+        Ok(())
+    }
+
+    /// The line as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// The point (cursor position) as a byte offset, as `rl_point` holds it.
+    pub fn point(&self) -> usize {
+        self.point
+    }
+
+    /// The point as a character index. If `rl_point` is in the middle of a multibyte character,
+    /// the index of that character is returned.
+    pub fn point_char(&self) -> usize {
+        self.floor_char(self.point)
+    }
+
+    /// The mark as a byte offset, as `rl_mark` holds it.
+    pub fn mark(&self) -> usize {
+        self.mark
+    }
+
+    /// The mark as a character index. If `rl_mark` is in the middle of a multibyte character, the
+    /// index of that character is returned.
+    pub fn mark_char(&self) -> usize {
+        self.floor_char(self.mark)
+    }
+
+    /// The length of the line in bytes, as `rl_end` holds it.
+    pub fn end(&self) -> usize {
+        self.text.len()
+    }
+
+    /// The length of the line in characters.
+    pub fn end_char(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    /// Convert the byte offset `byte` to a character index. Returns `None` if `byte` is past the
+    /// end of the line or not on a character boundary.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::readline::{modtext, util};
+    /// use rl_sys::readline::linebuf::LineBuffer;
+    ///
+    /// util::init();
+    ///
+    /// assert!(modtext::insert_text("é!").is_ok());
+    ///
+    /// let line = LineBuffer::current().unwrap();
+    /// assert_eq!(line.byte_to_char(2), Some(1));
+    /// assert_eq!(line.byte_to_char(1), None);
+    /// assert_eq!(line.char_to_byte(1), Some(2));
+    /// assert_eq!(line.char_to_byte(3), None);
+    /// ```
+    pub fn byte_to_char(&self, byte: usize) -> Option<usize> {
+        if self.text.is_char_boundary(byte) {
+            Some(self.text[..byte].chars().count())
+        } else {
+            None
+        }
+    }
+
+    /// Convert the character index `ch` to a byte offset. An index equal to the number of
+    /// characters converts to the end of the line. Returns `None` if `ch` is past the end.
+    pub fn char_to_byte(&self, ch: usize) -> Option<usize> {
+        self.text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(Some(self.text.len()))
+            .nth(ch)
+    }
+
+    /// Return the text between the character indices `start` and `end`.
+    pub fn slice(&self, start: usize, end: usize) -> Result<&str, ::ReadlineError> {
+        let (start, end) = try!(self.byte_range(start, end));
+        Ok(&self.text[cmp::min(start, end)..cmp::max(start, end)])
+    }
+
+    /// Move the point to the character index `ch`.
+    pub fn set_point(&mut self, ch: usize) -> Result<(), ::ReadlineError> {
+        let byte = try!(self.char_to_byte(ch).ok_or_else(out_of_range));
+        try!(vars::set_point(try!(to_c_int(byte))));
+        self.point = byte;
+        Ok(())
+    }
+
+    /// Set the mark to the character index `ch`.
+    pub fn set_mark(&mut self, ch: usize) -> Result<(), ::ReadlineError> {
+        let byte = try!(self.char_to_byte(ch).ok_or_else(out_of_range));
+        try!(vars::set_mark(try!(to_c_int(byte))));
+        self.mark = byte;
+        Ok(())
+    }
+
+    /// Insert `text` at the point, as `modtext::insert_text` does.
+    pub fn insert(&mut self, text: &str) -> Result<(), ::ReadlineError> {
+        if !text.is_empty() {
+            try!(modtext::insert_text(text));
+        }
+        self.refresh()
+    }
+
+    /// Delete the text between the character indices `start` and `end`, as `modtext::delete_text`
+    /// does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rl_sys::readline::{modtext, util};
+    /// use rl_sys::readline::linebuf::LineBuffer;
+    ///
+    /// util::init();
+    ///
+    /// assert!(modtext::insert_text("naïve café").is_ok());
+    ///
+    /// let mut line = LineBuffer::current().unwrap();
+    /// assert!(line.delete(0, 6).is_ok());
+    /// assert_eq!(line.as_str(), "café");
+    /// ```
+    pub fn delete(&mut self, start: usize, end: usize) -> Result<(), ::ReadlineError> {
+        let (start, end) = try!(self.c_range(start, end));
+        if start != end {
+            try!(modtext::delete_text(start, end));
+        }
+        self.refresh()
+    }
+
+    /// Kill the text between the character indices `start` and `end`, as `modtext::kill_text`
+    /// does. If `start` is greater than `end`, the text is prepended to the last kill.
+    pub fn kill(&mut self, start: usize, end: usize) -> Result<(), ::ReadlineError> {
+        let (start, end) = try!(self.c_range(start, end));
+        try!(modtext::kill_text(start, end));
+        self.refresh()
+    }
+
+    /// Tell readline to save the text between the character indices `start` and `end` as a single
+    /// undo unit, as `undo::modifying` does.
+    pub fn modifying(&self, start: usize, end: usize) -> Result<(), ::ReadlineError> {
+        let (start, end) = try!(self.c_range(start, end));
+        undo::modifying(start, end);
+        Ok(())
+    }
+
+    /// The character index of the character containing `byte`.
+    fn floor_char(&self, byte: usize) -> usize {
+        self.text
+            .char_indices()
+            .take_while(|&(i, c)| i + c.len_utf8() <= byte)
+            .count()
+    }
+
+    fn byte_range(&self, start: usize, end: usize) -> Result<(usize, usize), ::ReadlineError> {
+        let start = try!(self.char_to_byte(start).ok_or_else(out_of_range));
+        let end = try!(self.char_to_byte(end).ok_or_else(out_of_range));
+        Ok((start, end))
+    }
+
+    fn c_range(&self, start: usize, end: usize) -> Result<(c_int, c_int), ::ReadlineError> {
+        let (start, end) = try!(self.byte_range(start, end));
+        Ok((try!(to_c_int(start)), try!(to_c_int(end))))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn line(text: &str, point: usize) -> LineBuffer {
+        LineBuffer {
+            text: text.to_string(),
+            point,
+            mark: 0,
+        }
+    }
+
+    #[test]
+    fn test_conversions() {
+        let l = line("aé€😀", 3);
+        assert_eq!(l.end(), 10);
+        assert_eq!(l.end_char(), 4);
+        assert_eq!(l.point_char(), 2);
+
+        assert_eq!(l.char_to_byte(0), Some(0));
+        assert_eq!(l.char_to_byte(2), Some(3));
+        assert_eq!(l.char_to_byte(4), Some(10));
+        assert_eq!(l.char_to_byte(5), None);
+
+        assert_eq!(l.byte_to_char(6), Some(3));
+        assert_eq!(l.byte_to_char(7), None);
+        assert_eq!(l.byte_to_char(11), None);
+
+        assert_eq!(l.slice(1, 3).unwrap(), "é€");
+        assert_eq!(l.slice(3, 1).unwrap(), "é€");
+        assert!(l.slice(0, 5).is_err());
+    }
+
+    #[test]
+    fn test_point_mid_character() {
+        // A point inside "é" belongs to that character.
+        assert_eq!(line("aé", 2).point_char(), 1);
+        assert_eq!(line("aé", 3).point_char(), 2);
+    }
+}
@@ -17,7 +17,10 @@
 
 //! [2.4.11 Miscellaneous Functions]
 //! [2.4.11 miscellaneous functions]: https://goo.gl/2BieXb
+use readline::{capture_outstream, funmap, keymap};
+use readline::keymap::{Binding, KeySeq};
 use std::ffi::{CStr, CString};
+use std::fmt;
 
 mod ext_misc {
     use libc::{c_char, c_int};
@@ -37,6 +40,8 @@ mod ext_misc {
 /// `rl_outstream`. If `readable` is true, the list is formatted in such a way that it can be made
 /// part of an inputrc file and re-read.
 ///
+/// To get the output as data rather than printing it, use `macros`.
+///
 /// # Examples
 ///
 /// ```
@@ -52,6 +57,55 @@ pub fn macro_dumper(readable: bool) -> () {
     unsafe { ext_misc::rl_macro_dumper(read) }
 }
 
+/// A key sequence bound to a keyboard macro.
+///
+/// `Display` formats the macro as an `inputrc` line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroBinding {
+    /// The key sequence that invokes the macro.
+    pub keyseq: KeySeq,
+    /// The text the macro inserts.
+    pub text: String,
+}
+
+impl fmt::Display for MacroBinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "\"{}\": \"{}\"",
+               self.keyseq,
+               KeySeq(self.text.clone().into_bytes()))
+    }
+}
+
+/// Return the key sequences bound to macros in the current keymap, along with their values. This
+/// is the data `macro_dumper` prints.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{binding, misc, util};
+///
+/// util::init();
+///
+/// binding::parse_and_bind("\"\\C-xq\": \"quit\\C-m\"").unwrap();
+///
+/// let macros = misc::macros().unwrap();
+/// let quit = macros.iter().find(|m| m.text == "quit\r").unwrap();
+/// assert_eq!(quit.to_string(), "\"\\C-xq\": \"quit\\C-m\"");
+/// ```
+pub fn macros() -> Result<Vec<MacroBinding>, ::ReadlineError> {
+    let km = try!(unsafe { keymap::get() });
+
+    Ok(keymap::bindings(km)
+        .filter_map(|(keyseq, binding)| {
+            match binding {
+                Binding::Macro(text) => Some(MacroBinding { keyseq, text }),
+                _ => None,
+            }
+        })
+        .collect())
+}
+
 /// Make the Readline variable `name` have `value`. This behaves as if the readline command
 /// `set variable value` had been executed in an inputrc file (see section
 /// [1.3.1 Readline Init File Syntax]).
@@ -99,8 +153,7 @@ pub fn variable_value(name: &str) -> Result<String, ::ReadlineError> {
 
     let val_ptr = unsafe { ext_misc::rl_variable_value(csname.as_ptr()) };
     if val_ptr.is_null() {
-        Err(::ReadlineError::new("Misc Error",
-                                 "Null pointer returned from rl_variable_value!"))
+        Err(::ReadlineError::NullPointer { func: String::from("rl_variable_value") })
     } else {
         Ok(unsafe { CStr::from_ptr(val_ptr).to_string_lossy().into_owned() })
     }
@@ -110,6 +163,8 @@ pub fn variable_value(name: &str) -> Result<String, ::ReadlineError> {
 /// true, the list is formatted in such a way that it can be made part of an inputrc file and
 /// re-read.
 ///
+/// To get the output as data rather than printing it, use `variables`.
+///
 /// # Examples
 ///
 /// ```
@@ -125,6 +180,96 @@ pub fn variable_dumper(readable: bool) -> () {
     unsafe { ext_misc::rl_variable_dumper(read) }
 }
 
+/// A Readline variable and its current value.
+///
+/// `Display` formats the variable as an `inputrc` `set` line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variable {
+    /// The variable name.
+    pub name: String,
+    /// The current value. For boolean variables, this is either `on` or `off`.
+    pub value: String,
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "set {} {}", self.name, self.value)
+    }
+}
+
+/// Return the readline variable names and their current values. This is the data
+/// `variable_dumper` prints.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{misc, util};
+///
+/// util::init();
+///
+/// assert!(misc::variable_bind("bell-style", "visible").is_ok());
+///
+/// let vars = misc::variables().unwrap();
+/// let bell = vars.iter().find(|v| v.name == "bell-style").unwrap();
+/// assert_eq!(bell.value, "visible");
+/// assert_eq!(bell.to_string(), "set bell-style visible");
+/// ```
+pub fn variables() -> Result<Vec<Variable>, ::ReadlineError> {
+    // Readline has no way to list its variables other than printing them, so the readable dump
+    // is captured and parsed back.
+    let dump = try!(capture_outstream(|| unsafe { ext_misc::rl_variable_dumper(1) }));
+
+    Ok(dump.lines()
+        .filter_map(|line| {
+            let mut parts = line.trim_end().splitn(3, ' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some("set"), Some(name), value) => {
+                    Some(Variable {
+                        name: name.to_string(),
+                        value: value.unwrap_or("").to_string(),
+                    })
+                }
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+/// Return the effective readline configuration for the current keymap in `inputrc` syntax: the
+/// value of every variable, followed by the key sequences bound to functions and to macros. The
+/// result can be written to a file and read back with `binding::read_init_file`.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{misc, util};
+///
+/// util::init();
+///
+/// let inputrc = misc::export_inputrc().unwrap();
+/// assert!(inputrc.lines().any(|l| l == "set editing-mode emacs"));
+/// assert!(inputrc.lines().any(|l| l == "\"\\C-a\": beginning-of-line"));
+/// ```
+pub fn export_inputrc() -> Result<String, ::ReadlineError> {
+    let mut out = String::new();
+
+    for var in try!(variables()) {
+        out.push_str(&format!("{}\n", var));
+    }
+
+    for binding in try!(funmap::function_bindings()) {
+        if !binding.keyseqs.is_empty() {
+            out.push_str(&format!("{}\n", binding));
+        }
+    }
+
+    for mac in try!(macros()) {
+        out.push_str(&format!("{}\n", mac));
+    }
+
+    Ok(out)
+}
+
 /// Set the time interval (in microseconds) that Readline waits when showing a balancing character
 /// when `blink-matching-paren` has been enabled.
 ///
@@ -172,7 +317,7 @@ pub fn get_termcap(cap: &str) -> Result<String, ::ReadlineError> {
 
     let cap_ptr = unsafe { ext_misc::rl_get_termcap(cscap.as_ptr()) };
     if cap_ptr.is_null() {
-        Err(::ReadlineError::new("Misc Error", "rl_get_termcap returned a null pointer!"))
+        Err(::ReadlineError::NullPointer { func: String::from("rl_get_termcap") })
     } else {
         Ok(unsafe { CStr::from_ptr(cscap.as_ptr()).to_string_lossy().into_owned() })
     }
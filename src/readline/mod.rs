@@ -54,18 +54,28 @@
 //! call `add_history()` to save the line away in a history list of such lines.
 //!
 //! For full details on the GNU History Library, see the associated manual.
-use libc::{c_char, c_int, c_long, c_uint, c_ushort, c_void, free, size_t};
-use std::ffi::{CStr, CString};
+use libc::{self, c_char, c_int, c_uint, c_void, free};
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString, OsString};
 use std::fmt;
+use std::io;
 use std::mem;
+use std::os::unix::ffi::OsStringExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub mod binding;
+pub mod callback;
 pub mod charin;
+pub mod command;
+pub mod completion;
 mod ext_readline {
-    use libc::c_char;
+    use libc::{FILE, c_char};
     use super::HandlerFunction;
 
     extern "C" {
+        pub static mut rl_outstream: *mut FILE;
         pub fn readline(p: *const c_char) -> *const c_char;
         pub fn rl_callback_handler_install(p: *const c_char,
                                            lhandler: Option<HandlerFunction>)
@@ -75,11 +85,16 @@ mod ext_readline {
     }
 }
 pub mod funmap;
+pub mod hooks;
+pub mod input;
 pub mod keymap;
+pub mod linebuf;
 pub mod misc;
 pub mod modtext;
 pub mod naming;
 pub mod redisplay;
+pub mod signals;
+pub mod stream;
 pub mod termmgmt;
 pub mod util;
 pub mod undo;
@@ -97,99 +112,21 @@ pub type VoidFunction = extern "C" fn() -> ();
 pub type PrepFunction = extern "C" fn(flag: c_int) -> ();
 /// Readline Callback Handler
 pub type HandlerFunction = unsafe extern "C" fn(line: *mut c_char) -> ();
+/// Readline Attempted Completion Function Type
+pub type CompletionFunction = unsafe extern "C" fn(text: *const c_char,
+                                                   start: c_int,
+                                                   end: c_int)
+                                                   -> *mut *mut c_char;
+/// Readline Completion Entry (Generator) Function Type
+pub type CompentryFunction = unsafe extern "C" fn(text: *const c_char, state: c_int) -> *mut c_char;
 /// Keymap Entry Array
 pub type KeymapEntryArray = [KeymapEntry; 257usize];
 /// Keymap
 pub type Keymap = *mut KeymapEntryArray;
 
-
-#[repr(C)]
-#[derive(Clone, Copy, Debug)]
-/// I/O Marker
-pub struct IOMarker {
-    /// Next I/O marker.
-    pub next: *mut IOMarker,
-    /// File buffer.
-    pub sbuf: *mut IOFile,
-    /// Position.
-    pub pos: c_int,
-}
-
-impl Default for IOMarker {
-    fn default() -> Self {
-        unsafe { mem::zeroed() }
-    }
-}
-
-#[repr(C)]
-#[derive(Clone, Copy, Debug)]
-/// I/O File Type
-pub struct IOFile {
-    /// Flags
-    pub flags: c_int,
-    /// Flags
-    pub read_ptr: *mut c_char,
-    /// Flags
-    pub read_end: *mut c_char,
-    /// Flags
-    pub read_base: *mut c_char,
-    /// Flags
-    pub write_base: *mut c_char,
-    /// Flags
-    pub write_ptr: *mut c_char,
-    /// Flags
-    pub write_end: *mut c_char,
-    /// Flags
-    pub buf_base: *mut c_char,
-    /// Flags
-    pub buf_end: *mut c_char,
-    /// Flags
-    pub save_base: *mut c_char,
-    /// Flags
-    pub backup_base: *mut c_char,
-    /// Flags
-    pub save_end: *mut c_char,
-    /// Flags
-    pub markers: *mut IOMarker,
-    /// Flags
-    pub chain: *mut IOFile,
-    /// Flags
-    pub fileno: c_int,
-    /// Flags
-    pub flags2: c_int,
-    /// Flags
-    pub old_offset: c_long,
-    /// Flags
-    pub cur_column: c_ushort,
-    /// Flags
-    pub vtable_offset: c_char,
-    /// Flags
-    pub shortbuf: [c_char; 1usize],
-    /// Flags
-    pub lock: *mut c_void,
-    /// Flags
-    pub offset: c_long,
-    /// Flags
-    pub pad1: *mut c_void,
-    /// Flags
-    pub pad2: *mut c_void,
-    /// Flags
-    pub pad3: *mut c_void,
-    /// Flags
-    pub pad4: *mut c_void,
-    /// Flags
-    pub pad5: size_t,
-    /// Flags
-    pub mode: c_int,
-    /// Flags
-    pub unused2: [c_char; 20usize],
-}
-
-impl Default for IOFile {
-    fn default() -> Self {
-        unsafe { mem::zeroed() }
-    }
-}
+/// An opaque stdio stream (`FILE *`), as used for `rl_instream` and `rl_outstream`. See
+/// `readline::stream` to point readline at a file descriptor.
+pub type IOFile = libc::FILE;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -316,20 +253,156 @@ impl Default for UndoList {
     }
 }
 
+/// Set by the `SIGINT` handler installed by `signals::catch_interrupt`, and checked by
+/// `readline_bytes()` to report the line as interrupted.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The names passed to `rl_add_funmap_entry` and `rl_add_defun`. Readline keeps the name pointers
+/// in the funmap, which is shared by every thread and never shrinks, so each name is kept here for
+/// the life of the process, however many times it is registered.
+static FUNMAP_NAMES: Mutex<BTreeMap<String, CString>> = Mutex::new(BTreeMap::new());
+
+/// Get a NUL-terminated copy of `name` that outlives the funmap.
+fn funmap_cname(name: &str) -> Result<*const c_char, ::ReadlineError> {
+    let mut names = FUNMAP_NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(csname) = names.get(name) {
+        return Ok(csname.as_ptr());
+    }
+
+    let csname = try!(CString::new(name));
+    let ptr = csname.as_ptr();
+    names.insert(name.to_string(), csname);
+    Ok(ptr)
+}
+
+/// Run `f`, catching any panic so that it never unwinds into the C library. If `f` panics, the
+/// panic is logged and `default` is returned to readline instead.
+fn ffi_guard<T, F>(default: T, f: F) -> T
+    where F: FnOnce() -> T
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res,
+        Err(_) => {
+            error!("Panic caught at the readline FFI boundary!");
+            default
+        }
+    }
+}
+
+/// Run `f` with `rl_outstream` redirected to a temporary file, and return whatever was written to
+/// it. This is used to collect the output of the readline functions that can only print.
+fn capture_outstream<F>(f: F) -> Result<String, ::ReadlineError>
+    where F: FnOnce()
+{
+    unsafe {
+        let tmp = libc::tmpfile();
+        if tmp.is_null() {
+            return Err(::ReadlineError::from(io::Error::last_os_error()));
+        }
+
+        let saved = ext_readline::rl_outstream;
+        ext_readline::rl_outstream = tmp;
+        f();
+        ext_readline::rl_outstream = saved;
+
+        libc::fflush(tmp);
+        libc::rewind(tmp);
+
+        let mut out = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = libc::fread(buf.as_mut_ptr() as *mut c_void, 1, buf.len(), tmp);
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        libc::fclose(tmp);
+
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+}
+
+/// The result of reading a line with `readline()`.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{Outcome, RawOutcome};
+///
+/// assert_eq!(Outcome::from(RawOutcome::Line(b"ls".to_vec())), Outcome::Line(String::from("ls")));
+/// assert_eq!(Outcome::from(RawOutcome::Line(vec![b'f', 0xff])),
+///            Outcome::InvalidUtf8(vec![b'f', 0xff]));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// A complete line, without the trailing newline.
+    Line(String),
+    /// `EOF` was read on an empty line.
+    Eof,
+    /// The line was cancelled by `SIGINT`. See `signals::catch_interrupt`.
+    Interrupted,
+    /// A complete line that is not valid UTF-8, with its bytes unchanged.
+    InvalidUtf8(Vec<u8>),
+}
+
+impl From<RawOutcome> for Outcome {
+    fn from(raw: RawOutcome) -> Outcome {
+        match raw {
+            RawOutcome::Line(bytes) => {
+                match String::from_utf8(bytes) {
+                    Ok(line) => Outcome::Line(line),
+                    Err(e) => Outcome::InvalidUtf8(e.into_bytes()),
+                }
+            }
+            RawOutcome::Eof => Outcome::Eof,
+            RawOutcome::Interrupted => Outcome::Interrupted,
+        }
+    }
+}
+
+/// The result of reading a line with `readline_bytes()`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RawOutcome {
+    /// A complete line, without the trailing newline.
+    Line(Vec<u8>),
+    /// `EOF` was read on an empty line.
+    Eof,
+    /// The line was cancelled by `SIGINT`. See `signals::catch_interrupt`.
+    Interrupted,
+}
+
+impl RawOutcome {
+    /// Convert a `Line` into an `OsString`, for input such as file paths that need not be UTF-8.
+    /// Returns `None` for the other outcomes.
+    pub fn into_os_string(self) -> Option<OsString> {
+        match self {
+            RawOutcome::Line(bytes) => Some(OsString::from_vec(bytes)),
+            _ => None,
+        }
+    }
+}
+
 /// Wraps the libreadline readline function.  The argument is the prompt to use.
 ///
 /// If readline encounters an `EOF` while reading the line, and the line is empty at that point,
-/// then `Ok(None)` is returned. Otherwise, the line is ended just as if a newline has been typed.
+/// then `Outcome::Eof` is returned. Otherwise, the line is ended just as if a newline has been
+/// typed. A line that is not valid UTF-8 is returned untouched as `Outcome::InvalidUtf8`.
+///
+/// If `SIGINT` handling has been enabled with `signals::catch_interrupt`, a `Ctrl-C` discards the
+/// line and `Outcome::Interrupted` is returned.
 ///
 /// # Examples
 ///
 /// ```
-/// use rl_sys::readline;
+/// use rl_sys::readline::{self, Outcome};
 ///
 /// loop {
 ///     match readline::readline("$ ") {
-///         Ok(Some(s)) => println!("{}", s),
-///         Ok(None) => break,
+///         Ok(Outcome::Line(s)) => println!("{}", s),
+///         Ok(Outcome::InvalidUtf8(b)) => println!("{}", String::from_utf8_lossy(&b)),
+///         Ok(Outcome::Interrupted) => continue,
+///         Ok(Outcome::Eof) => break,
 ///         Err(e) => {
 ///             println!("{}", e);
 ///             break;
@@ -337,18 +410,44 @@ impl Default for UndoList {
 ///     }
 /// }
 /// ```
-pub fn readline(prompt: &str) -> Result<Option<String>, ::ReadlineError> {
+pub fn readline(prompt: &str) -> Result<Outcome, ::ReadlineError> {
+    readline_bytes(prompt).map(Outcome::from)
+}
+
+/// Like `readline()`, but the line is returned as raw bytes, so input that is not UTF-8 survives
+/// unchanged.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{self, RawOutcome, input};
+/// use std::ffi::OsString;
+/// use std::os::unix::ffi::OsStringExt;
+///
+/// let mut keys = b"caf\xe9\n".to_vec().into_iter();
+/// let _guard = input::set_getc(move || keys.next());
+///
+/// let line = readline::readline_bytes("").unwrap();
+/// assert_eq!(line, RawOutcome::Line(b"caf\xe9".to_vec()));
+/// assert_eq!(line.into_os_string(), Some(OsString::from_vec(b"caf\xe9".to_vec())));
+/// ```
+pub fn readline_bytes(prompt: &str) -> Result<RawOutcome, ::ReadlineError> {
     let csprompt = try!(CString::new(prompt));
 
+    INTERRUPTED.store(false, Ordering::SeqCst);
     let ret = unsafe { ext_readline::readline(csprompt.as_ptr()) };
-    if ret.is_null() {
+
+    if INTERRUPTED.swap(false, Ordering::SeqCst) {
+        unsafe { free(ret as *mut c_void) };
+        Ok(RawOutcome::Interrupted)
+    } else if ret.is_null() {
         // user pressed Ctrl-D
-        Ok(None)
+        Ok(RawOutcome::Eof)
     } else {
         unsafe {
-            let line = CStr::from_ptr(ret).to_string_lossy().into_owned();
+            let line = CStr::from_ptr(ret).to_bytes().to_vec();
             free(ret as *mut c_void);
-            Ok(Some(line))
+            Ok(RawOutcome::Line(line))
         }
     }
 }
@@ -361,6 +460,9 @@ pub fn readline(prompt: &str) -> Result<Option<String>, ::ReadlineError> {
 /// on various file descriptors. To accommodate this need, readline can also be invoked as a
 /// 'callback' function from an event loop. There are functions available to make this easy.
 ///
+/// `readline::callback::CallbackReader` wraps these functions, delivering each line to a Rust
+/// closure or channel and removing the handler when dropped.
+///
 /// Set up the terminal for readline I/O and display the initial expanded value of prompt `p`. Save
 /// the value of `lhandler` to use as a handler function to call when a complete line of input has
 /// been entered. The handler function receives the text of the line as an argument.
@@ -17,6 +17,9 @@
 
 //! [2.4.7 Modifying Text]
 //! [2.4.7 modifying text]: https://goo.gl/DLJ9Gn
+//!
+//! The `start` and `end` offsets taken by these functions are byte offsets into `rl_line_buffer`.
+//! To work with character indices instead, see `readline::linebuf`.
 use std::ffi::{CStr, CString};
 
 mod ext_modtext {
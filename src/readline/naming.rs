@@ -28,15 +28,16 @@
 //!
 //! This binds the keystroke `Meta-Rubout` to the function descriptively named `backward-kill-word`.
 //! You, as the programmer, should bind the functions you write to descriptive names as well.
-use readline::CommandFunction;
-use std::ffi::CString;
+//!
+//! To name a Rust closure rather than an `extern "C" fn`, see `readline::command`.
+use readline::{CommandFunction, funmap_cname};
 
 mod ext_naming {
     use libc::{c_char, c_int};
     use readline::CommandFunction;
 
     extern "C" {
-        pub fn rl_add_defun(name: *const c_char, f: *mut CommandFunction, key: c_int) -> c_int;
+        pub fn rl_add_defun(name: *const c_char, f: CommandFunction, key: c_int) -> c_int;
     }
 }
 
@@ -65,8 +66,6 @@ mod ext_naming {
 /// # }
 /// ```
 pub fn add_func(name: &str, key: char, f: CommandFunction) -> Result<i32, ::ReadlineError> {
-    unsafe {
-        let csname = try!(CString::new(name));
-        Ok(ext_naming::rl_add_defun(csname.as_ptr(), f as *mut CommandFunction, key as i32))
-    }
+    let csname = try!(funmap_cname(name));
+    Ok(unsafe { ext_naming::rl_add_defun(csname, f, key as i32) })
 }
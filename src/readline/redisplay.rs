@@ -140,12 +140,12 @@ pub fn on_new_line() -> Result<i32, ::ReadlineError> {
 /// unsafe {
 ///     vars::rl_line_buffer = buffer;
 ///     print!("blah: ");
-///     vars::rl_already_prompted = 1;
+///     vars::set_already_prompted(true);
 ///     let _ = readline::readline("blah: ");
 ///     redisplay::redisplay();
 ///     print!("blah: ");
 ///     assert!(redisplay::on_new_line_with_prompt().is_ok());
-///     vars::rl_already_prompted = 0;
+///     vars::set_already_prompted(false);
 ///     let _ = CString::from_raw(buffer);
 /// }
 /// ```
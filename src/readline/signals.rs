@@ -0,0 +1,494 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! [2.5 Readline Signal Handling]
+//! [2.5 readline signal handling]: https://cnswww.cns.cwru.edu/php/chet/readline/readline.html
+//!
+//! Signals are asynchronous events sent to a process by the Unix kernel, sometimes on behalf of
+//! another process. They are intended to indicate exceptional events, like a user pressing the
+//! interrupt key on their terminal, or a network connection being broken. There is a class of
+//! signals that can be sent to the process currently reading input from the keyboard. Since
+//! Readline changes the terminal attributes when it is called, it needs to perform special
+//! processing when such a signal is received in order to restore the terminal to a sane state, or
+//! provide application writers with functions to do so manually.
+//!
+//! Readline contains an internal signal handler that is installed for a number of signals
+//! (`SIGINT`, `SIGQUIT`, `SIGTERM`, `SIGHUP`, `SIGALRM`, `SIGTSTP`, `SIGTTIN`, and `SIGTTOU`).
+//! When one of these signals is received, the signal handler will reset the terminal attributes to
+//! those that were in effect before `readline()` was called, reset the signal handling to what it
+//! was before `readline()` was called, and resend the signal to the calling application. If and
+//! when the calling application's signal handler returns, Readline will reinitialize the terminal
+//! and continue to accept input. When a `SIGINT` is received, the Readline signal handler performs
+//! some additional work, which will cause any partially-entered line to be aborted.
+//!
+//! `catch_interrupt` changes the `SIGINT` behavior so that `Ctrl-C` cancels the current line and
+//! `readline()` returns `Outcome::Interrupted`, instead of the process being killed.
+use libc::{self, EOF, c_int, c_void, sighandler_t};
+use readline::{GetcFunction, INTERRUPTED, IOFile};
+use readline::vars::{self, RL_STATE_READCMD};
+use std::cell::Cell;
+use std::io;
+use std::mem;
+use std::sync::atomic::Ordering;
+
+mod ext_signals {
+    use libc::c_int;
+    use readline::{GetcFunction, HookFunction, IOFile};
+
+    extern "C" {
+        pub static rl_signal_event_hook: Option<HookFunction>;
+        pub fn rl_getc(stream: *mut IOFile) -> c_int;
+        pub static mut rl_catch_signals: c_int;
+        pub static mut rl_catch_sigwinch: c_int;
+        pub static mut rl_getc_function: Option<GetcFunction>;
+        pub fn rl_pending_signal() -> c_int;
+        pub fn rl_cleanup_after_signal() -> ();
+        pub fn rl_free_line_state() -> ();
+        pub fn rl_reset_after_signal() -> ();
+        pub fn rl_echo_signal_char(sig: c_int) -> ();
+        pub fn rl_resize_terminal() -> ();
+        pub fn rl_set_screen_size(rows: c_int, cols: c_int) -> ();
+        pub fn rl_get_screen_size(rows: *mut c_int, cols: *mut c_int) -> ();
+        pub fn rl_reset_screen_size() -> ();
+        pub fn rl_set_signals() -> c_int;
+        pub fn rl_clear_signals() -> c_int;
+    }
+}
+
+/// If true, Readline will install signal handlers for `SIGINT`, `SIGQUIT`, `SIGTERM`, `SIGHUP`,
+/// `SIGALRM`, `SIGTSTP`, `SIGTTIN`, and `SIGTTOU`. The default is true.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::signals;
+///
+/// signals::set_catch_signals(false);
+/// assert!(!signals::get_catch_signals());
+/// signals::set_catch_signals(true);
+/// ```
+pub fn set_catch_signals(catch: bool) -> () {
+    unsafe { ext_signals::rl_catch_signals = catch as c_int }
+}
+
+/// Get the current value of the `rl_catch_signals` variable.
+pub fn get_catch_signals() -> bool {
+    unsafe { ext_signals::rl_catch_signals != 0 }
+}
+
+/// If true, Readline will install a signal handler for `SIGWINCH`, and update its idea of the
+/// screen size when the terminal is resized. The default is true. An application that handles
+/// `SIGWINCH` itself should call `resize_terminal` from its handler.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::signals;
+///
+/// signals::set_catch_sigwinch(false);
+/// assert!(!signals::get_catch_sigwinch());
+/// signals::set_catch_sigwinch(true);
+/// ```
+pub fn set_catch_sigwinch(catch: bool) -> () {
+    unsafe { ext_signals::rl_catch_sigwinch = catch as c_int }
+}
+
+/// Get the current value of the `rl_catch_sigwinch` variable.
+pub fn get_catch_sigwinch() -> bool {
+    unsafe { ext_signals::rl_catch_sigwinch != 0 }
+}
+
+/// Return the number of the signal Readline has caught but not yet handled, or 0 if there is none.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::signals;
+///
+/// assert_eq!(signals::pending_signal(), 0);
+/// ```
+pub fn pending_signal() -> i32 {
+    unsafe { ext_signals::rl_pending_signal() }
+}
+
+/// This function will reset the state of the terminal to what it was before `readline()` was
+/// called, and remove the Readline signal handlers for all signals, depending on the values of
+/// `rl_catch_signals` and `rl_catch_sigwinch`.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{signals, util};
+///
+/// util::init();
+///
+/// signals::cleanup_after_signal();
+/// signals::reset_after_signal();
+/// ```
+pub fn cleanup_after_signal() -> () {
+    unsafe { ext_signals::rl_cleanup_after_signal() }
+}
+
+/// This will free any partial state associated with the current input line (undo information, any
+/// partial history entry, any partially-entered keyboard macro, and any partially-entered numeric
+/// argument). This should be called before `cleanup_after_signal()`. The Readline signal handler
+/// for `SIGINT` calls this to abort the current input line.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{signals, util};
+///
+/// util::init();
+///
+/// signals::free_line_state();
+/// ```
+pub fn free_line_state() -> () {
+    unsafe { ext_signals::rl_free_line_state() }
+}
+
+/// This will reinitialize the terminal and reinstall any Readline signal handlers, depending on
+/// the values of `rl_catch_signals` and `rl_catch_sigwinch`.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{signals, util};
+///
+/// util::init();
+///
+/// signals::cleanup_after_signal();
+/// signals::reset_after_signal();
+/// ```
+pub fn reset_after_signal() -> () {
+    unsafe { ext_signals::rl_reset_after_signal() }
+}
+
+/// If an application wishes to install its own signal handlers, but still have readline display
+/// characters that generate signals, calling this function with `sig` set to `SIGINT`, `SIGQUIT`,
+/// or `SIGTSTP` will display the character generating that signal.
+///
+/// # Examples
+///
+/// ```
+/// extern crate libc;
+/// extern crate rl_sys;
+///
+/// use rl_sys::readline::{signals, util};
+///
+/// fn main() {
+///     util::init();
+///     signals::echo_signal_char(libc::SIGINT);
+/// }
+/// ```
+pub fn echo_signal_char(sig: i32) -> () {
+    unsafe { ext_signals::rl_echo_signal_char(sig) }
+}
+
+/// Update Readline's internal screen size by reading values from the kernel.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{signals, util};
+///
+/// util::init();
+///
+/// signals::resize_terminal();
+/// ```
+pub fn resize_terminal() -> () {
+    unsafe { ext_signals::rl_resize_terminal() }
+}
+
+/// Set Readline's idea of the terminal size to `rows` rows and `cols` columns. If either `rows` or
+/// `columns` is less than or equal to 0, Readline's idea of that terminal dimension is unchanged.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{signals, util};
+///
+/// util::init();
+///
+/// signals::set_screen_size(24, 80);
+/// assert_eq!(signals::get_screen_size().0, 24);
+/// ```
+pub fn set_screen_size(rows: i32, cols: i32) -> () {
+    unsafe { ext_signals::rl_set_screen_size(rows, cols) }
+}
+
+/// Return Readline's idea of the terminal's size as `(rows, columns)`.
+pub fn get_screen_size() -> (i32, i32) {
+    let mut rows = 0;
+    let mut cols = 0;
+    unsafe { ext_signals::rl_get_screen_size(&mut rows, &mut cols) };
+    (rows, cols)
+}
+
+/// Cause Readline to reobtain the screen size and recalculate its dimensions.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{signals, util};
+///
+/// util::init();
+///
+/// signals::reset_screen_size();
+/// ```
+pub fn reset_screen_size() -> () {
+    unsafe { ext_signals::rl_reset_screen_size() }
+}
+
+/// Install Readline's signal handler for `SIGINT`, `SIGQUIT`, `SIGTERM`, `SIGHUP`, `SIGALRM`,
+/// `SIGTSTP`, `SIGTTIN`, `SIGTTOU`, and `SIGWINCH`, depending on the values of `rl_catch_signals`
+/// and `rl_catch_sigwinch`.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::signals;
+///
+/// assert!(signals::set_signals().is_ok());
+/// assert!(signals::clear_signals().is_ok());
+/// ```
+pub fn set_signals() -> Result<i32, ::ReadlineError> {
+    let res = unsafe { ext_signals::rl_set_signals() };
+    if res == 0 {
+        Ok(res)
+    } else {
+        Err(::ReadlineError::new("Signals Error", "Unable to set signal handlers!"))
+    }
+}
+
+/// Remove all of the Readline signal handlers installed by `set_signals()`.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::signals;
+///
+/// assert!(signals::set_signals().is_ok());
+/// assert!(signals::clear_signals().is_ok());
+/// ```
+pub fn clear_signals() -> Result<i32, ::ReadlineError> {
+    let res = unsafe { ext_signals::rl_clear_signals() };
+    if res == 0 {
+        Ok(res)
+    } else {
+        Err(::ReadlineError::new("Signals Error", "Unable to clear signal handlers!"))
+    }
+}
+
+extern "C" fn interrupt_handler(_sig: c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// What `rl_getc` returns when a read fails, so that `readline()` gives up on the line.
+const READERR: c_int = -2;
+
+thread_local!(static PREVIOUS_GETC: Cell<Option<GetcFunction>> = Cell::new(None));
+
+fn read_error() -> c_int {
+    if vars::is_state(RL_STATE_READCMD) {
+        READERR
+    } else {
+        EOF
+    }
+}
+
+/// Read a byte from `stream` like `rl_getc`, except that a read interrupted by `SIGINT`, `SIGHUP`
+/// or `SIGTERM` returns instead of being retried.
+unsafe fn read_byte(stream: *mut IOFile) -> c_int {
+    let fd = if stream.is_null() {
+        libc::STDIN_FILENO
+    } else {
+        libc::fileno(stream)
+    };
+
+    loop {
+        let mut c = 0u8;
+        match libc::read(fd, &mut c as *mut u8 as *mut c_void, 1) {
+            1 => return c_int::from(c),
+            0 => return EOF,
+            _ => {
+                match io::Error::last_os_error().raw_os_error() {
+                    Some(libc::EINTR) => {
+                        match ext_signals::rl_pending_signal() {
+                            libc::SIGINT | libc::SIGHUP | libc::SIGTERM => return read_error(),
+                            _ if INTERRUPTED.load(Ordering::SeqCst) => return read_error(),
+                            _ => {}
+                        }
+                        if let Some(hook) = ext_signals::rl_signal_event_hook {
+                            hook();
+                        }
+                    }
+                    Some(e) if e == libc::EAGAIN || e == libc::EWOULDBLOCK => {
+                        // Like `rl_getc`, put the descriptor back into blocking mode.
+                        let flags = libc::fcntl(fd, libc::F_GETFL);
+                        if flags < 0 ||
+                           libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) < 0 {
+                            return read_error();
+                        }
+                    }
+                    _ => return read_error(),
+                }
+            }
+        }
+    }
+}
+
+/// The input function installed by `catch_interrupt`. This gets each byte from the input function
+/// that was installed before it, or reads it itself in place of `rl_getc`, and reports a read error
+/// once a `SIGINT` arrives so that `readline()` returns without accepting the line.
+///
+/// When readline caught the signal, it echoes it, frees the line state and passes it on to
+/// `interrupt_handler` as soon as this returns. Otherwise `rl_catch_signals` is off and that is
+/// done here.
+unsafe extern "C" fn interrupt_getc(stream: *mut IOFile) -> c_int {
+    if !INTERRUPTED.load(Ordering::SeqCst) {
+        let c = match PREVIOUS_GETC.with(|p| p.get()) {
+            Some(getc) => getc(stream),
+            None => read_byte(stream),
+        };
+
+        match ext_signals::rl_pending_signal() {
+            libc::SIGINT => {}
+            _ if INTERRUPTED.load(Ordering::SeqCst) => {
+                ext_signals::rl_echo_signal_char(libc::SIGINT);
+                ext_signals::rl_free_line_state();
+            }
+            _ => return c,
+        }
+    }
+    read_error()
+}
+
+/// Restores the previous `SIGINT` handling when dropped.
+#[must_use]
+pub struct InterruptGuard {
+    previous_action: libc::sigaction,
+    previous_getc: Option<GetcFunction>,
+    previous_chained: Option<GetcFunction>,
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        unsafe {
+            libc::sigaction(libc::SIGINT, &self.previous_action, ::std::ptr::null_mut());
+            ext_signals::rl_getc_function = self.previous_getc;
+        }
+        PREVIOUS_GETC.with(|p| p.set(self.previous_chained));
+    }
+}
+
+/// Make `Ctrl-C` (`SIGINT`) cancel the line being read instead of killing the process. While the
+/// returned guard is alive, a `SIGINT` during `readline()` discards the partially entered line and
+/// `readline()` returns `Outcome::Interrupted`.
+///
+/// This installs a `SIGINT` handler and an `rl_getc_function` that wraps the one already in place,
+/// so it should be called after installing any custom input source from `readline::input`. A
+/// custom source is only interrupted once it returns a byte. Readline's own signal handling is left
+/// as it is, so with `rl_catch_signals` set it still restores the terminal on other signals.
+///
+/// The input function and the chained one are stored per-thread, so this should be called on the
+/// thread that calls `readline()`.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{self, Outcome, signals};
+///
+/// let _guard = signals::catch_interrupt().unwrap();
+///
+/// loop {
+///     match readline::readline("$ ") {
+///         Ok(Outcome::Line(s)) => println!("{}", s),
+///         Ok(Outcome::Interrupted) => continue,
+///         Ok(_) => break,
+///         Err(e) => {
+///             println!("{}", e);
+///             break;
+///         }
+///     }
+/// }
+/// ```
+#[cfg_attr(feature = "clippy", allow(fn_to_numeric_cast))]
+pub fn catch_interrupt() -> Result<InterruptGuard, ::ReadlineError> {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = interrupt_handler as extern "C" fn(c_int) as sighandler_t;
+        // No SA_RESTART, so the signal interrupts a blocked read.
+        action.sa_flags = 0;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        let mut previous_action: libc::sigaction = mem::zeroed();
+        if libc::sigaction(libc::SIGINT, &action, &mut previous_action) != 0 {
+            return Err(::ReadlineError::from(io::Error::last_os_error()));
+        }
+
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        let previous_getc = ext_signals::rl_getc_function;
+        // `rl_getc` retries reads interrupted by a signal, so it is replaced rather than chained.
+        let chained = previous_getc.and_then(|getc| {
+            if getc as usize == ext_signals::rl_getc as GetcFunction as usize {
+                None
+            } else {
+                Some(getc)
+            }
+        });
+        let previous_chained = PREVIOUS_GETC.with(|p| p.replace(chained));
+        ext_signals::rl_getc_function = Some(interrupt_getc as GetcFunction);
+
+        Ok(InterruptGuard {
+            previous_action,
+            previous_getc,
+            previous_chained,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use libc::{self, EOF};
+    use readline::{INTERRUPTED, input, util};
+    use std::ptr;
+    use std::sync::atomic::Ordering;
+    use super::*;
+    use super::{ext_signals, interrupt_getc};
+
+    #[test]
+    fn test_catch_interrupt() {
+        util::init();
+        let catch = get_catch_signals();
+        let _input = input::set_getc(|| Some(b'a'));
+        let getc = unsafe { ext_signals::rl_getc_function };
+
+        {
+            let _guard = catch_interrupt().unwrap();
+            assert_eq!(get_catch_signals(), catch);
+            assert_eq!(unsafe { interrupt_getc(ptr::null_mut()) }, 'a' as i32);
+
+            unsafe { libc::raise(libc::SIGINT) };
+            assert!(INTERRUPTED.load(Ordering::SeqCst));
+            assert_eq!(unsafe { interrupt_getc(ptr::null_mut()) }, EOF);
+            INTERRUPTED.store(false, Ordering::SeqCst);
+        }
+
+        assert!(unsafe { ext_signals::rl_getc_function } == getc);
+    }
+}
@@ -0,0 +1,319 @@
+// Copyright 2015-2016 Jason Ozias
+//
+// This file is part of rl-sys.
+//
+// rl-sys is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rl-sys is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rl-sys.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Input and Output Streams
+//!
+//! Readline reads from the stdio stream `rl_instream` and writes to `rl_outstream`, which default
+//! to stdin and stdout. The functions in this module open a stream over a file descriptor with
+//! `fdopen` and install it in place of one of these, so readline can be pointed at a pty, a pipe
+//! or a socket. The returned `StreamGuard` restores the previous stream and closes its own when
+//! dropped.
+//!
+//! Readline picks up the streams each time `readline()` is called or a callback handler is
+//! installed, and keeps using that copy until the next line. Dropping a guard points the copy back
+//! at the previous stream along with the variable, but the terminal readline prepared stays as it
+//! is, so a guard must not be dropped while a line is being read: inside `readline()`, or while a
+//! callback handler is installed.
+//!
+//! Guards may be dropped in any order. Each guard remembers the stream it replaced, and if a guard
+//! is dropped while a later one is still installed, that later guard takes over restoring it.
+//!
+//! Pointing readline's working copy away from a closed stream relies on the private readline
+//! variables `_rl_in_stream` and `_rl_out_stream`, which are exported but not part of its API.
+use libc::{self, FILE, c_char};
+use readline::IOFile;
+use std::io;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::sync::Mutex;
+
+mod ext_stream {
+    use libc::FILE;
+
+    extern "C" {
+        pub static mut rl_instream: *mut FILE;
+        pub static mut rl_outstream: *mut FILE;
+        // Readline's working copies of the two, set up when it starts reading a line.
+        pub static mut _rl_in_stream: *mut FILE;
+        pub static mut _rl_out_stream: *mut FILE;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Input,
+    Output,
+}
+
+/// An installed guard: its kind, its stream, and the stream to restore when it is dropped. The
+/// pointers are kept as addresses so the stack can be shared between threads, as the readline
+/// variables are.
+struct Installed {
+    kind: Kind,
+    stream: usize,
+    previous: usize,
+}
+
+/// The guards that are installed, oldest first.
+static INSTALLED: Mutex<Vec<Installed>> = Mutex::new(Vec::new());
+
+fn get_var(kind: Kind) -> *mut FILE {
+    unsafe {
+        match kind {
+            Kind::Input => ext_stream::rl_instream,
+            Kind::Output => ext_stream::rl_outstream,
+        }
+    }
+}
+
+/// Point readline's working copy of the stream back from `stream` to `previous`, so it isn't left
+/// holding a stream that is about to be closed.
+fn release_copy(kind: Kind, stream: *mut FILE, previous: *mut FILE) {
+    unsafe {
+        match kind {
+            Kind::Input if ext_stream::_rl_in_stream == stream => {
+                ext_stream::_rl_in_stream = previous
+            }
+            Kind::Output if ext_stream::_rl_out_stream == stream => {
+                ext_stream::_rl_out_stream = previous
+            }
+            _ => {}
+        }
+    }
+}
+
+fn set_var(kind: Kind, stream: *mut FILE) {
+    unsafe {
+        match kind {
+            Kind::Input => ext_stream::rl_instream = stream,
+            Kind::Output => ext_stream::rl_outstream = stream,
+        }
+    }
+}
+
+/// Restores the stream that was installed before the guard's stream when dropped, and closes the
+/// guard's stream (and so its file descriptor).
+#[must_use]
+#[derive(Debug)]
+pub struct StreamGuard {
+    kind: Kind,
+    stream: *mut FILE,
+}
+
+impl StreamGuard {
+    /// Get the stream installed by this guard.
+    pub fn as_ptr(&self) -> *mut IOFile {
+        self.stream
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(i) = installed.iter().position(|g| g.stream == self.stream as usize) {
+            let previous = installed.remove(i).previous;
+
+            // If a later guard replaced this one, it restores our previous stream instead.
+            let kind = self.kind;
+            let stream = self.stream as usize;
+            match installed[i..].iter_mut().find(|g| g.kind == kind && g.previous == stream) {
+                Some(later) => later.previous = previous,
+                None if get_var(kind) == self.stream => set_var(kind, previous as *mut FILE),
+                None => {}
+            }
+        }
+
+        release_copy(self.kind, self.stream, get_var(self.kind));
+        unsafe { libc::fclose(self.stream) };
+    }
+}
+
+/// Open a stream over `fd`, which the stream takes ownership of, and install it.
+fn install(kind: Kind, fd: RawFd) -> Result<StreamGuard, ::ReadlineError> {
+    let mode = match kind {
+        Kind::Input => b"r\0",
+        Kind::Output => b"w\0",
+    };
+
+    let stream = unsafe { libc::fdopen(fd, mode.as_ptr() as *const c_char) };
+    if stream.is_null() {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(::ReadlineError::from(err));
+    }
+
+    let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+    installed.push(Installed {
+        kind,
+        stream: stream as usize,
+        previous: get_var(kind) as usize,
+    });
+    set_var(kind, stream);
+
+    Ok(StreamGuard { kind, stream })
+}
+
+/// Duplicate `fd`, so the caller keeps ownership of the original.
+fn dup(fd: RawFd) -> Result<RawFd, ::ReadlineError> {
+    let new_fd = unsafe { libc::dup(fd) };
+    if new_fd < 0 {
+        Err(::ReadlineError::from(io::Error::last_os_error()))
+    } else {
+        Ok(new_fd)
+    }
+}
+
+/// Set `rl_instream` to a stream reading from `file`, which is closed when the guard is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{self, Outcome, stream};
+/// use std::io::Write;
+/// use std::os::unix::net::UnixStream;
+///
+/// let (mut client, server) = UnixStream::pair().unwrap();
+/// client.write_all(b"hello\n").unwrap();
+///
+/// let _input = stream::set_instream(server.try_clone().unwrap()).unwrap();
+/// let _output = stream::set_outstream(server).unwrap();
+///
+/// assert_eq!(readline::readline("> ").unwrap(), Outcome::Line(String::from("hello")));
+/// ```
+pub fn set_instream<F>(file: F) -> Result<StreamGuard, ::ReadlineError>
+    where F: IntoRawFd
+{
+    install(Kind::Input, file.into_raw_fd())
+}
+
+/// Set `rl_outstream` to a stream writing to `file`, which is closed when the guard is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{misc, stream, util};
+/// use std::fs::{self, File};
+///
+/// util::init();
+///
+/// let path = std::env::temp_dir().join("rl-sys-outstream-doctest");
+/// {
+///     let _guard = stream::set_outstream(File::create(&path).unwrap()).unwrap();
+///     misc::variable_dumper(false);
+/// }
+///
+/// assert!(fs::read_to_string(&path).unwrap().contains("bell-style"));
+/// fs::remove_file(&path).unwrap();
+/// ```
+pub fn set_outstream<F>(file: F) -> Result<StreamGuard, ::ReadlineError>
+    where F: IntoRawFd
+{
+    install(Kind::Output, file.into_raw_fd())
+}
+
+/// Set `rl_instream` to a stream reading from a duplicate of `fd`. The caller keeps ownership of
+/// `fd`; only the duplicate is closed when the guard is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::stream;
+///
+/// let guard = stream::set_instream_fd(0).unwrap();
+/// assert!(!guard.as_ptr().is_null());
+/// ```
+pub fn set_instream_fd(fd: RawFd) -> Result<StreamGuard, ::ReadlineError> {
+    install(Kind::Input, try!(dup(fd)))
+}
+
+/// Set `rl_outstream` to a stream writing to a duplicate of `fd`. The caller keeps ownership of
+/// `fd`; only the duplicate is closed when the guard is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::stream;
+///
+/// let guard = stream::set_outstream_fd(1).unwrap();
+/// assert!(!guard.as_ptr().is_null());
+/// ```
+pub fn set_outstream_fd(fd: RawFd) -> Result<StreamGuard, ::ReadlineError> {
+    install(Kind::Output, try!(dup(fd)))
+}
+
+#[cfg(test)]
+mod test {
+    use readline::{misc, util};
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::io::AsRawFd;
+    use super::*;
+
+    #[test]
+    fn test_outstream_restored() {
+        util::init();
+        let before = get_var(Kind::Output);
+        let (mut client, server) = UnixStream::pair().unwrap();
+
+        {
+            let guard = set_outstream_fd(server.as_raw_fd()).unwrap();
+            assert_eq!(get_var(Kind::Output), guard.as_ptr());
+            misc::variable_dumper(false);
+        }
+        assert_eq!(get_var(Kind::Output), before);
+
+        // Dropping the guard closed the duplicate, but `server` is still open.
+        drop(server);
+        let mut out = String::new();
+        client.read_to_string(&mut out).unwrap();
+        assert!(out.contains("bell-style"));
+    }
+
+    #[test]
+    fn test_working_copy_released() {
+        util::init();
+        let before = get_var(Kind::Input);
+        let (_client, server) = UnixStream::pair().unwrap();
+
+        {
+            let guard = set_instream_fd(server.as_raw_fd()).unwrap();
+            // As readline does when it starts reading a line.
+            unsafe { ext_stream::_rl_in_stream = guard.as_ptr() };
+        }
+        assert_eq!(unsafe { ext_stream::_rl_in_stream }, before);
+    }
+
+    #[test]
+    fn test_out_of_order_drop() {
+        util::init();
+        let before = get_var(Kind::Input);
+        let (_client, server) = UnixStream::pair().unwrap();
+
+        let first = set_instream_fd(server.as_raw_fd()).unwrap();
+        let second = set_instream_fd(server.as_raw_fd()).unwrap();
+        drop(first);
+        assert_eq!(get_var(Kind::Input), second.as_ptr());
+        drop(second);
+        assert_eq!(get_var(Kind::Input), before);
+    }
+
+    #[test]
+    fn test_bad_fd() {
+        assert!(set_instream_fd(-1).is_err());
+        assert!(set_outstream_fd(-1).is_err());
+    }
+}
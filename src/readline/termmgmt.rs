@@ -17,7 +17,7 @@
 
 //! [2.4.9 Terminal Management]
 //! [2.4.9 terminal management]: https://goo.gl/NKP48u
-use readline::Keymap;
+use readline::keymap::KeymapRef;
 use std::ffi::CString;
 use std::ptr;
 
@@ -72,16 +72,17 @@ pub fn deprep_terminal() -> () {
 ///
 /// ```
 /// use rl_sys::readline::{keymap, termmgmt, util};
-/// use std::ptr;
 ///
 /// util::init();
 ///
-/// let km = keymap::create_empty().unwrap_or(ptr::null_mut());
-/// termmgmt::tty_set_default_bindings(km);
-/// termmgmt::tty_unset_default_bindings(km);
+/// let km = keymap::create_empty().unwrap();
+/// termmgmt::tty_set_default_bindings(&km);
+/// termmgmt::tty_unset_default_bindings(&km);
 /// ```
-pub fn tty_set_default_bindings(kmap: Keymap) -> () {
-    unsafe { ext_termmgmt::rl_tty_set_default_bindings(kmap) }
+pub fn tty_set_default_bindings<'a, M>(kmap: M) -> ()
+    where M: Into<KeymapRef<'a>>
+{
+    unsafe { ext_termmgmt::rl_tty_set_default_bindings(kmap.into().as_ptr()) }
 }
 
 /// Reset the bindings manipulated by `rl_tty_set_default_bindings` so that the terminal editing
@@ -91,16 +92,17 @@ pub fn tty_set_default_bindings(kmap: Keymap) -> () {
 ///
 /// ```
 /// use rl_sys::readline::{keymap, termmgmt, util};
-/// use std::ptr;
 ///
 /// util::init();
 ///
-/// let km = keymap::create_empty().unwrap_or(ptr::null_mut());
-/// termmgmt::tty_set_default_bindings(km);
-/// termmgmt::tty_unset_default_bindings(km);
+/// let km = keymap::create_empty().unwrap();
+/// termmgmt::tty_set_default_bindings(&km);
+/// termmgmt::tty_unset_default_bindings(&km);
 /// ```
-pub fn tty_unset_default_bindings(kmap: Keymap) -> () {
-    unsafe { ext_termmgmt::rl_tty_unset_default_bindings(kmap) }
+pub fn tty_unset_default_bindings<'a, M>(kmap: M) -> ()
+    where M: Into<KeymapRef<'a>>
+{
+    unsafe { ext_termmgmt::rl_tty_unset_default_bindings(kmap.into().as_ptr()) }
 }
 
 /// Reinitialize Readline's idea of the terminal settings using `name` as the terminal type (e.g.,
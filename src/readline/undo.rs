@@ -191,7 +191,7 @@ pub fn do_undo() -> Result<i32, ::ReadlineError> {
         let res = ext_undo::rl_do_undo();
 
         if res == 0 {
-            Err(::ReadlineError::new("Undo Error", "There was nothing to undo!"))
+            Err(::ReadlineError::NothingToUndo)
         } else {
             Ok(res)
         }
@@ -17,10 +17,14 @@
 
 //! [2.3 Readline Variables]
 //! [2.3 readline variables]: https://goo.gl/E1D6om
-use libc::{c_char, c_int};
+use libc::{c_char, c_int, c_ulong};
 use readline::{CommandFunction, GetcFunction, HookFunction, IOFile, Keymap, PrepFunction,
                VoidFunction};
-use std::ffi::CStr;
+use readline::keymap::KeySeq;
+use std::cmp;
+use std::ffi::{CStr, CString};
+use std::slice;
+use std::sync::Mutex;
 
 bitflags!(
 /// Readline State Bitflags
@@ -89,18 +93,18 @@ extern "C" {
     /// [2.4.5 allowing undoing]: https://goo.gl/oYj9bq
     pub static mut rl_line_buffer: *mut c_char;
     /// The offset of the current cursor position in `rl_line_buffer` (the *point*).
-    pub static rl_point: c_int;
+    pub static mut rl_point: c_int;
     /// The number of characters present in `rl_line_buffer`. When `rl_point` is at the end of the
     /// line, `rl_point` and `rl_end` are equal.
-    pub static rl_end: c_int;
+    pub static mut rl_end: c_int;
     /// The mark (saved position) in the current line. If set, the mark and point define a region.
-    pub static rl_mark: c_int;
+    pub static mut rl_mark: c_int;
     /// Setting this to a non-zero value causes Readline to return the current line immediately.
-    pub static rl_done: c_int;
+    pub static mut rl_done: c_int;
     /// Setting this to a positive value before calling `readline()` causes Readline to return
     /// after accepting that many characters, rather than reading up to a character bound to
     /// `accept-line`.
-    pub static rl_num_chars_to_read: c_int;
+    pub static mut rl_num_chars_to_read: c_int;
     /// Setting this to a value makes it the next keystroke read. This is a way to stuff a single
     /// character into the input stream.
     pub static rl_pending_input: c_int;
@@ -111,7 +115,7 @@ extern "C" {
     /// Setting this to a non-zero value causes Readline to completely erase the current line,
     /// including any prompt, any time a newline is typed as the only character on an
     /// otherwise-empty line. The cursor is moved to the beginning of the newly-blank line.
-    pub static rl_erase_empty_line: c_int;
+    pub static mut rl_erase_empty_line: c_int;
     /// The prompt Readline uses. This is set from the argument to `readline()`, and should not be
     /// assigned to directly. The `rl_set_prompt()` function (see section [2.4.6 Redisplay]) may be
     /// used to modify the prompt string after calling `readline()`.
@@ -136,41 +140,43 @@ extern "C" {
     pub static rl_gnu_readline_p: c_int;
     /// The terminal type, used for initialization. If not set by the application, Readline sets
     /// this to the value of the TERM environment variable the first time it is called.
-    pub static rl_terminal_name: *const c_char;
+    pub static mut rl_terminal_name: *const c_char;
     /// This variable is set to a unique name by each application using Readline. The value allows
     /// conditional parsing of the inputrc file (see section
     /// [1.3.2 Conditional Init Constructs]).
     /// [1.3.2 conditional init constructs]: https://goo.gl/ENcVC5
-    pub static rl_readline_name: *const c_char;
-    /// The stdio stream from which Readline reads input. If NULL, Readline defaults to stdin.
+    pub static mut rl_readline_name: *const c_char;
+    /// The stdio stream from which Readline reads input. If NULL, Readline defaults to stdin. See
+    /// `readline::stream` to change it.
     pub static rl_instream: *mut IOFile;
     /// The stdio stream to which Readline performs output. If NULL, Readline defaults to stdout.
+    /// See `readline::stream` to change it.
     pub static rl_outstream: *mut IOFile;
     /// If non-zero, Readline gives values found in the LINES and COLUMNS environment variables
     /// greater precedence than values fetched from the kernel when computing the screen dimensions.
-    pub static rl_prefer_env_winsize: c_int;
+    pub static mut rl_prefer_env_winsize: c_int;
     /// The address of the last command function Readline executed. May be used to test whether or
     /// not a function is being executed twice in succession, for example.
     pub static rl_last_command_func: *mut CommandFunction;
     /// If non-zero, this is the address of a function to call just before `readline` prints the
-    /// first prompt.
-    pub static rl_startup_hook: *mut HookFunction;
+    /// first prompt. See `readline::hooks` to install a closure.
+    pub static rl_startup_hook: Option<HookFunction>;
     /// If non-zero, this is the address of a function to call after the first prompt has been
     /// printed and just before `readline` starts reading input characters.
-    pub static rl_pre_input_hook: *mut HookFunction;
+    pub static rl_pre_input_hook: Option<HookFunction>;
     /// If non-zero, this is the address of a function to call periodically when Readline is waiting
     /// for terminal input. By default, this will be called at most ten times a second if there is
     /// no keyboard input.
-    pub static rl_event_hook: *mut HookFunction;
+    pub static rl_event_hook: Option<HookFunction>;
     /// If non-zero, Readline will call indirectly through this pointer to get a character from the
     /// input stream. By default, it is set to `rl_getc`, the default Readline character input
     /// function (see section [2.4.8 Character Input]). In general, an application that sets
     /// `rl_getc_function` should consider setting `rl_input_available_hook` as well.
     /// [2.4.8 character input]: https://goo.gl/olQQLc
-    pub static rl_getc_function: *mut GetcFunction;
+    pub static rl_getc_function: Option<GetcFunction>;
     /// If non-zero, this is the address of a function to call if a read system call is interrupted
     /// when Readline is reading terminal input.
-    pub static rl_signal_event_hook: *mut HookFunction;
+    pub static rl_signal_event_hook: Option<HookFunction>;
     /// If non-zero, Readline will use this function's return value when it needs to determine
     /// whether or not there is available input on the current input source. The default hook checks
     /// `rl_instream`; if an application is using a different input source, it should set the hook
@@ -186,7 +192,7 @@ extern "C" {
     /// not using the callback interface. In general, an application that sets `rl_getc_function`
     /// should consider setting `rl_input_available_hook` as well.
     /// [2.4.12 alternate interface]: https://goo.gl/PHb4Kq
-    pub static rl_input_available_hook: *mut HookFunction;
+    pub static rl_input_available_hook: Option<HookFunction>;
     /// If non-zero, Readline will call indirectly through this pointer to update the display with
     /// the current contents of the editing buffer. By default, it is set to `rl_redisplay`, the
     /// default Readline redisplay function (see section [2.4.6 Redisplay]).
@@ -220,7 +226,7 @@ extern "C" {
     /// The number of characters in `rl_executing_keyseq`.
     pub static rl_key_sequence_length: c_int;
     /// A variable with bit values that encapsulate the current Readline state.
-    static rl_readline_state: c_int;
+    static mut rl_readline_state: c_ulong;
     /// Set to a non-zero value if an explicit numeric argument was specified by the user. Only
     /// valid in a bindable command function.
     pub static rl_explicit_arg: c_int;
@@ -233,8 +239,27 @@ extern "C" {
 }
 
 /// Get the Readline state flags.
+#[cfg_attr(feature = "clippy", allow(cast_possible_truncation))]
 pub fn get_state() -> Option<ReadlineState> {
-    unsafe { ReadlineState::from_bits(rl_readline_state) }
+    unsafe { ReadlineState::from_bits(rl_readline_state as i32) }
+}
+
+/// Returns true if all of the flags in `state` are set, as `RL_ISSTATE` does.
+pub fn is_state(state: ReadlineState) -> bool {
+    let bits = state.bits() as c_ulong;
+    unsafe { rl_readline_state & bits == bits }
+}
+
+/// Set the flags in `state`, as `RL_SETSTATE` does. This is meant for custom input and command
+/// functions that need to report what they are doing, e.g. `RL_STATE_MOREINPUT` while reading the
+/// rest of a multi-key sequence.
+pub fn set_state(state: ReadlineState) -> () {
+    unsafe { rl_readline_state |= state.bits() as c_ulong }
+}
+
+/// Clear the flags in `state`, as `RL_UNSETSTATE` does.
+pub fn unset_state(state: ReadlineState) -> () {
+    unsafe { rl_readline_state &= !(state.bits() as c_ulong) }
 }
 
 /// The version number of this revision of the library.
@@ -242,6 +267,408 @@ pub fn get_library_version() -> String {
     unsafe { CStr::from_ptr(rl_library_version).to_string_lossy().into_owned() }
 }
 
+/// The strings most recently handed to readline by `set_readline_name` and `set_terminal_name`.
+/// Readline keeps the pointer, so each is only freed once readline has been pointed at its
+/// replacement.
+static READLINE_NAME: Mutex<Option<CString>> = Mutex::new(None);
+static TERMINAL_NAME: Mutex<Option<CString>> = Mutex::new(None);
+
+fn replace_string<F>(owned: &Mutex<Option<CString>>,
+                     val: &str,
+                     set: F)
+                     -> Result<(), ::ReadlineError>
+    where F: FnOnce(*const c_char)
+{
+    let new = try!(CString::new(val));
+    let mut owned = owned.lock().unwrap_or_else(|e| e.into_inner());
+    set(new.as_ptr());
+    *owned = Some(new);
+    Ok(())
+}
+
+fn opt_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(ptr).to_string_lossy().into_owned() })
+    }
+}
+
+fn check_offset(offset: i32, max: i32, what: &str) -> Result<(), ::ReadlineError> {
+    if offset < 0 || offset > max {
+        Err(::ReadlineError::new("Range Error",
+                                 format!("{} {} is outside the line (0..={})!", what, offset, max)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Get the offset of the current cursor position in `rl_line_buffer` (the *point*). This is a
+/// byte offset; see `readline::linebuf` for character indices.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{modtext, util, vars};
+///
+/// util::init();
+///
+/// assert!(modtext::insert_text("test").is_ok());
+/// assert_eq!(vars::get_point(), 4);
+/// ```
+pub fn get_point() -> i32 {
+    unsafe { rl_point }
+}
+
+/// Set the point. Fails if `point` is not within the line (`0..=rl_end`).
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{modtext, util, vars};
+///
+/// util::init();
+///
+/// assert!(modtext::insert_text("test").is_ok());
+/// assert!(vars::set_point(2).is_ok());
+/// assert_eq!(vars::get_point(), 2);
+/// assert!(vars::set_point(5).is_err());
+/// ```
+pub fn set_point(point: i32) -> Result<(), ::ReadlineError> {
+    unsafe {
+        try!(check_offset(point, rl_end, "Point"));
+        rl_point = point;
+    }
+    Ok(())
+}
+
+/// Get the number of bytes present in `rl_line_buffer`. When the point is at the end of the line,
+/// the point and the end are equal.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{modtext, util, vars};
+///
+/// util::init();
+///
+/// assert!(modtext::insert_text("test").is_ok());
+/// assert_eq!(vars::get_end(), 4);
+/// ```
+pub fn get_end() -> i32 {
+    unsafe { rl_end }
+}
+
+/// Set the end of the line. Fails if `end` is past the text in `rl_line_buffer`. If the point or
+/// the mark are past the new end, they are moved back to it.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{modtext, util, vars};
+///
+/// util::init();
+///
+/// assert!(modtext::insert_text("test").is_ok());
+/// assert!(vars::set_end(2).is_ok());
+/// assert_eq!(vars::get_point(), 2);
+/// assert!(vars::set_end(5).is_err());
+/// ```
+#[cfg_attr(feature = "clippy", allow(cast_possible_truncation, cast_possible_wrap))]
+pub fn set_end(end: i32) -> Result<(), ::ReadlineError> {
+    unsafe {
+        let len = if rl_line_buffer.is_null() {
+            0
+        } else {
+            CStr::from_ptr(rl_line_buffer).to_bytes().len() as i32
+        };
+        try!(check_offset(end, cmp::max(len, rl_end), "End"));
+        rl_end = end;
+        rl_point = cmp::min(rl_point, end);
+        rl_mark = cmp::min(rl_mark, end);
+    }
+    Ok(())
+}
+
+/// Get the mark (saved position) in the current line. If set, the mark and point define a region.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{util, vars};
+///
+/// util::init();
+///
+/// assert!(vars::get_mark() >= 0);
+/// ```
+pub fn get_mark() -> i32 {
+    unsafe { rl_mark }
+}
+
+/// Set the mark. Fails if `mark` is not within the line (`0..=rl_end`).
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{modtext, util, vars};
+///
+/// util::init();
+///
+/// assert!(modtext::insert_text("test").is_ok());
+/// assert!(vars::set_mark(1).is_ok());
+/// assert_eq!(vars::get_mark(), 1);
+/// assert!(vars::set_mark(-1).is_err());
+/// ```
+pub fn set_mark(mark: i32) -> Result<(), ::ReadlineError> {
+    unsafe {
+        try!(check_offset(mark, rl_end, "Mark"));
+        rl_mark = mark;
+    }
+    Ok(())
+}
+
+/// Setting this to true causes Readline to return the current line immediately.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::vars;
+///
+/// vars::set_done(true);
+/// assert!(vars::get_done());
+/// vars::set_done(false);
+/// ```
+pub fn set_done(done: bool) {
+    unsafe { rl_done = done as c_int }
+}
+
+/// Get the current value of the `rl_done` variable.
+pub fn get_done() -> bool {
+    unsafe { rl_done != 0 }
+}
+
+/// Setting this to a positive value before calling `readline()` causes Readline to return after
+/// accepting that many characters, rather than reading up to a character bound to `accept-line`.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::vars;
+///
+/// vars::set_num_chars_to_read(1);
+/// assert_eq!(vars::get_num_chars_to_read(), 1);
+/// vars::set_num_chars_to_read(0);
+/// ```
+pub fn set_num_chars_to_read(n: i32) {
+    unsafe { rl_num_chars_to_read = n }
+}
+
+/// Get the current value of the `rl_num_chars_to_read` variable.
+pub fn get_num_chars_to_read() -> i32 {
+    unsafe { rl_num_chars_to_read }
+}
+
+/// Get the keystroke that will be read next, as set by `charin::execute_next`, or 0 if there is
+/// none.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{charin, vars};
+///
+/// assert!(charin::execute_next('t').is_ok());
+/// assert_eq!(vars::get_pending_input(), 't' as i32);
+/// assert!(charin::clear_pending_input().is_ok());
+/// ```
+pub fn get_pending_input() -> i32 {
+    unsafe { rl_pending_input }
+}
+
+/// Returns true if a function is being called from a key binding.
+pub fn is_dispatching() -> bool {
+    unsafe { rl_dispatching != 0 }
+}
+
+/// Setting this to true causes Readline to completely erase the current line, including any
+/// prompt, any time a newline is typed as the only character on an otherwise-empty line.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::vars;
+///
+/// vars::set_erase_empty_line(true);
+/// assert!(vars::get_erase_empty_line());
+/// ```
+pub fn set_erase_empty_line(erase: bool) {
+    unsafe { rl_erase_empty_line = erase as c_int }
+}
+
+/// Get the current value of the `rl_erase_empty_line` variable.
+pub fn get_erase_empty_line() -> bool {
+    unsafe { rl_erase_empty_line != 0 }
+}
+
+/// Get the prompt Readline uses, or `None` if `readline()` has not been called yet.
+pub fn get_prompt() -> Option<String> {
+    opt_string(unsafe { rl_prompt })
+}
+
+/// Get the string displayed as the prompt, which may differ from `get_prompt` while the prompt
+/// is used as a message area, e.g. during incremental search.
+pub fn get_display_prompt() -> Option<String> {
+    opt_string(unsafe { rl_display_prompt })
+}
+
+/// Set this to true after displaying the prompt yourself, so `readline()` doesn't print it again.
+/// Readline never sets it.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::vars;
+///
+/// vars::set_already_prompted(true);
+/// assert!(vars::get_already_prompted());
+/// vars::set_already_prompted(false);
+/// ```
+pub fn set_already_prompted(prompted: bool) {
+    unsafe { rl_already_prompted = prompted as c_int }
+}
+
+/// Get the current value of the `rl_already_prompted` variable.
+pub fn get_already_prompted() -> bool {
+    unsafe { rl_already_prompted != 0 }
+}
+
+/// Get the version of the library, encoded as 0xMMmm.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::vars;
+///
+/// assert!(vars::get_readline_version() >= 0x0603);
+/// ```
+pub fn get_readline_version() -> i32 {
+    unsafe { rl_readline_version }
+}
+
+/// Returns true if this is GNU readline rather than some emulation.
+pub fn is_gnu_readline() -> bool {
+    unsafe { rl_gnu_readline_p == 1 }
+}
+
+/// Set the terminal type used for initialization. This takes effect the next time the terminal is
+/// initialized, e.g. by `termmgmt::reset_terminal`.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::vars;
+///
+/// assert!(vars::set_terminal_name("vt100").is_ok());
+/// assert_eq!(vars::get_terminal_name(), Some(String::from("vt100")));
+/// ```
+pub fn set_terminal_name(name: &str) -> Result<(), ::ReadlineError> {
+    replace_string(&TERMINAL_NAME, name, |ptr| unsafe { rl_terminal_name = ptr })
+}
+
+/// Get the terminal type, or `None` if it hasn't been set yet.
+pub fn get_terminal_name() -> Option<String> {
+    opt_string(unsafe { rl_terminal_name })
+}
+
+/// Set the name used for conditional parsing of the inputrc file (`$if name`).
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::vars;
+///
+/// assert!(vars::set_readline_name("myapp").is_ok());
+/// assert_eq!(vars::get_readline_name(), Some(String::from("myapp")));
+/// ```
+pub fn set_readline_name(name: &str) -> Result<(), ::ReadlineError> {
+    replace_string(&READLINE_NAME, name, |ptr| unsafe { rl_readline_name = ptr })
+}
+
+/// Get the name used for conditional parsing of the inputrc file.
+pub fn get_readline_name() -> Option<String> {
+    opt_string(unsafe { rl_readline_name })
+}
+
+/// If true, Readline gives values found in the LINES and COLUMNS environment variables greater
+/// precedence than values fetched from the kernel when computing the screen dimensions.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::vars;
+///
+/// vars::set_prefer_env_winsize(true);
+/// assert!(vars::get_prefer_env_winsize());
+/// ```
+pub fn set_prefer_env_winsize(prefer: bool) {
+    unsafe { rl_prefer_env_winsize = prefer as c_int }
+}
+
+/// Get the current value of the `rl_prefer_env_winsize` variable.
+pub fn get_prefer_env_winsize() -> bool {
+    unsafe { rl_prefer_env_winsize != 0 }
+}
+
+/// Get the text of the currently executing macro, if any.
+pub fn get_executing_macro() -> Option<String> {
+    opt_string(unsafe { rl_executing_macro })
+}
+
+/// Get the key that caused the dispatch to the currently executing function.
+pub fn get_executing_key() -> i32 {
+    unsafe { rl_executing_key }
+}
+
+/// Get the full key sequence that caused the dispatch to the currently executing function, or
+/// `None` outside of a command function.
+#[cfg_attr(feature = "clippy", allow(cast_sign_loss))]
+pub fn get_executing_keyseq() -> Option<KeySeq> {
+    unsafe {
+        if rl_executing_keyseq.is_null() || rl_key_sequence_length <= 0 {
+            None
+        } else {
+            let len = rl_key_sequence_length as usize;
+            let bytes = slice::from_raw_parts(rl_executing_keyseq as *const u8, len);
+            Some(KeySeq(bytes.to_vec()))
+        }
+    }
+}
+
+/// Returns true if an explicit numeric argument was specified by the user. Only valid in a
+/// command function.
+pub fn get_explicit_arg() -> bool {
+    unsafe { rl_explicit_arg != 0 }
+}
+
+/// Get the numeric argument specified by the user. Only valid in a command function.
+pub fn get_numeric_arg() -> i32 {
+    unsafe { rl_numeric_arg }
+}
+
+/// Get the current editing mode. 1 means emacs mode, 0 means vi mode.
+///
+/// # Examples
+///
+/// ```
+/// use rl_sys::readline::{util, vars};
+///
+/// util::init();
+///
+/// assert_eq!(vars::get_editing_mode(), 1);
+/// ```
+pub fn get_editing_mode() -> i32 {
+    unsafe { rl_editing_mode }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -251,7 +678,35 @@ mod test {
     fn test_rl_readline_version() {
         util::init();
         assert!(get_library_version() == "6.3");
-        assert!(rl_readline_version == 0x0603);
+        assert!(get_readline_version() == 0x0603);
+    }
+
+    #[test]
+    fn test_point_bounds() {
+        util::init();
+        assert!(::readline::modtext::insert_text("bounds").is_ok());
+        assert!(set_point(0).is_ok());
+        assert!(set_mark(6).is_ok());
+        assert!(set_point(7).is_err());
+        assert!(set_mark(-1).is_err());
+
+        assert!(set_end(3).is_ok());
+        assert_eq!(get_end(), 3);
+        assert_eq!(get_point(), 0);
+        assert_eq!(get_mark(), 3);
+        assert!(set_end(6).is_ok());
+        assert!(set_end(7).is_err());
+    }
+
+    #[test]
+    fn test_set_state() {
+        util::init();
+        assert!(!is_state(RL_STATE_MOREINPUT));
+        set_state(RL_STATE_MOREINPUT);
+        assert!(is_state(RL_STATE_MOREINPUT | RL_STATE_INITIALIZED));
+        unset_state(RL_STATE_MOREINPUT);
+        assert!(!is_state(RL_STATE_MOREINPUT));
+        assert!(is_state(RL_STATE_INITIALIZED));
     }
 
     #[test]